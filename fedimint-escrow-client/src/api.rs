@@ -2,11 +2,23 @@ use fedimint_core::api::{FederationApiExt, IModuleFederationApi};
 use fedimint_core::module::ApiRequestErased;
 use fedimint_core::task::{MaybeSend, MaybeSync};
 use fedimint_core::{apply, async_trait_maybe_send};
-use fedimint_escrow_common::endpoints::{EscrowInfo, GET_MODULE_INFO};
+use fedimint_escrow_common::endpoints::{
+    DisputeEvidence, DisputeSummary, EscrowInfo, ListEscrowsRequest, ListEscrowsResponse,
+    ARBITER_INBOX, GET_DISPUTE_EVIDENCE, GET_MODULE_INFO, LIST_ESCROWS_BY_PUBKEY,
+};
+use secp256k1::PublicKey;
 
 #[apply(async_trait_maybe_send!)]
 pub trait EscrowFederationApi: IModuleFederationApi {
     async fn get_escrow_info(&self, escrow_id: String) -> anyhow::Result<EscrowInfo>;
+    async fn list_escrows_by_pubkey(
+        &self,
+        request: ListEscrowsRequest,
+    ) -> anyhow::Result<ListEscrowsResponse>;
+    async fn arbiter_inbox(&self, arbiter_pubkey: PublicKey)
+        -> anyhow::Result<Vec<DisputeSummary>>;
+    async fn get_dispute_evidence(&self, escrow_id: String)
+        -> anyhow::Result<Vec<DisputeEvidence>>;
 }
 
 #[apply(async_trait_maybe_send!)]
@@ -27,4 +39,58 @@ where
             Err(e) => Err(anyhow::anyhow!("Federation API error: {}", e)),
         }
     }
+
+    // gets a page of the escrows the public key takes part in from the federation
+    // api
+    async fn list_escrows_by_pubkey(
+        &self,
+        request: ListEscrowsRequest,
+    ) -> anyhow::Result<ListEscrowsResponse> {
+        let result = self
+            .request_current_consensus(
+                LIST_ESCROWS_BY_PUBKEY.to_string(),
+                ApiRequestErased::new(request),
+            )
+            .await;
+        match result {
+            Ok(response) => Ok(response),
+            Err(e) => Err(anyhow::anyhow!("Federation API error: {}", e)),
+        }
+    }
+
+    // gets the disputes waiting for the decision of the arbiter from the
+    // federation api
+    async fn arbiter_inbox(
+        &self,
+        arbiter_pubkey: PublicKey,
+    ) -> anyhow::Result<Vec<DisputeSummary>> {
+        let result = self
+            .request_current_consensus(
+                ARBITER_INBOX.to_string(),
+                ApiRequestErased::new(arbiter_pubkey),
+            )
+            .await;
+        match result {
+            Ok(disputes) => Ok(disputes),
+            Err(e) => Err(anyhow::anyhow!("Federation API error: {}", e)),
+        }
+    }
+
+    // gets the encrypted evidence attached to the dispute of the escrow from the
+    // federation api
+    async fn get_dispute_evidence(
+        &self,
+        escrow_id: String,
+    ) -> anyhow::Result<Vec<DisputeEvidence>> {
+        let result = self
+            .request_current_consensus(
+                GET_DISPUTE_EVIDENCE.to_string(),
+                ApiRequestErased::new(escrow_id),
+            )
+            .await;
+        match result {
+            Ok(evidence) => Ok(evidence),
+            Err(e) => Err(anyhow::anyhow!("Federation API error: {}", e)),
+        }
+    }
 }
@@ -0,0 +1,34 @@
+use fedimint_client::module::recovery::{DynModuleBackup, ModuleBackup};
+use fedimint_core::core::{IntoDynInstance, ModuleInstanceId};
+use fedimint_core::encoding::{Decodable, Encodable};
+use fedimint_escrow_common::EscrowRole;
+
+/// The backup of the escrows this client takes part in.
+///
+/// The federation never learns the secret codes, so they can only be restored
+/// from here. The client backup is encrypted with a key derived from the root
+/// secret before it is uploaded to the federation, thus the secret codes are
+/// only readable by whoever holds the seed.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Encodable, Decodable)]
+pub struct EscrowModuleBackup {
+    pub escrows: Vec<EscrowBackup>,
+}
+
+/// The backup of a single escrow
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Encodable, Decodable)]
+pub struct EscrowBackup {
+    pub escrow_id: String,
+    pub role: EscrowRole,
+    /// the secret codes of the milestones, only known by the buyer
+    pub secret_codes: Vec<String>,
+}
+
+impl ModuleBackup for EscrowModuleBackup {}
+
+impl IntoDynInstance for EscrowModuleBackup {
+    type DynType = DynModuleBackup;
+
+    fn into_dyn(self, instance_id: ModuleInstanceId) -> Self::DynType {
+        DynModuleBackup::from_typed(instance_id, self)
+    }
+}
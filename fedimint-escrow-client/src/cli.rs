@@ -1,46 +1,175 @@
 use std::{ffi, iter};
 
-use clap::Parser;
+use bitcoin::hashes::sha256;
+use bitcoin::Address;
+use clap::{Args, Parser};
+use fedimint_core::core::OperationId;
 use fedimint_core::Amount;
-use fedimint_escrow_common::endpoints::EscrowInfo;
-use fedimint_escrow_common::hash256;
+use fedimint_escrow_common::config::ESCROW_ID_LENGTH;
+use fedimint_escrow_common::endpoints::{EscrowInfo, ListEscrowsRequest, MAX_LIST_ESCROWS_LIMIT};
+use fedimint_escrow_common::{EscrowError, EscrowRole, FallbackOutcome};
+use futures::StreamExt;
+use lightning_invoice::Bolt11Invoice;
 use random_string::generate;
+use secp256k1::schnorr::Signature;
 use secp256k1::PublicKey;
 use serde::Serialize;
 use serde_json::json;
 
-use super::EscrowClientModule;
+use super::{
+    ClaimPayout, EscrowClientModule, EscrowOperation, EscrowOperationState, EscrowUpdate,
+    InvoiceEscrowOutcome, NewEscrow,
+};
 use crate::api::EscrowFederationApi;
+use crate::unix_time_now;
+
+/// The default time after which an unresolved escrow can be refunded to the
+/// buyer, 30 days
+const DEFAULT_EXPIRY_SECS: u64 = 30 * 24 * 60 * 60;
+/// The default time after a dispute within which the arbiters have to decide,
+/// 14 days
+const DEFAULT_ARBITRATION_TIMEOUT_SECS: u64 = 14 * 24 * 60 * 60;
+
+/// The terms of a new escrow given by the buyer
+#[derive(Args, Serialize)]
+struct CreateArgs {
+    seller_pubkey: PublicKey,
+    arbiter_pubkey: PublicKey,
+    cost: Amount,             // actual cost of product
+    max_arbiter_fee_bps: u16, // maximum arbiter fee in basis points
+    #[arg(long = "arbiter")]
+    arbiters: Vec<PublicKey>, // further arbiters of the panel deciding a dispute
+    #[arg(long)]
+    arbiter_threshold: Option<u32>, // arbiters that have to agree, a majority by default
+    #[arg(long, default_value_t = DEFAULT_EXPIRY_SECS)]
+    expiry_secs: u64, // seconds after which the buyer can reclaim an open escrow
+    #[arg(long = "milestone")]
+    milestones: Vec<Amount>, // amounts of the milestones, the whole cost if none is given
+    #[arg(long)]
+    appeal_arbiter: Option<PublicKey>, // reviews the decision of the arbiters when appealed
+    #[arg(long, default_value_t = DEFAULT_ARBITRATION_TIMEOUT_SECS)]
+    arbitration_timeout_secs: u64, /* seconds after a dispute within which the arbiters have to
+                               * decide */
+    #[arg(long, default_value = "refund-buyer")]
+    fallback: FallbackOutcome, /* outcome if the arbiters do not decide in time:
+                                * refund-buyer, pay-seller or split */
+    #[arg(long = "payment-hash")]
+    payment_hashes: Vec<sha256::Hash>, /* lock the milestones to these payment hashes, claimed
+                                        * with their preimages instead of secret codes */
+}
+
+/// Where the claimed ecash is paid out to, instead of the wallet
+#[derive(Args, Serialize)]
+struct PayoutArgs {
+    #[arg(long, conflicts_with = "to_address")]
+    to_invoice: Option<Bolt11Invoice>, // pays the claimed ecash out over Lightning
+    #[arg(long)]
+    to_address: Option<Address>, // pegs the claimed ecash out on-chain
+    #[arg(long, requires = "to_address")]
+    estimate_fees: bool, // only shows the peg-out fees, without claiming
+}
 
 #[derive(Parser, Serialize)]
 enum Command {
     Create {
-        seller_pubkey: PublicKey,
-        arbiter_pubkey: PublicKey,
-        cost: Amount,             // actual cost of product
-        max_arbiter_fee_bps: u16, // maximum arbiter fee in basis points
+        #[command(flatten)]
+        args: CreateArgs,
+    },
+    CreateFromInvoice {
+        #[command(flatten)]
+        args: CreateArgs,
+    },
+    AwaitInvoiceEscrow {
+        operation_id: OperationId,
+    },
+    Accept {
+        escrow_id: String,
     },
     Info {
         escrow_id: String,
     },
+    AwaitState {
+        escrow_id: String,
+        state: String, // the state as shown by info, e.g. DisputedBySeller
+    },
+    AwaitOperation {
+        operation_id: OperationId,
+    },
     Claim {
         escrow_id: String,
         secret_code: String,
+        #[command(flatten)]
+        payout: PayoutArgs,
+    },
+    Release {
+        escrow_id: String,
+    },
+    ClaimReleased {
+        escrow_id: String,
     },
     Dispute {
         escrow_id: String,
+        #[arg(long)]
+        reason: Option<String>, // encrypted for the arbiter and the counterparty
+    },
+    RespondToDispute {
+        escrow_id: String,
+        response: String, // encrypted for the arbiter and the disputer
+    },
+    Evidence {
+        escrow_id: String,
     },
     ArbiterDecision {
         escrow_id: String,
         decision: String,
         arbiter_fee_bps: u16, // arbiter fee in basis points out of predecided maximum arbiters fee
+        #[arg(long)]
+        buyer_share_bps: Option<u16>, // buyer share in basis points when the decision is split
+    },
+    ArbiterClaimFee {
+        escrow_id: String,
+    },
+    Appeal {
+        escrow_id: String,
+    },
+    AppealDecision {
+        escrow_id: String,
+        decision: String,
+        arbiter_fee_bps: u16, /* appeal arbiter fee in basis points, bounded by what is left of
+                               * the maximum arbiters fee */
+        #[arg(long)]
+        buyer_share_bps: Option<u16>, // buyer share in basis points when the decision is split
     },
     BuyerClaim {
         escrow_id: String,
+        #[command(flatten)]
+        payout: PayoutArgs,
     },
     SellerClaim {
         escrow_id: String,
+        #[command(flatten)]
+        payout: PayoutArgs,
+    },
+    Refund {
+        escrow_id: String,
+    },
+    CancelPropose {
+        escrow_id: String,
+    },
+    CancelSign {
+        escrow_id: String,
+        signature: Signature, // cancellation signature of the seller
     },
+    List {},
+    Discover {
+        #[arg(long)]
+        role: Option<EscrowRole>, // only the escrows we take part in with this role
+        #[arg(long, default_value_t = 0)]
+        offset: u64,
+        #[arg(long, default_value_t = MAX_LIST_ESCROWS_LIMIT)]
+        limit: u64,
+    },
+    ArbiterInbox {},
     PublicKey {},
 }
 
@@ -53,137 +182,475 @@ pub(crate) async fn handle_cli_command(
         Command::parse_from(iter::once(&ffi::OsString::from("escrow")).chain(args.iter()));
 
     let res = match command {
-        Command::Create {
-            seller_pubkey,
-            arbiter_pubkey,
-            cost,
-            max_arbiter_fee_bps,
-        } => {
-            // Create a random escrow id, which will only be known by the buyer, and will be
-            // shared to seller or arbiter by the buyer
-            let escrow_id: String = generate(
-                32,
-                "abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ0123456789",
-            );
-
-            // Generate a random secret code
-            let secret_code: String = generate(
-                32,
-                "abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ0123456789",
-            );
-
-            let secret_code_hash = hash256(secret_code.clone());
-
-            // finalize_and_submit txns to lock ecash by underfunding to create an escrow
-            escrow
-                .create_escrow(
-                    cost,
-                    seller_pubkey,
-                    arbiter_pubkey,
-                    escrow_id.clone(),
-                    secret_code_hash,
-                    max_arbiter_fee_bps,
-                )
+        Command::Create { args } => {
+            let new_escrow = args.into_new_escrow();
+
+            // finalize_and_submit txns to lock ecash by underfunding to create an escrow, a
+            // secret code is generated for every milestone
+            let (_operation_id, secret_codes) = escrow.create_escrow(new_escrow.clone()).await?;
+
+            Ok(created_escrow_json(&new_escrow, &secret_codes))
+        }
+        Command::CreateFromInvoice { args } => {
+            let new_escrow = args.into_new_escrow();
+
+            // the escrow is created once the invoice is paid, see await-invoice-escrow
+            let (operation_id, invoice) = escrow
+                .create_escrow_from_invoice(new_escrow.clone())
                 .await?;
 
-            // If transaction is accepted and state is opened in server, share escrow ID and
-            // CODE
             Ok(json!({
-                "secret-code": secret_code, // shared by buyer out of band to seller
-                "escrow-id": escrow_id, // even though unique transaction id will be assigned, escrow id will used to collectively get all data related to the escrow
-                "state": "escrow opened!"
+                "operation-id": operation_id,
+                "invoice": invoice.to_string(),
+                "escrow-id": new_escrow.escrow_id,
+                "state": "waiting for the invoice to be paid!"
+            }))
+        }
+        Command::AwaitInvoiceEscrow { operation_id } => {
+            // locks the ecash received through the invoice in the escrow
+            match escrow.await_invoice_escrow(operation_id).await? {
+                InvoiceEscrowOutcome::Funded {
+                    new_escrow,
+                    secret_codes,
+                } => Ok(created_escrow_json(&new_escrow, &secret_codes)),
+                // the ecash is received in a transaction of its own, thus it is kept in the
+                // wallet when the escrow cannot be created
+                InvoiceEscrowOutcome::NotFunded {
+                    escrow_id,
+                    paid,
+                    error,
+                } => Ok(json!({
+                    "escrow-id": escrow_id,
+                    "paid": paid,
+                    "error": error,
+                    "state": if paid {
+                        "escrow not created, the received ecash stays in the wallet!"
+                    } else {
+                        "escrow not created, the invoice was not paid!"
+                    }
+                })),
+            }
+        }
+        Command::Accept { escrow_id } => {
+            // the seller agrees to the terms of the escrow shown by the info command
+            escrow.accept_escrow(escrow_id.clone()).await?;
+
+            Ok(json!({
+                "escrow_id": escrow_id,
+                "status": "escrow accepted!"
             }))
         }
         Command::Info { escrow_id } => {
-            // get escrow info corresponding to the id from db using federation api
-            let escrow_value: EscrowInfo =
-                escrow.module_api.get_escrow_info(escrow_id.clone()).await?;
+            // get escrow info corresponding to the id from db using federation api, and
+            // remember it if we take part in the escrow
+            let escrow_value: EscrowInfo = escrow.refresh_escrow(&escrow_id).await?;
 
             Ok(json!({
                 "buyer_pubkey": escrow_value.buyer_pubkey,
                 "seller_pubkey": escrow_value.seller_pubkey,
-                "arbiter_pubkey": escrow_value.arbiter_pubkey,
-                "amount": escrow_value.amount, // this amount will be (ecash in the contract - arbiter fee)
+                "arbiter_pubkeys": escrow_value.arbiter_pubkeys,
+                "arbiter_threshold": escrow_value.arbiter_threshold,
+                "amount": escrow_value.amount, // this amount will be (ecash in the unreleased milestones - arbiter fee)
+                "milestones": escrow_value.milestones,
                 "state": escrow_value.state,
+                "created_at": escrow_value.created_at,
+                "deadline": escrow_value.deadline,
+                "arbiter_votes": escrow_value.arbiter_votes,
+                "arbiter_decision": escrow_value.arbiter_decision,
+                "decided_at": escrow_value.decided_at,
+                "appeal_arbiter": escrow_value.appeal_arbiter,
+                "appealed_by": escrow_value.appealed_by,
+                "appeal_decision": escrow_value.appeal_decision,
+                "arbitration_deadline": escrow_value.arbitration_deadline,
+                "appeal_decision_deadline": escrow_value.appeal_decision_deadline,
+                "fallback": escrow_value.fallback,
+                "fallback_applied": escrow_value.fallback_applied,
+                "hash_lock": escrow_value.hash_lock,
+                "released_unclaimed": escrow_value.released_unclaimed,
+                "buyer_withdrawn": escrow_value.buyer_withdrawn,
+                "seller_withdrawn": escrow_value.seller_withdrawn,
+            }))
+        }
+        Command::AwaitState { escrow_id, state } => {
+            // the watcher of the escrow replays the changes it has seen, then follows the
+            // escrow until it is resolved
+            let mut updates = escrow
+                .subscribe_escrow_updates(&escrow_id)
+                .await?
+                .into_stream();
+            while let Some(update) = updates.next().await {
+                let (EscrowUpdate::Changed(escrow_value) | EscrowUpdate::Resolved(escrow_value)) =
+                    update;
+                if serde_json::to_value(&escrow_value.state)? == state {
+                    return Ok(json!({
+                        "escrow_id": escrow_id,
+                        "state": escrow_value.state,
+                    }));
+                }
+            }
+
+            Err(anyhow::anyhow!(
+                "Escrow {} was resolved without reaching the state {}",
+                escrow_id,
+                state
+            ))
+        }
+        Command::AwaitOperation { operation_id } => {
+            // the transaction of the operation is looked up in the operation log, thus the
+            // operation can be from an earlier run of the client
+            let operation_meta = escrow.get_operation_meta(operation_id).await?;
+            let updates = if operation_meta.operation == EscrowOperation::Create {
+                escrow.subscribe_transactions_input(operation_id).await?
+            } else {
+                escrow.subscribe_transactions_output(operation_id).await?
+            };
+            let mut updates = updates.into_stream();
+            let mut state = EscrowOperationState::Created;
+            while let Some(update) = updates.next().await {
+                state = update;
+            }
+
+            Ok(json!({
+                "operation_id": operation_id,
+                "escrow_id": operation_meta.escrow_id,
+                "operation": operation_meta.operation,
+                "state": state,
             }))
         }
         Command::Claim {
             escrow_id,
             secret_code,
+            payout,
         } => {
-            // get escrow info corresponding to the id from db using federation api
+            // the payout is checked before claiming, so that the ecash is not claimed for
+            // a payout it cannot make
             let escrow_value: EscrowInfo =
                 escrow.module_api.get_escrow_info(escrow_id.clone()).await?;
+            let (_, milestone) = escrow_value
+                .pending_milestone(&secret_code)
+                .ok_or(EscrowError::MilestoneNotFound)?;
+            let estimate_fees = payout.estimate_fees;
+            let payout = payout.prepare(escrow, milestone.amount).await?;
+            if estimate_fees {
+                return Ok(fees_estimate(&payout, &escrow_id, milestone.amount));
+            }
 
             // arbiter fee is 0 in this case!
-            escrow
-                .claim_escrow(escrow_id.clone(), escrow_value.amount, secret_code)
+            // the secret code unlocks the milestone it was generated for
+            let operation_id = escrow
+                .claim_escrow(escrow_id.clone(), secret_code, payout.clone())
                 .await?;
+            let payout_result = await_payout(escrow, payout, operation_id).await?;
+
+            // the escrow is only resolved once all of its milestones are released
+            let escrow_value: EscrowInfo =
+                escrow.module_api.get_escrow_info(escrow_id.clone()).await?;
+            let status = if escrow_value.state.is_resolved() {
+                "resolved"
+            } else {
+                "milestone released"
+            };
+
+            let mut result = json!({
+                "escrow_id": escrow_id,
+                "status": status
+            });
+            extend_json(&mut result, payout_result);
+            Ok(result)
+        }
+        Command::Release { escrow_id } => {
+            // the satisfied buyer pays out the seller without sharing a secret code
+            escrow.release_escrow(escrow_id.clone()).await?;
+
+            Ok(json!({
+                "escrow_id": escrow_id,
+                "status": "released!"
+            }))
+            // the seller claims the released ecash with claim-released
+        }
+        Command::ClaimReleased { escrow_id } => {
+            // arbiter fee is 0 in this case as well!
+            let amount = escrow.claim_released(escrow_id.clone()).await?;
 
             Ok(json!({
                 "escrow_id": escrow_id,
+                "amount": amount,
                 "status": "resolved"
             }))
         }
-        Command::Dispute { escrow_id } => {
+        Command::Dispute { escrow_id, reason } => {
             // the arbiter will take a fee (decided off band)
-            escrow.initiate_dispute(escrow_id.clone()).await?;
+            escrow.initiate_dispute(escrow_id.clone(), reason).await?;
 
             Ok(json!({
                 "escrow_id": escrow_id,
                 "status": "disputed!"
             }))
-            // out of band notification to arbiter give escrow_id to get the
-            // contract detail
+            // the arbiter finds the dispute in their arbiter-inbox
+        }
+        Command::RespondToDispute {
+            escrow_id,
+            response,
+        } => {
+            // the counterparty of the disputer gives its side before the arbiter decides
+            escrow
+                .respond_to_dispute(escrow_id.clone(), response)
+                .await?;
+
+            Ok(json!({
+                "escrow_id": escrow_id,
+                "status": "responded to dispute!"
+            }))
+        }
+        Command::Evidence { escrow_id } => {
+            // the text is only shown if we are one of its recipients
+            let evidence = escrow.dispute_evidence(escrow_id.clone()).await?;
+
+            Ok(json!({
+                "escrow_id": escrow_id,
+                "evidence": evidence
+            }))
         }
         Command::ArbiterDecision {
             escrow_id,
             decision,
             arbiter_fee_bps,
+            buyer_share_bps,
         } => {
             // arbiter will decide the ecash should be given to buyer or seller and change
             // the state of escrow!
             // the arbiter will take a fee (decided off band)
-            // decision has 2 values, buyer or seller.
+            // decision has 3 values, buyer, seller or split (with the buyer share in bps).
+            // with a panel the dispute is decided once enough arbiters vote for the same
+            // decision and fee
+            let (_operation_id, decided) = escrow
+                .arbiter_decision(
+                    escrow_id.clone(),
+                    decision,
+                    arbiter_fee_bps,
+                    buyer_share_bps,
+                )
+                .await?;
+
+            let status = if decided {
+                "arbiter decision made!"
+            } else {
+                "arbiter vote recorded!"
+            };
+
+            Ok(json!({
+                "escrow_id": escrow_id,
+                "status": status
+            }))
+        }
+        Command::ArbiterClaimFee { escrow_id } => {
+            // the arbiters who voted for the decision before it was made claim their share
+            let fee_share = escrow.claim_arbiter_fee(escrow_id.clone()).await?;
+
+            Ok(json!({
+                "escrow_id": escrow_id,
+                "fee_share": fee_share,
+                "status": "arbiter fee claimed!"
+            }))
+        }
+        Command::Appeal { escrow_id } => {
+            // the party who lost the decision of the arbiters escalates it to the appeal
+            // arbiter, within the appeal window
+            escrow.appeal(escrow_id.clone()).await?;
+
+            Ok(json!({
+                "escrow_id": escrow_id,
+                "status": "appealed!"
+            }))
+            // the appeal arbiter finds the appeal in their arbiter-inbox
+        }
+        Command::AppealDecision {
+            escrow_id,
+            decision,
+            arbiter_fee_bps,
+            buyer_share_bps,
+        } => {
+            // the appeal arbiter decides the dispute again, superseding the decision of the
+            // arbiters
             escrow
-                .arbiter_decision(escrow_id.clone(), decision, arbiter_fee_bps)
+                .appeal_decision(
+                    escrow_id.clone(),
+                    decision,
+                    arbiter_fee_bps,
+                    buyer_share_bps,
+                )
                 .await?;
 
             Ok(json!({
                 "escrow_id": escrow_id,
-                "status": "arbiter decision made!"
+                "status": "appeal decision made!"
             }))
         }
-        Command::BuyerClaim { escrow_id } => {
+        Command::BuyerClaim { escrow_id, payout } => {
+            // get escrow info corresponding to the id from db using federation api
+            let escrow_value: EscrowInfo =
+                escrow.module_api.get_escrow_info(escrow_id.clone()).await?;
+
+            // the amount to be claimed by buyer is the buyer share of (contract amount -
+            // arbiter fee)
+            let (buyer_claimable, _) = escrow_value.claimable_shares(unix_time_now());
+            let estimate_fees = payout.estimate_fees;
+            let payout = payout.prepare(escrow, buyer_claimable).await?;
+            if estimate_fees {
+                return Ok(fees_estimate(&payout, &escrow_id, buyer_claimable));
+            }
+            let operation_id = escrow
+                .buyer_claim(escrow_id.clone(), buyer_claimable, payout.clone())
+                .await?;
+            let payout_result = await_payout(escrow, payout, operation_id).await?;
+
+            let mut result = json!({
+                "escrow_id": escrow_id,
+                "status": "resolved!"
+            });
+            extend_json(&mut result, payout_result);
+            Ok(result)
+        }
+        Command::SellerClaim { escrow_id, payout } => {
+            // get escrow info corresponding to the id from db using federation api
+            let escrow_value: EscrowInfo =
+                escrow.module_api.get_escrow_info(escrow_id.clone()).await?;
+
+            // the amount to be claimed by seller is the seller share of (contract amount -
+            // arbiter fee)
+            let (_, seller_claimable) = escrow_value.claimable_shares(unix_time_now());
+            let estimate_fees = payout.estimate_fees;
+            let payout = payout.prepare(escrow, seller_claimable).await?;
+            if estimate_fees {
+                return Ok(fees_estimate(&payout, &escrow_id, seller_claimable));
+            }
+            let operation_id = escrow
+                .seller_claim(escrow_id.clone(), seller_claimable, payout.clone())
+                .await?;
+            let payout_result = await_payout(escrow, payout, operation_id).await?;
+
+            let mut result = json!({
+                "escrow_id": escrow_id,
+                "status": "resolved!"
+            });
+            extend_json(&mut result, payout_result);
+            Ok(result)
+        }
+        Command::Refund { escrow_id } => {
             // get escrow info corresponding to the id from db using federation api
             let escrow_value: EscrowInfo =
                 escrow.module_api.get_escrow_info(escrow_id.clone()).await?;
 
-            // the amount to be claimed by buyer is the contract amount - arbiter fee
+            // the whole contract amount is refunded to the buyer
             escrow
-                .buyer_claim(escrow_id.clone(), escrow_value.amount)
+                .refund_escrow(escrow_id.clone(), escrow_value.amount)
                 .await?;
 
             Ok(json!({
                 "escrow_id": escrow_id,
-                "status": "resolved!"
+                "status": "refunded!"
             }))
         }
-        Command::SellerClaim { escrow_id } => {
+        Command::CancelPropose { escrow_id } => {
             // get escrow info corresponding to the id from db using federation api
             let escrow_value: EscrowInfo =
                 escrow.module_api.get_escrow_info(escrow_id.clone()).await?;
 
-            // the amount to be claimed by seller is the contract amount - arbiter fee
+            // the signature is shared out of band with the counterparty, the buyer
+            // completes the cancellation as the ecash is refunded to the buyer
+            let signature = escrow.sign_cancellation(&escrow_id, escrow_value.amount);
+
+            Ok(json!({
+                "escrow_id": escrow_id,
+                "signature": signature.to_string(),
+            }))
+        }
+        Command::CancelSign {
+            escrow_id,
+            signature,
+        } => {
+            // get escrow info corresponding to the id from db using federation api
+            let escrow_value: EscrowInfo =
+                escrow.module_api.get_escrow_info(escrow_id.clone()).await?;
+
+            // the whole contract amount is refunded to the buyer
             escrow
-                .seller_claim(escrow_id.clone(), escrow_value.amount)
+                .cancel_escrow(escrow_id.clone(), escrow_value.amount, signature)
                 .await?;
 
             Ok(json!({
                 "escrow_id": escrow_id,
-                "status": "resolved!"
+                "status": "cancelled!"
+            }))
+        }
+        Command::List {} => {
+            // the escrows stored in the client database with their last known state
+            let escrows: Vec<serde_json::Value> = escrow
+                .list_escrows()
+                .await
+                .into_iter()
+                .map(|(escrow_id, escrow_value)| {
+                    json!({
+                        "escrow_id": escrow_id,
+                        "role": escrow_value.role,
+                        "buyer_pubkey": escrow_value.buyer_pubkey,
+                        "seller_pubkey": escrow_value.seller_pubkey,
+                        "arbiter_pubkeys": escrow_value.arbiter_pubkeys,
+                        "amount": escrow_value.amount,
+                        "secret_codes": escrow_value.secret_codes,
+                        "state": escrow_value.state,
+                    })
+                })
+                .collect();
+
+            Ok(json!({ "escrows": escrows }))
+        }
+        Command::Discover {
+            role,
+            offset,
+            limit,
+        } => {
+            // the escrows the federation knows we take part in, including the ones we
+            // have not been told about out of band yet
+            let response = escrow
+                .module_api
+                .list_escrows_by_pubkey(ListEscrowsRequest {
+                    pubkey: escrow.key.public_key(),
+                    role,
+                    offset,
+                    limit,
+                })
+                .await?;
+
+            Ok(json!({
+                "escrows": response.escrows,
+                "next_offset": response.next_offset,
             }))
         }
+        Command::ArbiterInbox {} => {
+            // the disputes waiting for our decision, the oldest one first
+            let now = unix_time_now();
+            let disputes: Vec<serde_json::Value> = escrow
+                .module_api
+                .arbiter_inbox(escrow.key.public_key())
+                .await?
+                .into_iter()
+                .map(|dispute| {
+                    json!({
+                        "escrow_id": dispute.escrow_id,
+                        "disputer": dispute.disputer,
+                        "disputed_at": dispute.disputed_at,
+                        "dispute_age_secs": now.saturating_sub(dispute.disputed_at),
+                        "amount": dispute.amount,
+                        "max_arbiter_fee": dispute.max_arbiter_fee,
+                        "appeal": dispute.appeal,
+                        "arbitration_deadline": dispute.arbitration_deadline,
+                    })
+                })
+                .collect();
+
+            Ok(json!({ "disputes": disputes }))
+        }
         Command::PublicKey {} => Ok(json!({
             "public_key": escrow.key.public_key().to_string()
         })),
@@ -191,3 +658,147 @@ pub(crate) async fn handle_cli_command(
 
     res
 }
+
+impl CreateArgs {
+    /// The terms of the escrow with a new random escrow id
+    fn into_new_escrow(self) -> NewEscrow {
+        // Create a random escrow id, which will only be known by the buyer, and will be
+        // shared to seller or arbiter by the buyer
+        let escrow_id: String = generate(
+            ESCROW_ID_LENGTH as usize,
+            "abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ0123456789",
+        );
+
+        // the whole cost is a single milestone unless the buyer pays in stages
+        let milestone_amounts = if self.milestones.is_empty() {
+            vec![self.cost]
+        } else {
+            self.milestones
+        };
+
+        // a single arbiter unless a panel is named, which decides by majority
+        let arbiter_pubkeys: Vec<PublicKey> = iter::once(self.arbiter_pubkey)
+            .chain(self.arbiters)
+            .collect();
+        let arbiter_threshold = self
+            .arbiter_threshold
+            .unwrap_or(arbiter_pubkeys.len() as u32 / 2 + 1);
+
+        NewEscrow {
+            amount: self.cost,
+            seller_pubkey: self.seller_pubkey,
+            arbiter_pubkeys,
+            arbiter_threshold,
+            escrow_id,
+            milestone_amounts,
+            max_arbiter_fee_bps: self.max_arbiter_fee_bps,
+            // the buyer can reclaim the ecash if the escrow is still open after this
+            deadline: unix_time_now() + self.expiry_secs,
+            appeal_arbiter: self.appeal_arbiter,
+            arbitration_timeout_secs: self.arbitration_timeout_secs,
+            fallback: self.fallback,
+            payment_hashes: self.payment_hashes,
+        }
+    }
+}
+
+impl PayoutArgs {
+    /// Checks that the claimed ecash can be paid out, estimating the fees of a
+    /// peg-out
+    async fn prepare(
+        self,
+        escrow: &EscrowClientModule,
+        claimed: Amount,
+    ) -> anyhow::Result<ClaimPayout> {
+        if let Some(invoice) = self.to_invoice {
+            return escrow.invoice_payout(invoice, claimed).await;
+        }
+        if let Some(address) = self.to_address {
+            return escrow.peg_out_payout(address, claimed).await;
+        }
+        Ok(ClaimPayout::Wallet)
+    }
+}
+
+/// The fees of the peg-out shown before the escrow is claimed
+fn fees_estimate(payout: &ClaimPayout, escrow_id: &str, claimed: Amount) -> serde_json::Value {
+    match payout {
+        ClaimPayout::PegOut { amount, fees, .. } => json!({
+            "escrow_id": escrow_id,
+            "claimable": claimed,
+            "peg_out_fees_sats": fees.amount().to_sat(),
+            "peg_out_amount_sats": amount.to_sat(),
+            "status": "peg-out fees estimated, run again without --estimate-fees to claim"
+        }),
+        ClaimPayout::Wallet | ClaimPayout::Invoice { .. } => json!({
+            "escrow_id": escrow_id,
+            "claimable": claimed,
+        }),
+    }
+}
+
+/// Waits for the ecash paid out in the transaction of the claim to reach its
+/// destination, returns the fields describing the payout
+async fn await_payout(
+    escrow: &EscrowClientModule,
+    payout: ClaimPayout,
+    claim_operation_id: OperationId,
+) -> anyhow::Result<serde_json::Value> {
+    match payout {
+        ClaimPayout::Wallet => Ok(json!({})),
+        ClaimPayout::Invoice { .. } => {
+            let preimage = escrow.await_invoice_payout(claim_operation_id).await?;
+            Ok(json!({ "payment_preimage": preimage }))
+        }
+        ClaimPayout::PegOut { amount, fees, .. } => {
+            let txid = escrow.await_peg_out(claim_operation_id).await?;
+            Ok(json!({
+                "peg_out_txid": txid.to_string(),
+                "peg_out_fees_sats": fees.amount().to_sat(),
+                "peg_out_amount_sats": amount.to_sat(),
+            }))
+        }
+    }
+}
+
+/// Adds the fields of the object to the result
+fn extend_json(result: &mut serde_json::Value, fields: serde_json::Value) {
+    if let (Some(result), serde_json::Value::Object(fields)) = (result.as_object_mut(), fields) {
+        result.extend(fields);
+    }
+}
+
+/// The escrow id and the secret codes of a created escrow, which the buyer
+/// shares with the seller
+fn created_escrow_json(new_escrow: &NewEscrow, secret_codes: &[String]) -> serde_json::Value {
+    // If transaction is accepted and state is opened in server, share escrow ID and
+    // CODE
+    let mut result = json!({
+        "escrow-id": new_escrow.escrow_id, // even though unique transaction id will be assigned, escrow id will used to collectively get all data related to the escrow
+        "deadline": new_escrow.deadline,
+        "state": "waiting for seller acceptance!"
+    });
+    if !new_escrow.payment_hashes.is_empty() {
+        // the seller claims the milestones with the preimages of the payment hashes,
+        // which the buyer never knew
+        result["payment-hashes"] = json!(new_escrow.payment_hashes);
+    } else if secret_codes.len() == 1 {
+        // shared by buyer out of band to seller
+        result["secret-code"] = json!(secret_codes[0]);
+    } else {
+        // the secret code of each milestone is shared by buyer out of band to seller
+        // once the milestone is delivered
+        result["milestones"] = new_escrow
+            .milestone_amounts
+            .iter()
+            .zip(secret_codes)
+            .map(|(amount, secret_code)| {
+                json!({
+                    "amount": amount,
+                    "secret-code": secret_code,
+                })
+            })
+            .collect();
+    }
+    result
+}
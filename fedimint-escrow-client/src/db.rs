@@ -0,0 +1,106 @@
+use fedimint_core::core::OperationId;
+use fedimint_core::encoding::{Decodable, Encodable};
+use fedimint_core::{impl_db_lookup, impl_db_record, Amount};
+use fedimint_escrow_common::endpoints::EscrowInfo;
+use fedimint_escrow_common::{EscrowOutput, EscrowRole, EscrowStates};
+use secp256k1::PublicKey;
+use serde::{Deserialize, Serialize};
+use strum_macros::EnumIter;
+
+use crate::NewEscrow;
+
+/// The prefix for the database keys
+#[repr(u8)]
+#[derive(Clone, Debug, EnumIter)]
+pub enum DbKeyPrefix {
+    Escrow = 0x04,
+    InvoiceEscrow = 0x05,
+}
+
+impl std::fmt::Display for DbKeyPrefix {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+/// The key of an escrow this client created or participates in
+#[derive(Debug, Clone, Encodable, Decodable, Eq, PartialEq, Hash)]
+pub struct EscrowKey {
+    pub escrow_id: String,
+}
+
+/// The prefix to lookup all the escrows of this client
+#[derive(Debug, Encodable, Decodable)]
+pub struct EscrowKeyPrefix;
+
+/// The escrow as known by this client
+#[derive(Debug, Clone, Eq, PartialEq, Encodable, Decodable, Serialize, Deserialize)]
+pub struct EscrowValue {
+    pub role: EscrowRole,
+    pub buyer_pubkey: PublicKey,
+    pub seller_pubkey: PublicKey,
+    pub arbiter_pubkeys: Vec<PublicKey>,
+    pub amount: Amount,
+    /// the secret codes of the milestones, only known by the buyer
+    pub secret_codes: Vec<String>,
+    /// the state of the escrow the last time this client looked it up
+    pub state: EscrowStates,
+}
+
+impl EscrowValue {
+    /// The escrow the buyer is about to create with the output, together with
+    /// the secret codes of its milestones
+    pub fn of_buyer(output: &EscrowOutput, secret_codes: Vec<String>) -> Self {
+        EscrowValue {
+            role: EscrowRole::Buyer,
+            buyer_pubkey: output.buyer_pubkey,
+            seller_pubkey: output.seller_pubkey,
+            arbiter_pubkeys: output.arbiter_pubkeys.clone(),
+            amount: output.amount,
+            secret_codes,
+            state: EscrowStates::PendingAcceptance,
+        }
+    }
+
+    /// The escrow as looked up from the federation, together with the secret
+    /// codes known by this client
+    pub fn new(role: EscrowRole, escrow_info: &EscrowInfo, secret_codes: Vec<String>) -> Self {
+        EscrowValue {
+            role,
+            buyer_pubkey: escrow_info.buyer_pubkey,
+            seller_pubkey: escrow_info.seller_pubkey,
+            arbiter_pubkeys: escrow_info.arbiter_pubkeys.clone(),
+            amount: escrow_info.amount,
+            secret_codes,
+            state: escrow_info.state.clone(),
+        }
+    }
+}
+
+impl_db_record!(
+    key = EscrowKey,
+    value = EscrowValue,
+    db_prefix = DbKeyPrefix::Escrow,
+);
+impl_db_lookup!(key = EscrowKey, query_prefix = EscrowKeyPrefix);
+
+/// The key of an escrow waiting for the Lightning invoice paying for it, by the
+/// operation of the invoice
+#[derive(Debug, Clone, Encodable, Decodable, Eq, PartialEq, Hash)]
+pub struct InvoiceEscrowKey {
+    pub operation_id: OperationId,
+}
+
+/// The prefix to lookup all the escrows waiting for their invoice
+#[derive(Debug, Encodable, Decodable)]
+pub struct InvoiceEscrowKeyPrefix;
+
+impl_db_record!(
+    key = InvoiceEscrowKey,
+    value = NewEscrow,
+    db_prefix = DbKeyPrefix::InvoiceEscrow,
+);
+impl_db_lookup!(
+    key = InvoiceEscrowKey,
+    query_prefix = InvoiceEscrowKeyPrefix
+);
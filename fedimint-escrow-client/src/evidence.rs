@@ -0,0 +1,51 @@
+use chacha20poly1305::aead::{Aead, KeyInit};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use fedimint_escrow_common::KIND;
+use rand::{thread_rng, Rng};
+use secp256k1::ecdh::SharedSecret;
+use secp256k1::{KeyPair, PublicKey};
+use sha2::{Digest, Sha256};
+
+/// The length of the random nonce prepended to the encrypted evidence
+const NONCE_LEN: usize = 12;
+
+/// Derives the key encrypting the evidence between two parties of an escrow.
+/// Both parties get the same key, from their own secret key and the public key
+/// of the other one.
+pub fn evidence_key(key: &KeyPair, other: &PublicKey, escrow_id: &str) -> [u8; 32] {
+    let shared_secret = SharedSecret::new(other, &key.secret_key());
+
+    let mut hasher = Sha256::new();
+    hasher.update(format!("fedimint/{KIND}/evidence").as_bytes());
+    hasher.update(shared_secret.secret_bytes());
+    hasher.update(escrow_id.as_bytes());
+    hasher.finalize().into()
+}
+
+/// Encrypts the evidence with the key, the nonce is prepended to the
+/// ciphertext
+pub fn encrypt_evidence(text: &str, key: &[u8; 32]) -> Vec<u8> {
+    let cipher = ChaCha20Poly1305::new(Key::from_slice(key));
+    let nonce: [u8; NONCE_LEN] = thread_rng().gen();
+
+    let mut ciphertext = nonce.to_vec();
+    ciphertext.extend(
+        cipher
+            .encrypt(Nonce::from_slice(&nonce), text.as_bytes())
+            .expect("encryption can't fail"),
+    );
+    ciphertext
+}
+
+/// Decrypts the evidence with the key, returns None if it was not encrypted
+/// with this key
+pub fn decrypt_evidence(ciphertext: &[u8], key: &[u8; 32]) -> Option<String> {
+    if ciphertext.len() < NONCE_LEN {
+        return None;
+    }
+    let (nonce, ciphertext) = ciphertext.split_at(NONCE_LEN);
+
+    let cipher = ChaCha20Poly1305::new(Key::from_slice(key));
+    let text = cipher.decrypt(Nonce::from_slice(nonce), ciphertext).ok()?;
+    String::from_utf8(text).ok()
+}
@@ -1,41 +1,80 @@
 pub mod api;
+pub mod backup;
 pub mod cli;
+pub mod db;
+pub mod evidence;
 pub mod states;
 
+use std::collections::{BTreeMap, BTreeSet};
 use std::sync::Arc;
+use std::time::UNIX_EPOCH;
 
-use anyhow::Context as _;
+use anyhow::{bail, Context as _};
 use async_stream::stream;
 use async_trait::async_trait;
-use fedimint_client::module::init::{ClientModuleInit, ClientModuleInitArgs};
-use fedimint_client::module::recovery::NoModuleBackup;
+use bitcoin::hashes::{sha256, Hash, HashEngine};
+use bitcoin::{Address, Network, Txid};
+use fedimint_client::module::init::{
+    ClientModuleInit, ClientModuleInitArgs, ClientModuleRecoverArgs,
+};
 use fedimint_client::module::{ClientContext, ClientModule};
 use fedimint_client::oplog::UpdateStreamOrOutcome;
+use fedimint_client::sm::{DynState, ModuleNotifier};
 use fedimint_client::transaction::{ClientInput, ClientOutput, TransactionBuilder};
+use fedimint_client::AddStateMachinesError;
 use fedimint_core::api::DynModuleApi;
-use fedimint_core::core::{KeyPair, OperationId};
-use fedimint_core::db::{Database, DatabaseTransaction, DatabaseVersion};
+use fedimint_core::core::{DynOutput, KeyPair, OperationId};
+use fedimint_core::db::{
+    Database, DatabaseTransaction, DatabaseVersion, IDatabaseTransactionOpsCoreTyped,
+};
+use fedimint_core::encoding::{Decodable, Encodable};
 use fedimint_core::module::{
     ApiVersion, ModuleCommon, ModuleInit, MultiApiVersion, TransactionItemAmount,
 };
-use fedimint_core::{apply, async_trait_maybe_send, Amount, OutPoint, TransactionId};
+use fedimint_core::task::{MaybeSend, MaybeSync};
+use fedimint_core::{
+    apply, async_trait_maybe_send, push_db_pair_items, Amount, OutPoint, TransactionId,
+};
 use fedimint_escrow_common::config::EscrowClientConfig;
-use fedimint_escrow_common::endpoints::EscrowInfo;
+use fedimint_escrow_common::endpoints::{EscrowInfo, ListEscrowsRequest, MAX_LIST_ESCROWS_LIMIT};
 use fedimint_escrow_common::{
-    ArbiterDecision, EscrowCommonInit, EscrowError, EscrowInput, EscrowInputArbiterDecision,
-    EscrowInputClaimingAfterDispute, EscrowInputClamingWithoutDispute, EscrowInputDisputing,
-    EscrowModuleTypes, EscrowOutput, EscrowStates, KIND,
+    bps_of, escrow_signing_message, hash256, is_valid_panel, verify_signature, ArbiterDecision,
+    ArbiterVote, Disputer, EncryptedEvidence, EscrowAction, EscrowCommonInit, EscrowError,
+    EscrowInput, EscrowInputAccepting, EscrowInputAppealDecision, EscrowInputAppealing,
+    EscrowInputArbiterDecision, EscrowInputCancel, EscrowInputClaimingAfterDispute,
+    EscrowInputClaimingAfterExpiry, EscrowInputClaimingArbiterFee, EscrowInputClaimingReleased,
+    EscrowInputClamingWithoutDispute, EscrowInputDisputing, EscrowInputReleasing,
+    EscrowInputRespondingToDispute, EscrowModuleTypes, EscrowOutput, EscrowRole, EscrowStates,
+    FallbackOutcome, HashLock, Milestone, KIND, MAX_BPS,
 };
+use fedimint_ln_client::api::LnFederationApi;
+use fedimint_ln_client::LightningClientModule;
+use fedimint_ln_common::config::FeeToAmount;
+use fedimint_ln_common::contracts::outgoing::{OutgoingContract, OutgoingContractAccount};
+use fedimint_ln_common::contracts::Contract;
+use fedimint_ln_common::{ContractOutput, LightningGateway, LightningOutput};
+use fedimint_wallet_client::{PegOutFees, WalletClientModule};
+use fedimint_wallet_common::WalletOutput;
 use futures::StreamExt;
+use lightning_invoice::{Bolt11Invoice, Bolt11InvoiceDescription, Description, RoutingFees};
 use rand::{thread_rng, Rng};
-use rust_decimal::prelude::ToPrimitive;
-use rust_decimal::Decimal;
-use secp256k1::{Message, PublicKey, Secp256k1};
+use random_string::generate;
+use secp256k1::schnorr::Signature;
+use secp256k1::{PublicKey, Secp256k1};
 use serde::{Deserialize, Serialize};
-use sha2::{Digest, Sha256};
+use strum::IntoEnumIterator;
 
 use crate::api::EscrowFederationApi;
-use crate::states::{EscrowClientContext, EscrowStateMachine};
+use crate::backup::{EscrowBackup, EscrowModuleBackup};
+use crate::db::{
+    DbKeyPrefix, EscrowKey, EscrowKeyPrefix, EscrowValue, InvoiceEscrowKey, InvoiceEscrowKeyPrefix,
+};
+use crate::evidence::{decrypt_evidence, encrypt_evidence, evidence_key};
+use crate::states::{
+    watcher_operation_id, EscrowClientContext, EscrowClientState, EscrowClientStateMachines,
+    EscrowStateMachine, InvoiceEscrowState, InvoiceEscrowStateMachine, InvoicePayoutState,
+    InvoicePayoutStateMachine, PegOutState, PegOutStateMachine, PAYOUT_TIMELOCK_BLOCKS,
+};
 
 /// The escrow client module
 #[derive(Debug)]
@@ -45,6 +84,7 @@ pub struct EscrowClientModule {
     client_ctx: ClientContext<Self>,
     module_api: DynModuleApi,
     db: Database,
+    notifier: ModuleNotifier<EscrowClientStateMachines>,
 }
 
 /// The high level state for tracking operations of transactions
@@ -58,20 +98,224 @@ pub enum EscrowOperationState {
     Rejected,
 }
 
+/// The actions of the client on an escrow, each one is an operation
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub enum EscrowOperation {
+    Create,
+    Accept,
+    Claim,
+    Dispute,
+    RespondToDispute,
+    ArbiterDecision,
+    ClaimArbiterFee,
+    Appeal,
+    AppealDecision,
+    ClaimAfterDispute,
+    Refund,
+    Cancel,
+    Release,
+    ClaimReleased,
+    /// takes back the ecash of a payout the gateway did not pay
+    RefundPayout,
+}
+
+/// The meta of an escrow operation as recorded in the operation log
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct EscrowOperationMeta {
+    pub escrow_id: String,
+    pub operation: EscrowOperation,
+    /// the role of this client in the escrow
+    pub role: EscrowRole,
+    /// the ecash locked or paid out by the operation
+    pub amount: Amount,
+    /// the other side of the trade, the arbiter acts for neither side
+    pub counterparty: Option<PublicKey>,
+    pub txid: TransactionId,
+    /// the change outputs of the transaction
+    pub change: Vec<OutPoint>,
+}
+
+/// The terms of an escrow the buyer creates
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Encodable, Decodable, Serialize, Deserialize)]
+pub struct NewEscrow {
+    pub amount: Amount,
+    pub seller_pubkey: PublicKey,
+    pub arbiter_pubkeys: Vec<PublicKey>,
+    /// the number of arbiters that have to agree on a decision
+    pub arbiter_threshold: u32,
+    pub escrow_id: String,
+    /// the amounts of the milestones, adding up to the amount
+    pub milestone_amounts: Vec<Amount>,
+    pub max_arbiter_fee_bps: u16,
+    /// the unix time after which the buyer can reclaim the escrow if it is
+    /// still open
+    pub deadline: u64,
+    pub appeal_arbiter: Option<PublicKey>,
+    pub arbitration_timeout_secs: u64,
+    pub fallback: FallbackOutcome,
+    /// the payment hashes locking the milestones in order, the seller claims
+    /// them with their preimages instead of generated secret codes
+    pub payment_hashes: Vec<sha256::Hash>,
+}
+
+impl NewEscrow {
+    /// Generates a random secret code for every milestone, none if the
+    /// milestones are locked to the payment hashes given by the buyer
+    pub fn generate_secret_codes(&self) -> Vec<String> {
+        if !self.payment_hashes.is_empty() {
+            return vec![];
+        }
+        self.milestone_amounts
+            .iter()
+            .map(|_| {
+                generate(
+                    32,
+                    "abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ0123456789",
+                )
+            })
+            .collect()
+    }
+
+    /// The output locking the ecash of the buyer in the escrow, each milestone
+    /// behind the hash of its secret code or its payment hash
+    pub fn to_output(&self, buyer_pubkey: PublicKey, secret_codes: &[String]) -> EscrowOutput {
+        let (hash_lock, secret_code_hashes): (HashLock, Vec<String>) =
+            if self.payment_hashes.is_empty() {
+                let secret_code_hashes = secret_codes
+                    .iter()
+                    .map(|secret_code| hash256(secret_code.clone()))
+                    .collect();
+                (HashLock::SecretCode, secret_code_hashes)
+            } else {
+                let payment_hashes = self
+                    .payment_hashes
+                    .iter()
+                    .map(|payment_hash| payment_hash.to_string())
+                    .collect();
+                (HashLock::Preimage, payment_hashes)
+            };
+        let milestones = self
+            .milestone_amounts
+            .iter()
+            .zip(secret_code_hashes)
+            .map(|(amount, secret_code_hash)| Milestone {
+                amount: *amount,
+                secret_code_hash,
+            })
+            .collect();
+
+        EscrowOutput {
+            amount: self.amount,
+            buyer_pubkey,
+            seller_pubkey: self.seller_pubkey,
+            arbiter_pubkeys: self.arbiter_pubkeys.clone(),
+            arbiter_threshold: self.arbiter_threshold,
+            escrow_id: self.escrow_id.clone(),
+            milestones,
+            // the maximum arbiter fee that can be charged
+            max_arbiter_fee: bps_of(self.amount, self.max_arbiter_fee_bps),
+            deadline: self.deadline,
+            appeal_arbiter: self.appeal_arbiter,
+            arbitration_timeout_secs: self.arbitration_timeout_secs,
+            fallback: self.fallback,
+            hash_lock,
+        }
+    }
+}
+
+/// Where the ecash claimed from an escrow goes, paid out in the transaction of
+/// the claim
+#[derive(Debug, Clone)]
+pub enum ClaimPayout {
+    /// the claimed ecash is issued to the wallet of this client
+    Wallet,
+    /// the claimed ecash funds the payment of the invoice through the gateway,
+    /// which takes its routing fee from it
+    Invoice {
+        invoice: Bolt11Invoice,
+        gateway: LightningGateway,
+    },
+    /// the claimed ecash is pegged out on-chain to the address, which receives
+    /// the amount left after the fees
+    PegOut {
+        address: Address,
+        amount: bitcoin::Amount,
+        fees: PegOutFees,
+    },
+}
+
+/// The evidence attached to a dispute, with its text if this client is one of
+/// its recipients
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct DisputeEvidenceText {
+    pub party: Disputer,
+    pub submitted_at: u64,
+    pub text: Option<String>,
+}
+
+/// The outcome of the funding of an escrow through a Lightning invoice
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub enum InvoiceEscrowOutcome {
+    /// The escrow was created, with the secret code of every milestone
+    Funded {
+        new_escrow: NewEscrow,
+        secret_codes: Vec<String>,
+    },
+    /// The escrow was not created, the ecash received through the invoice, if
+    /// it was paid, stays in the wallet
+    NotFunded {
+        escrow_id: String,
+        paid: bool,
+        error: String,
+    },
+}
+
+/// The updates of an escrow tracked by its watcher
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub enum EscrowUpdate {
+    /// The escrow changed, e.g. it was disputed or the arbiters voted on it
+    Changed(EscrowInfo),
+    /// The escrow is resolved and cannot change anymore
+    Resolved(EscrowInfo),
+}
+
 #[apply(async_trait_maybe_send!)]
 impl ClientModule for EscrowClientModule {
     type Init = EscrowClientInit;
     type Common = EscrowModuleTypes;
-    type Backup = NoModuleBackup;
+    type Backup = EscrowModuleBackup;
     type ModuleStateMachineContext = EscrowClientContext;
-    type States = EscrowStateMachine;
+    type States = EscrowClientStateMachines;
 
     fn context(&self) -> Self::ModuleStateMachineContext {
         EscrowClientContext {
             escrow_decoder: EscrowModuleTypes::decoder(),
+            cfg: self.cfg.clone(),
+            key: self.key.clone(),
+            client_ctx: self.client_ctx.clone(),
         }
     }
 
+    fn supports_backup(&self) -> bool {
+        true
+    }
+
+    /// backs up the escrows this client takes part in, with their secret codes
+    async fn backup(&self) -> anyhow::Result<EscrowModuleBackup> {
+        let escrows = self
+            .list_escrows()
+            .await
+            .into_iter()
+            .map(|(escrow_id, escrow_value)| EscrowBackup {
+                escrow_id,
+                role: escrow_value.role,
+                secret_codes: escrow_value.secret_codes,
+            })
+            .collect();
+
+        Ok(EscrowModuleBackup { escrows })
+    }
+
     /// conveys the monetary value of escrow input
     fn input_amount(
         &self,
@@ -90,7 +334,31 @@ impl ClientModule for EscrowClientModule {
                 amount: input.amount,
                 fee: Amount::ZERO,
             }),
-            EscrowInput::Disputing(_) => Some(TransactionItemAmount {
+            EscrowInput::ClaimingArbiterFee(input) => Some(TransactionItemAmount {
+                amount: input.amount,
+                fee: Amount::ZERO,
+            }),
+            EscrowInput::ClaimingAfterExpiry(input) => Some(TransactionItemAmount {
+                amount: input.amount,
+                fee: Amount::ZERO,
+            }),
+            EscrowInput::Cancel(input) => Some(TransactionItemAmount {
+                amount: input.amount,
+                fee: Amount::ZERO,
+            }),
+            EscrowInput::ClaimingReleased(input) => Some(TransactionItemAmount {
+                amount: input.amount,
+                fee: Amount::ZERO,
+            }),
+            EscrowInput::AppealDecision(input) => Some(TransactionItemAmount {
+                amount: input.amount,
+                fee: Amount::ZERO,
+            }),
+            EscrowInput::Disputing(_)
+            | EscrowInput::RespondingToDispute(_)
+            | EscrowInput::Appealing(_)
+            | EscrowInput::Accepting(_)
+            | EscrowInput::Releasing(_) => Some(TransactionItemAmount {
                 amount: Amount::ZERO,
                 fee: Amount::ZERO,
             }),
@@ -120,50 +388,44 @@ impl ClientModule for EscrowClientModule {
 }
 
 impl EscrowClientModule {
-    /// Handles the buyer transaction for the escrow creation
+    /// Handles the buyer transaction for the escrow creation, returns the
+    /// operation and the secret code of every milestone, none if the
+    /// milestones are locked to payment hashes. A dispute is decided
+    /// once `arbiter_threshold` of the arbiters agree, or ends with the
+    /// `fallback` outcome if they do not within `arbitration_timeout_secs`.
     pub async fn create_escrow(
         &self,
-        amount: Amount,
-        seller_pubkey: PublicKey,
-        arbiter_pubkey: PublicKey,
-        escrow_id: String,
-        secret_code_hash: String,
-        max_arbiter_fee_bps: u16,
-    ) -> anyhow::Result<()> {
+        new_escrow: NewEscrow,
+    ) -> anyhow::Result<(OperationId, Vec<String>)> {
         let operation_id = OperationId(thread_rng().gen());
 
-        // Validate max_arbiter_fee_bps (should be in range 10 to 1000)
-        if let Err(e) = self.cfg.limit_max_arbiter_fee_bps(max_arbiter_fee_bps) {
-            return Err(anyhow::anyhow!("Invalid max_arbiter_fee_bps: {}", e));
-        }
-
-        // converting bps to percentage
-        let fee_percentage = Decimal::from(max_arbiter_fee_bps) / Decimal::from(100);
-        // getting the maximum arbiter fee that can be charged
-        let max_arbiter_fee: Amount = Amount::from_sats(
-            (Decimal::from(amount.msats) * fee_percentage / Decimal::from(1000))
-                .to_u64()
-                .unwrap(),
-        );
-
-        // creating output for buyers transaction by underfunding
-        let output = EscrowOutput {
+        self.check_new_escrow(&new_escrow)?;
+        let secret_codes = new_escrow.generate_secret_codes();
+        let output = new_escrow.to_output(self.key.public_key(), &secret_codes);
+        let NewEscrow {
             amount,
-            buyer_pubkey: self.key.public_key(),
             seller_pubkey,
-            arbiter_pubkey,
             escrow_id,
-            secret_code_hash,
-            max_arbiter_fee,
+            ..
+        } = new_escrow;
+
+        // the secret codes are only known by the buyer, thus they are stored before the
+        // escrow is created so that they cannot get lost
+        let escrow_key = EscrowKey {
+            escrow_id: escrow_id.clone(),
         };
+        let mut dbtx = self.db.begin_transaction().await;
+        dbtx.insert_entry(
+            &escrow_key,
+            &EscrowValue::of_buyer(&output, secret_codes.clone()),
+        )
+        .await;
+        dbtx.commit_tx().await;
 
-        let operation_id_clone = operation_id.clone();
         let client_output = ClientOutput {
             output,
-            state_machines: Arc::new(move |_: TransactionId, _: u64| {
-                vec![EscrowStateMachine {
-                    operation_id: operation_id_clone,
-                }]
+            state_machines: Arc::new(|_: TransactionId, _: u64| {
+                Vec::<EscrowClientStateMachines>::new()
             }),
         };
 
@@ -172,17 +434,21 @@ impl EscrowClientModule {
         // output to cover the output amount and create the corresponding inputs itself
         let tx = TransactionBuilder::new()
             .with_output(self.client_ctx.make_client_output(client_output));
-        let outpoint = |txid, _| OutPoint { txid, out_idx: 0 };
-        let (txid, _change) = self
-            .client_ctx
-            .finalize_and_submit_transaction(operation_id, KIND.as_str(), outpoint, tx)
+        let operation_meta = operation_meta_fn(
+            escrow_id.clone(),
+            EscrowOperation::Create,
+            EscrowRole::Buyer,
+            amount,
+            Some(seller_pubkey),
+        );
+        self.client_ctx
+            .finalize_and_submit_transaction(operation_id, KIND.as_str(), operation_meta, tx)
             .await?;
 
         // Subscribe to transaction updates
         let mut updates = self
-            .subscribe_transactions_input(operation_id, txid)
-            .await
-            .unwrap()
+            .subscribe_transactions_input(operation_id)
+            .await?
             .into_stream();
 
         // Process the update stream
@@ -190,22 +456,128 @@ impl EscrowClientModule {
             match update {
                 EscrowOperationState::Created | EscrowOperationState::Accepted => {}
                 EscrowOperationState::Rejected => {
+                    // the escrow was never created
+                    let mut dbtx = self.db.begin_transaction().await;
+                    dbtx.remove_entry(&escrow_key).await;
+                    dbtx.commit_tx().await;
+
                     return Err(anyhow::anyhow!(EscrowError::TransactionRejected));
                 }
             }
         }
 
-        Ok(())
+        self.watch_escrow(&escrow_id).await;
+
+        Ok((operation_id, secret_codes))
+    }
+
+    /// Creates a Lightning invoice through the gateway of the federation paying
+    /// for the escrow and its deposit fee, so that a buyer without ecash can
+    /// fund it. Once the invoice is paid the client locks the received ecash
+    /// in the escrow, which [`Self::await_invoice_escrow`] waits for, returns
+    /// the operation of the invoice and the invoice.
+    ///
+    /// The ecash is received and locked in two transactions, see
+    /// [`InvoiceEscrowStateMachine`].
+    pub async fn create_escrow_from_invoice(
+        &self,
+        new_escrow: NewEscrow,
+    ) -> anyhow::Result<(OperationId, Bolt11Invoice)> {
+        // the terms are checked before the buyer pays, the ecash is only locked once
+        // the invoice is paid
+        self.check_new_escrow(&new_escrow)?;
+
+        let lightning = self.client_ctx.get_first_module::<LightningClientModule>();
+        let gateway = lightning.select_active_gateway_opt().await;
+        let description = Description::new(format!("escrow {}", new_escrow.escrow_id))?;
+        let (operation_id, invoice, _) = lightning
+            .create_bolt11_invoice(
+                new_escrow.amount + self.cfg.deposit_fee,
+                Bolt11InvoiceDescription::Direct(&description),
+                None,
+                new_escrow.escrow_id.clone(),
+                gateway,
+            )
+            .await?;
+
+        // the terms are kept until the escrow is created, the state machine funding the
+        // escrow follows the receipt of the invoice
+        let secret_codes = new_escrow.generate_secret_codes();
+        let funding = self
+            .client_ctx
+            .make_dyn_state(EscrowClientStateMachines::InvoiceFunding(
+                InvoiceEscrowStateMachine {
+                    operation_id,
+                    new_escrow: new_escrow.clone(),
+                    secret_codes,
+                    state: InvoiceEscrowState::AwaitingPayment,
+                },
+            ));
+        let mut dbtx = self.db.begin_transaction().await;
+        dbtx.insert_new_entry(&InvoiceEscrowKey { operation_id }, &new_escrow)
+            .await;
+        dbtx.commit_tx().await;
+        let mut dbtx = self.client_ctx.global_db().begin_transaction().await;
+        self.client_ctx
+            .add_state_machines(&mut dbtx.to_ref_nc(), vec![funding])
+            .await?;
+        dbtx.commit_tx().await;
+
+        Ok((operation_id, invoice))
+    }
+
+    /// Waits for the escrow of [`Self::create_escrow_from_invoice`] to be
+    /// funded with the ecash received through the invoice, returns the terms
+    /// and the secret code of every milestone. If the escrow cannot be created
+    /// the outcome tells whether the invoice was paid, the received ecash then
+    /// stays in the wallet.
+    pub async fn await_invoice_escrow(
+        &self,
+        operation_id: OperationId,
+    ) -> anyhow::Result<InvoiceEscrowOutcome> {
+        // the states of the funding are replayed, thus an escrow funded while nobody
+        // was waiting is returned right away
+        let mut state_machines = self.notifier.subscribe(operation_id).await;
+        while let Some(state_machine) = state_machines.next().await {
+            let EscrowClientStateMachines::InvoiceFunding(funding) = state_machine else {
+                continue;
+            };
+            match funding.state {
+                InvoiceEscrowState::AwaitingPayment | InvoiceEscrowState::Funding { .. } => {}
+                InvoiceEscrowState::Funded => {
+                    self.watch_escrow(&funding.new_escrow.escrow_id).await;
+                    return Ok(InvoiceEscrowOutcome::Funded {
+                        new_escrow: funding.new_escrow,
+                        secret_codes: funding.secret_codes,
+                    });
+                }
+                InvoiceEscrowState::Failed { error, paid } => {
+                    return Ok(InvoiceEscrowOutcome::NotFunded {
+                        escrow_id: funding.new_escrow.escrow_id,
+                        paid,
+                        error,
+                    });
+                }
+            }
+        }
+        Err(anyhow::anyhow!(EscrowError::InvoiceEscrowNotFound))
+    }
+
+    /// Checks the terms of a new escrow of this client against the limits of
+    /// the federation
+    fn check_new_escrow(&self, new_escrow: &NewEscrow) -> anyhow::Result<()> {
+        check_new_escrow(&self.cfg, &self.key.public_key(), new_escrow)
     }
 
-    /// Handles the seller transaction to claim the funds that are locked in the
-    /// escrow upon providing the secret code
+    /// Handles the seller transaction to claim the funds of the milestone that
+    /// are locked in the escrow upon providing its secret code, paying them
+    /// out in the same transaction
     pub async fn claim_escrow(
         &self,
         escrow_id: String,
-        amount: Amount,
         secret_code: String,
-    ) -> anyhow::Result<()> {
+        payout: ClaimPayout,
+    ) -> anyhow::Result<OperationId> {
         // make an api call to server db and get the secret code hash and state of
         // escrow, and then verify it
         let escrow_value: EscrowInfo = self.module_api.get_escrow_info(escrow_id.clone()).await?;
@@ -215,366 +587,803 @@ impl EscrowClientModule {
         {
             return Err(anyhow::anyhow!(EscrowError::EscrowDisputed));
         }
-        if escrow_value.state != EscrowStates::WaitingforSellerToClaim
-            && escrow_value.state != EscrowStates::Open
-        {
-            return Err(anyhow::anyhow!(EscrowError::ArbiterNotDecided));
+        // the secret code can only be used while the escrow is open
+        if escrow_value.state != EscrowStates::Open {
+            return Err(anyhow::anyhow!(EscrowError::EscrowNotOpen));
         }
+        // find the milestone that is unlocked by the secret code
+        let (milestone, amount) = match escrow_value.pending_milestone(&secret_code) {
+            Some((milestone, escrow_milestone)) => (milestone, escrow_milestone.amount),
+            None => return Err(anyhow::anyhow!(EscrowError::MilestoneNotFound)),
+        };
 
-        let secp = Secp256k1::new();
-        // Hash the secret code string
-        let mut hasher = Sha256::new();
-        hasher.update(secret_code.as_bytes());
-        let hashed_message: [u8; 32] = hasher.finalize().into();
-        // Create the message from the hash
-        let message = Message::from_slice(&hashed_message).expect("32 bytes");
-        // Sign the message using Schnorr signature
-        let signature = secp.sign_schnorr(&message, &self.key);
+        // Sign the message authorizing the action on this escrow
+        let signature = self.sign_action(
+            &escrow_id,
+            EscrowAction::ClaimWithoutDispute { milestone },
+            amount,
+        );
 
-        let operation_id = OperationId(thread_rng().gen());
         // Transfer ecash to seller by overfunding the transaction
         // Create input using the buyer account
         let input = EscrowInput::ClamingWithoutDispute(EscrowInputClamingWithoutDispute {
             amount,
-            escrow_id,
-            secret_code: secret_code,
-            hashed_message: hashed_message,
-            signature: signature,
+            escrow_id: escrow_id.clone(),
+            milestone,
+            secret_code,
+            signature,
         });
 
-        let operation_id_clone = operation_id.clone();
-        let client_input = ClientInput {
+        self.submit_escrow_claim(
+            &escrow_value,
+            &escrow_id,
+            EscrowOperation::Claim,
+            amount,
             input,
-            keys: vec![self.key.clone()],
-            state_machines: Arc::new(move |_: TransactionId, _: u64| {
-                vec![EscrowStateMachine {
-                    operation_id: operation_id_clone,
-                }]
-            }),
-        };
+            payout,
+        )
+        .await
+    }
 
-        // Build and send tx to the fed
-        // The transaction builder will create mint output to cover the input amount by
-        // itself
-        let tx =
-            TransactionBuilder::new().with_input(self.client_ctx.make_client_input(client_input));
-        let outpoint = |txid, _| OutPoint { txid, out_idx: 0 };
-        let (txid, change) = self
-            .client_ctx
-            .finalize_and_submit_transaction(operation_id, KIND.as_str(), outpoint, tx)
-            .await?;
+    /// Handles the seller accepting the terms of the escrow, which opens it.
+    /// The signature of the seller covers the terms of the escrow.
+    pub async fn accept_escrow(&self, escrow_id: String) -> anyhow::Result<OperationId> {
+        let escrow_value: EscrowInfo = self.module_api.get_escrow_info(escrow_id.clone()).await?;
+        // only the seller accepts an escrow waiting for it
+        if escrow_value.seller_pubkey != self.key.public_key() {
+            return Err(anyhow::anyhow!(EscrowError::NotSeller));
+        }
+        if escrow_value.state != EscrowStates::PendingAcceptance {
+            return Err(anyhow::anyhow!(EscrowError::EscrowNotPendingAcceptance));
+        }
 
-        // Subscribe to transaction updates
-        let mut updates = self
-            .subscribe_transactions_output(operation_id, txid, change.clone())
-            .await
-            .unwrap()
-            .into_stream();
+        // Sign the message authorizing the action on this escrow
+        let signature = self.sign_action(
+            &escrow_id,
+            EscrowAction::Accept(escrow_value.terms()),
+            Amount::ZERO,
+        );
 
-        // Process the update stream
-        while let Some(update) = updates.next().await {
-            match update {
-                EscrowOperationState::Created | EscrowOperationState::Accepted => {}
-                EscrowOperationState::Rejected => {
-                    return Err(anyhow::anyhow!(EscrowError::TransactionRejected));
-                }
-            }
-        }
+        let input = EscrowInput::Accepting(EscrowInputAccepting {
+            escrow_id: escrow_id.clone(),
+            seller: self.key.public_key(),
+            signature,
+        });
 
-        Ok(())
+        self.submit_escrow_action(
+            &escrow_value,
+            &escrow_id,
+            EscrowOperation::Accept,
+            Amount::ZERO,
+            input,
+        )
+        .await
     }
 
     /// Handles the claiming of ecash by the buyer after the arbiter has decided
-    /// that buyer won the dispute
-    pub async fn buyer_claim(&self, escrow_id: String, amount: Amount) -> anyhow::Result<()> {
-        let operation_id = OperationId(thread_rng().gen());
-
+    /// that buyer won the dispute or split the escrow, paying it out in the
+    /// same transaction
+    pub async fn buyer_claim(
+        &self,
+        escrow_id: String,
+        amount: Amount,
+        payout: ClaimPayout,
+    ) -> anyhow::Result<OperationId> {
         let escrow_value: EscrowInfo = self.module_api.get_escrow_info(escrow_id.clone()).await?;
+        // the fallback outcome applies once the arbiters missed the arbitration
+        // deadline
+        let now = unix_time_now();
+        let state = escrow_value.state_at(now);
         // the arbiter has not decided yet if the escrow is disputed!
-        if escrow_value.state == EscrowStates::DisputedByBuyer
-            || escrow_value.state == EscrowStates::DisputedBySeller
-        {
+        if state.is_disputed() {
             return Err(anyhow::anyhow!(EscrowError::ArbiterNotDecided));
         }
         // the state should be waiting for buyer to claim the ecash as arbiter has
         // decided
-        if escrow_value.state != EscrowStates::WaitingforBuyerToClaim {
+        if state != EscrowStates::WaitingforBuyerToClaim
+            && state != EscrowStates::WaitingforBothToClaim
+        {
             return Err(anyhow::anyhow!(EscrowError::ArbiterNotDecided));
         }
+        // the decision of the arbiters cannot be claimed while it can still be
+        // appealed
+        if let Some(appeal_deadline) = escrow_value.appeal_deadline() {
+            if escrow_value.appealed_by.is_none() && now < appeal_deadline {
+                return Err(anyhow::anyhow!(EscrowError::AppealWindowOpen(
+                    appeal_deadline
+                )));
+            }
+        }
 
-        let secp = Secp256k1::new();
-        // Hash the decision string
-        let mut hasher = Sha256::new();
-        hasher.update("buyer_claim".as_bytes());
-        let hashed_message: [u8; 32] = hasher.finalize().into();
-        // Create the message from the hash
-        let message = Message::from_slice(&hashed_message).expect("32 bytes");
-        // Sign the message using Schnorr signature
-        let signature = secp.sign_schnorr(&message, &self.key);
+        // Sign the message authorizing the action on this escrow
+        let signature = self.sign_action(&escrow_id, EscrowAction::ClaimAfterDispute, amount);
 
         // Transfer ecash back to buyer after deduction of arbiter fee by underfunding
         // the transaction
         let input = EscrowInput::ClaimingAfterDispute(EscrowInputClaimingAfterDispute {
             amount,
-            escrow_id,
-            hashed_message: hashed_message,
-            signature: signature,
+            escrow_id: escrow_id.clone(),
+            claimer: self.key.public_key(),
+            signature,
         });
 
-        let operation_id_clone = operation_id.clone();
-        let client_input = ClientInput {
+        self.submit_escrow_claim(
+            &escrow_value,
+            &escrow_id,
+            EscrowOperation::ClaimAfterDispute,
+            amount,
             input,
-            keys: vec![self.key.clone()],
-            state_machines: Arc::new(move |_: TransactionId, _: u64| {
-                vec![EscrowStateMachine {
-                    operation_id: operation_id_clone,
-                }]
-            }),
-        };
-
-        // Build and send tx to the fed
-        // The transaction builder will create mint output to cover the input amount by
-        // itself
-        let tx =
-            TransactionBuilder::new().with_input(self.client_ctx.make_client_input(client_input));
-        let outpoint = |txid, _| OutPoint { txid, out_idx: 0 };
-        let (txid, change) = self
-            .client_ctx
-            .finalize_and_submit_transaction(operation_id, KIND.as_str(), outpoint, tx)
-            .await?;
-
-        // Subscribe to transaction updates
-        let mut updates = self
-            .subscribe_transactions_output(operation_id, txid, change.clone())
-            .await
-            .unwrap()
-            .into_stream();
-
-        // Process the update stream
-        while let Some(update) = updates.next().await {
-            match update {
-                EscrowOperationState::Created | EscrowOperationState::Accepted => {}
-                EscrowOperationState::Rejected => {
-                    return Err(anyhow::anyhow!(EscrowError::TransactionRejected));
-                }
-            }
-        }
-
-        Ok(())
+            payout,
+        )
+        .await
     }
 
     /// Handles the claiming of transaction by the seller after the arbiter has
-    /// decided that seller won the dispute
-    pub async fn seller_claim(&self, escrow_id: String, amount: Amount) -> anyhow::Result<()> {
-        let operation_id = OperationId(thread_rng().gen());
-
+    /// decided that seller won the dispute or split the escrow, paying it out
+    /// in the same transaction
+    pub async fn seller_claim(
+        &self,
+        escrow_id: String,
+        amount: Amount,
+        payout: ClaimPayout,
+    ) -> anyhow::Result<OperationId> {
         let escrow_value: EscrowInfo = self.module_api.get_escrow_info(escrow_id.clone()).await?;
+        // the fallback outcome applies once the arbiters missed the arbitration
+        // deadline
+        let now = unix_time_now();
+        let state = escrow_value.state_at(now);
         // the arbiter has not decided yet if the escrow is disputed!
-        if escrow_value.state == EscrowStates::DisputedByBuyer
-            || escrow_value.state == EscrowStates::DisputedBySeller
-        {
+        if state.is_disputed() {
             return Err(anyhow::anyhow!(EscrowError::ArbiterNotDecided));
         }
         // the state should be waiting for seller to claim the ecash as arbiter has
         // decided
-        if escrow_value.state != EscrowStates::WaitingforSellerToClaim {
+        if state != EscrowStates::WaitingforSellerToClaim
+            && state != EscrowStates::WaitingforBothToClaim
+        {
             return Err(anyhow::anyhow!(EscrowError::ArbiterNotDecided));
         }
+        // the decision of the arbiters cannot be claimed while it can still be
+        // appealed
+        if let Some(appeal_deadline) = escrow_value.appeal_deadline() {
+            if escrow_value.appealed_by.is_none() && now < appeal_deadline {
+                return Err(anyhow::anyhow!(EscrowError::AppealWindowOpen(
+                    appeal_deadline
+                )));
+            }
+        }
 
-        let secp = Secp256k1::new();
-        // Hash the decision string
-        let mut hasher = Sha256::new();
-        hasher.update("seller_claim".as_bytes());
-        let hashed_message: [u8; 32] = hasher.finalize().into();
-        // Create the message from the hash
-        let message = Message::from_slice(&hashed_message).expect("32 bytes");
-        // Sign the message using Schnorr signature
-        let signature = secp.sign_schnorr(&message, &self.key);
+        // Sign the message authorizing the action on this escrow
+        let signature = self.sign_action(&escrow_id, EscrowAction::ClaimAfterDispute, amount);
 
         // Transfer ecash back to buyer by underfunding the transaction
         let input = EscrowInput::ClaimingAfterDispute(EscrowInputClaimingAfterDispute {
             amount,
-            escrow_id,
-            hashed_message: hashed_message,
-            signature: signature,
+            escrow_id: escrow_id.clone(),
+            claimer: self.key.public_key(),
+            signature,
         });
 
-        let operation_id_clone = operation_id.clone();
-        let client_input = ClientInput {
+        self.submit_escrow_claim(
+            &escrow_value,
+            &escrow_id,
+            EscrowOperation::ClaimAfterDispute,
+            amount,
             input,
-            keys: vec![self.key.clone()],
-            state_machines: Arc::new(move |_: TransactionId, _: u64| {
-                vec![EscrowStateMachine {
-                    operation_id: operation_id_clone,
-                }]
-            }),
+            payout,
+        )
+        .await
+    }
+
+    /// Handles the buyer reclaiming the ecash of an escrow that was neither
+    /// claimed nor disputed before its deadline, or that the seller did not
+    /// accept in time
+    pub async fn refund_escrow(
+        &self,
+        escrow_id: String,
+        amount: Amount,
+    ) -> anyhow::Result<OperationId> {
+        let escrow_value: EscrowInfo = self.module_api.get_escrow_info(escrow_id.clone()).await?;
+        // only an open escrow past its deadline can be refunded, or one the seller
+        // did not accept in time, the guardians will check the deadline against their
+        // consensus time as well
+        let refundable_at = match escrow_value.state {
+            EscrowStates::Open => escrow_value.deadline,
+            EscrowStates::PendingAcceptance => escrow_value.acceptance_deadline(),
+            _ => return Err(anyhow::anyhow!(EscrowError::EscrowNotExpired)),
         };
+        if unix_time_now() < refundable_at {
+            return Err(anyhow::anyhow!(EscrowError::EscrowNotExpired));
+        }
 
-        // Build and send tx to the fed
-        // The transaction builder will create mint output to cover the input amount by
-        // itself
-        let tx =
-            TransactionBuilder::new().with_input(self.client_ctx.make_client_input(client_input));
-        let outpoint = |txid, _| OutPoint { txid, out_idx: 0 };
-        let (txid, change) = self
-            .client_ctx
-            .finalize_and_submit_transaction(operation_id, KIND.as_str(), outpoint, tx)
-            .await?;
+        // Sign the message authorizing the action on this escrow
+        let signature = self.sign_action(&escrow_id, EscrowAction::ClaimAfterExpiry, amount);
 
-        // Subscribe to transaction updates
-        let mut updates = self
-            .subscribe_transactions_output(operation_id, txid, change.clone())
-            .await
-            .unwrap()
-            .into_stream();
+        // Transfer ecash back to buyer by overfunding the transaction
+        let input = EscrowInput::ClaimingAfterExpiry(EscrowInputClaimingAfterExpiry {
+            amount,
+            escrow_id: escrow_id.clone(),
+            signature,
+        });
 
-        // Process the update stream
-        while let Some(update) = updates.next().await {
-            match update {
-                EscrowOperationState::Created | EscrowOperationState::Accepted => {}
-                EscrowOperationState::Rejected => {
-                    return Err(anyhow::anyhow!(EscrowError::TransactionRejected));
-                }
-            }
+        self.submit_escrow_action(
+            &escrow_value,
+            &escrow_id,
+            EscrowOperation::Refund,
+            amount,
+            input,
+        )
+        .await
+    }
+
+    /// Handles the buyer releasing the escrow to the seller without sharing a
+    /// secret code, which resolves it. The seller then claims the ecash with
+    /// [`Self::claim_released`].
+    pub async fn release_escrow(&self, escrow_id: String) -> anyhow::Result<OperationId> {
+        let escrow_value: EscrowInfo = self.module_api.get_escrow_info(escrow_id.clone()).await?;
+        // only the buyer releases an escrow that nobody has disputed
+        if escrow_value.buyer_pubkey != self.key.public_key() {
+            return Err(anyhow::anyhow!(EscrowError::NotBuyer));
+        }
+        if escrow_value.state != EscrowStates::Open {
+            return Err(anyhow::anyhow!(EscrowError::EscrowNotOpen));
         }
 
-        Ok(())
+        // Sign the message authorizing the action on this escrow
+        let signature = self.sign_action(&escrow_id, EscrowAction::Release, Amount::ZERO);
+
+        let input = EscrowInput::Releasing(EscrowInputReleasing {
+            escrow_id: escrow_id.clone(),
+            signature,
+        });
+
+        self.submit_escrow_action(
+            &escrow_value,
+            &escrow_id,
+            EscrowOperation::Release,
+            Amount::ZERO,
+            input,
+        )
+        .await
     }
 
-    /// Handles the initiation of dispute
-    pub async fn initiate_dispute(&self, escrow_id: String) -> anyhow::Result<()> {
-        let operation_id = OperationId(thread_rng().gen());
+    /// Handles the seller claiming the ecash of the escrow released by the
+    /// buyer, returns the claimed amount
+    pub async fn claim_released(&self, escrow_id: String) -> anyhow::Result<Amount> {
+        let escrow_value: EscrowInfo = self.module_api.get_escrow_info(escrow_id.clone()).await?;
+        // only the seller claims the ecash released by the buyer, once
+        if escrow_value.seller_pubkey != self.key.public_key() {
+            return Err(anyhow::anyhow!(EscrowError::NotSeller));
+        }
+        let amount = escrow_value.released_unclaimed;
+        if amount == Amount::ZERO {
+            return Err(anyhow::anyhow!(EscrowError::NotReleased));
+        }
 
-        let secp = Secp256k1::new();
-        // Hash the decision string
-        let mut hasher = Sha256::new();
-        hasher.update("dispute".as_bytes());
-        let hashed_message: [u8; 32] = hasher.finalize().into();
-        // Create the message from the hash
-        let message = Message::from_slice(&hashed_message).expect("32 bytes");
-        // Sign the message using Schnorr signature using disputers keypair
-        let signature = secp.sign_schnorr(&message, &self.key);
+        // Sign the message authorizing the action on this escrow
+        let signature = self.sign_action(&escrow_id, EscrowAction::ClaimReleased, amount);
+
+        // Transfer the released ecash to the seller by underfunding the transaction
+        let input = EscrowInput::ClaimingReleased(EscrowInputClaimingReleased {
+            amount,
+            escrow_id: escrow_id.clone(),
+            signature,
+        });
+
+        self.submit_escrow_action(
+            &escrow_value,
+            &escrow_id,
+            EscrowOperation::ClaimReleased,
+            amount,
+            input,
+        )
+        .await?;
+
+        Ok(amount)
+    }
+
+    /// Signs the cancellation of the escrow, the signature is shared out of
+    /// band with the counterparty who completes the cancellation
+    pub fn sign_cancellation(&self, escrow_id: &str, amount: Amount) -> Signature {
+        self.sign_action(escrow_id, EscrowAction::Cancel, amount)
+    }
+
+    /// Handles the buyer cancelling the escrow with the cancellation signature
+    /// of the seller, refunding the ecash to the buyer
+    pub async fn cancel_escrow(
+        &self,
+        escrow_id: String,
+        amount: Amount,
+        seller_signature: Signature,
+    ) -> anyhow::Result<OperationId> {
+        let escrow_value: EscrowInfo = self.module_api.get_escrow_info(escrow_id.clone()).await?;
+        // the ecash is refunded to the buyer, so only the buyer can submit the
+        // cancellation
+        if escrow_value.buyer_pubkey != self.key.public_key() {
+            return Err(anyhow::anyhow!(EscrowError::NotBuyer));
+        }
+
+        // check the signature of seller before submitting the transaction
+        let message = escrow_signing_message(
+            &self.cfg.signing_domain,
+            &escrow_id,
+            EscrowAction::Cancel,
+            amount,
+        );
+        if !verify_signature(&message, &seller_signature, &escrow_value.seller_pubkey) {
+            return Err(anyhow::anyhow!(EscrowError::InvalidCancellationSignature));
+        }
+        let buyer_signature = self.sign_cancellation(&escrow_id, amount);
+
+        // Transfer ecash back to buyer by overfunding the transaction
+        let input = EscrowInput::Cancel(EscrowInputCancel {
+            amount,
+            escrow_id: escrow_id.clone(),
+            buyer_signature,
+            seller_signature,
+        });
+
+        self.submit_escrow_action(
+            &escrow_value,
+            &escrow_id,
+            EscrowOperation::Cancel,
+            amount,
+            input,
+        )
+        .await
+    }
+
+    /// Handles the initiation of dispute, the reason is encrypted for the
+    /// arbiter and the counterparty
+    pub async fn initiate_dispute(
+        &self,
+        escrow_id: String,
+        reason: Option<String>,
+    ) -> anyhow::Result<OperationId> {
+        let escrow_value: EscrowInfo = self.module_api.get_escrow_info(escrow_id.clone()).await?;
+        let evidence = reason
+            .map(|reason| self.encrypt_evidence(&escrow_value, &escrow_id, &reason))
+            .transpose()?;
+
+        // Sign the message authorizing the action on this escrow
+        let signature = self.sign_action(
+            &escrow_id,
+            EscrowAction::Dispute {
+                evidence: evidence.clone(),
+            },
+            Amount::ZERO,
+        );
 
         let input = EscrowInput::Disputing(EscrowInputDisputing {
-            escrow_id: escrow_id,
+            escrow_id: escrow_id.clone(),
             disputer: self.key.public_key(),
-            hashed_message: hashed_message,
-            signature: signature,
+            evidence,
+            signature,
         });
 
-        let operation_id_clone = operation_id.clone();
-        let client_input = ClientInput {
+        self.submit_escrow_action(
+            &escrow_value,
+            &escrow_id,
+            EscrowOperation::Dispute,
+            Amount::ZERO,
             input,
-            keys: vec![self.key.clone()],
-            state_machines: Arc::new(move |_: TransactionId, _: u64| {
-                vec![EscrowStateMachine {
-                    operation_id: operation_id_clone,
-                }]
-            }),
+        )
+        .await
+    }
+
+    /// Handles the response of the counterparty of the disputer to the dispute,
+    /// the response is encrypted for the arbiter and the disputer
+    pub async fn respond_to_dispute(
+        &self,
+        escrow_id: String,
+        response: String,
+    ) -> anyhow::Result<OperationId> {
+        let escrow_value: EscrowInfo = self.module_api.get_escrow_info(escrow_id.clone()).await?;
+        let responder = match escrow_value.state {
+            EscrowStates::DisputedByBuyer => escrow_value.seller_pubkey,
+            EscrowStates::DisputedBySeller => escrow_value.buyer_pubkey,
+            _ => return Err(anyhow::anyhow!(EscrowError::EscrowNotDisputed)),
         };
+        if responder != self.key.public_key() {
+            return Err(anyhow::anyhow!(EscrowError::NotCounterparty));
+        }
+        let response = self.encrypt_evidence(&escrow_value, &escrow_id, &response)?;
+
+        // Sign the message authorizing the action on this escrow
+        let signature = self.sign_action(
+            &escrow_id,
+            EscrowAction::RespondToDispute {
+                response: response.clone(),
+            },
+            Amount::ZERO,
+        );
 
-        // Build and send tx to the fed
-        // The transaction builder will create mint output to cover the input amount by
-        // itself
-        let tx =
-            TransactionBuilder::new().with_input(self.client_ctx.make_client_input(client_input));
-        let outpoint = |txid, _| OutPoint { txid, out_idx: 0 };
-        let (txid, change) = self
-            .client_ctx
-            .finalize_and_submit_transaction(operation_id, KIND.as_str(), outpoint, tx)
-            .await?;
-        // Subscribe to transaction updates
-        let mut updates = self
-            .subscribe_transactions_output(operation_id, txid, change.clone())
-            .await
-            .unwrap()
-            .into_stream();
+        let input = EscrowInput::RespondingToDispute(EscrowInputRespondingToDispute {
+            escrow_id: escrow_id.clone(),
+            responder,
+            response,
+            signature,
+        });
 
-        // Process the update stream
-        while let Some(update) = updates.next().await {
-            match update {
-                EscrowOperationState::Created | EscrowOperationState::Accepted => {}
-                EscrowOperationState::Rejected => {
-                    return Err(anyhow::anyhow!(EscrowError::TransactionRejected));
+        self.submit_escrow_action(
+            &escrow_value,
+            &escrow_id,
+            EscrowOperation::RespondToDispute,
+            Amount::ZERO,
+            input,
+        )
+        .await
+    }
+
+    /// Gets the evidence attached to the dispute of the escrow, decrypting the
+    /// ones this client is a recipient of
+    pub async fn dispute_evidence(
+        &self,
+        escrow_id: String,
+    ) -> anyhow::Result<Vec<DisputeEvidenceText>> {
+        let escrow_info: EscrowInfo = self.module_api.get_escrow_info(escrow_id.clone()).await?;
+        let role = escrow_info
+            .role_of(&self.key.public_key())
+            .ok_or(EscrowError::NotParticipant)?;
+
+        let evidence = self
+            .module_api
+            .get_dispute_evidence(escrow_id.clone())
+            .await?
+            .into_iter()
+            .map(|dispute_evidence| {
+                let party_pubkey = match dispute_evidence.party {
+                    Disputer::Buyer => escrow_info.buyer_pubkey,
+                    Disputer::Seller => escrow_info.seller_pubkey,
+                };
+                // every arbiter reads its own copy, the buyer and the seller read the copy
+                // of the counterparty as both derive the same key
+                let text = match role {
+                    EscrowRole::Arbiter => escrow_info
+                        .arbiter_pubkeys
+                        .iter()
+                        .position(|arbiter_pubkey| arbiter_pubkey == &self.key.public_key())
+                        .and_then(|index| dispute_evidence.evidence.for_arbiters.get(index))
+                        .and_then(|for_arbiter| {
+                            decrypt_evidence(
+                                for_arbiter,
+                                &evidence_key(&self.key, &party_pubkey, &escrow_id),
+                            )
+                        }),
+                    EscrowRole::Buyer | EscrowRole::Seller => {
+                        let counterparty = if role == EscrowRole::Buyer {
+                            escrow_info.seller_pubkey
+                        } else {
+                            escrow_info.buyer_pubkey
+                        };
+                        decrypt_evidence(
+                            &dispute_evidence.evidence.for_counterparty,
+                            &evidence_key(&self.key, &counterparty, &escrow_id),
+                        )
+                    }
+                };
+                DisputeEvidenceText {
+                    party: dispute_evidence.party,
+                    submitted_at: dispute_evidence.submitted_at,
+                    text,
                 }
-            }
-        }
+            })
+            .collect();
 
-        Ok(())
+        Ok(evidence)
     }
 
-    /// Handles the arbiter decision making on who won the dispute
+    /// Handles the vote of an arbiter of the panel on who won the dispute, or
+    /// how the escrow is split between buyer and seller. Returns whether the
+    /// vote decided the dispute, in which case the arbiter is paid its share of
+    /// the fee right away.
     pub async fn arbiter_decision(
         &self,
         escrow_id: String,
         decision: String,
         arbiter_fee_bps: u16,
-    ) -> anyhow::Result<()> {
-        let operation_id = OperationId(thread_rng().gen());
-
-        let arbiter_decision = match decision.to_lowercase().as_str() {
-            "buyer" => ArbiterDecision::BuyerWins,
-            "seller" => ArbiterDecision::SellerWins,
+        buyer_share_bps: Option<u16>,
+    ) -> anyhow::Result<(OperationId, bool)> {
+        let arbiter_decision = match (decision.to_lowercase().as_str(), buyer_share_bps) {
+            ("buyer", None) => ArbiterDecision::BuyerWins,
+            ("seller", None) => ArbiterDecision::SellerWins,
+            ("split", Some(buyer_share_bps)) if buyer_share_bps <= MAX_BPS => {
+                ArbiterDecision::Split { buyer_share_bps }
+            }
             _ => return Err(anyhow::anyhow!(EscrowError::InvalidArbiterDecision)),
         };
 
         let escrow_value: EscrowInfo = self.module_api.get_escrow_info(escrow_id.clone()).await?;
 
-        // getting percentage from basis points
-        let fee_percentage = Decimal::from(arbiter_fee_bps) / Decimal::from(100);
         // calculating arbiter fee
-        let arbiter_fee: Amount = Amount::from_sats(
-            (Decimal::from(escrow_value.amount.msats) * fee_percentage / Decimal::from(1000))
-                .to_u64()
-                .unwrap(),
-        );
+        let arbiter_fee = bps_of(escrow_value.amount, arbiter_fee_bps);
 
-        let secp = Secp256k1::new();
-        // Hash the decision string
-        let mut hasher = Sha256::new();
-        hasher.update(decision.as_bytes());
-        let hashed_message: [u8; 32] = hasher.finalize().into();
-        // Create the message from the hash
-        let message = Message::from_slice(&hashed_message).expect("32 bytes");
-        // Sign the message using Schnorr signature
-        let signature = secp.sign_schnorr(&message, &self.key);
+        // only the arbiters of the panel vote
+        if !escrow_value
+            .arbiter_pubkeys
+            .contains(&self.key.public_key())
+        {
+            return Err(anyhow::anyhow!(EscrowError::NotArbiter));
+        }
+        // the arbiters cannot decide anymore once the arbitration deadline passed
+        if escrow_value.is_arbitration_timed_out(unix_time_now()) {
+            return Err(anyhow::anyhow!(EscrowError::ArbitrationTimedOut));
+        }
+        let vote = ArbiterVote {
+            arbiter: self.key.public_key(),
+            decision: arbiter_decision.clone(),
+            fee: arbiter_fee,
+            fee_claimed: false,
+        };
 
-        // Transfer ecash back to buyer by underfunding the transaction
+        // the vote decides the dispute if enough arbiters already agree with it, then
+        // its share of the fee is paid out
+        let decides =
+            escrow_value.agreeing_votes(&vote) + 1 >= escrow_value.arbiter_threshold as usize;
+        let payout = if decides {
+            vote.fee_share(escrow_value.arbiter_threshold)
+        } else {
+            Amount::ZERO
+        };
+
+        // Sign the message authorizing the action on this escrow
+        let signature = self.sign_action(
+            &escrow_id,
+            EscrowAction::ArbiterDecision {
+                decision: arbiter_decision.clone(),
+                fee: arbiter_fee,
+            },
+            payout,
+        );
+
+        // Transfer the share of the fee to the arbiter by underfunding the transaction
         let input = EscrowInput::ArbiterDecision(EscrowInputArbiterDecision {
+            amount: payout,
+            escrow_id: escrow_id.clone(),
+            arbiter: self.key.public_key(),
+            arbiter_decision,
+            fee: arbiter_fee,
+            signature,
+        });
+
+        let operation_id = self
+            .submit_escrow_action(
+                &escrow_value,
+                &escrow_id,
+                EscrowOperation::ArbiterDecision,
+                payout,
+                input,
+            )
+            .await?;
+
+        Ok((operation_id, decides))
+    }
+
+    /// Handles the claiming of the share of the arbiter fee by an arbiter who
+    /// voted for the decision before it was made
+    pub async fn claim_arbiter_fee(&self, escrow_id: String) -> anyhow::Result<Amount> {
+        let escrow_value: EscrowInfo = self.module_api.get_escrow_info(escrow_id.clone()).await?;
+
+        // the arbiters who voted for the decision and the fee are paid, unless
+        // already done
+        let fee_share = match (
+            &escrow_value.decided_vote,
+            escrow_value.arbiter_vote(&self.key.public_key()),
+        ) {
+            (Some(decided_vote), Some(vote))
+                if vote.agrees_with(decided_vote) && !vote.fee_claimed =>
+            {
+                vote.fee_share(escrow_value.arbiter_threshold)
+            }
+            _ => return Err(anyhow::anyhow!(EscrowError::NoArbiterFeeToClaim)),
+        };
+
+        // Sign the message authorizing the action on this escrow
+        let signature = self.sign_action(&escrow_id, EscrowAction::ClaimArbiterFee, fee_share);
+
+        let input = EscrowInput::ClaimingArbiterFee(EscrowInputClaimingArbiterFee {
+            amount: fee_share,
+            escrow_id: escrow_id.clone(),
+            arbiter: self.key.public_key(),
+            signature,
+        });
+
+        self.submit_escrow_action(
+            &escrow_value,
+            &escrow_id,
+            EscrowOperation::ClaimArbiterFee,
+            fee_share,
+            input,
+        )
+        .await?;
+
+        Ok(fee_share)
+    }
+
+    /// Handles the appeal of the decision of the arbiters by the party who lost
+    /// it, the appeal arbiter then decides the dispute again
+    pub async fn appeal(&self, escrow_id: String) -> anyhow::Result<OperationId> {
+        let escrow_value: EscrowInfo = self.module_api.get_escrow_info(escrow_id.clone()).await?;
+
+        // only the party who lost the decision can appeal it, within the appeal window
+        let appellant = match escrow_value.role_of(&self.key.public_key()) {
+            Some(EscrowRole::Buyer) => Disputer::Buyer,
+            Some(EscrowRole::Seller) => Disputer::Seller,
+            _ => return Err(anyhow::anyhow!(EscrowError::NotAppealable)),
+        };
+        let appealable = match (
+            &escrow_value.arbiter_decision,
+            escrow_value.appeal_deadline(),
+        ) {
+            (Some(arbiter_decision), Some(appeal_deadline)) => {
+                escrow_value.appealed_by.is_none()
+                    && arbiter_decision.is_lost_by(&appellant)
+                    && unix_time_now() < appeal_deadline
+            }
+            _ => false,
+        };
+        if !appealable {
+            return Err(anyhow::anyhow!(EscrowError::NotAppealable));
+        }
+
+        // Sign the message authorizing the action on this escrow
+        let signature = self.sign_action(&escrow_id, EscrowAction::Appeal, Amount::ZERO);
+
+        let input = EscrowInput::Appealing(EscrowInputAppealing {
+            escrow_id: escrow_id.clone(),
+            appellant: self.key.public_key(),
+            signature,
+        });
+
+        self.submit_escrow_action(
+            &escrow_value,
+            &escrow_id,
+            EscrowOperation::Appeal,
+            Amount::ZERO,
+            input,
+        )
+        .await
+    }
+
+    /// Handles the decision of the appeal arbiter on an appealed escrow, which
+    /// supersedes the decision of the arbiters. The appeal arbiter is paid its
+    /// fee right away.
+    pub async fn appeal_decision(
+        &self,
+        escrow_id: String,
+        decision: String,
+        arbiter_fee_bps: u16,
+        buyer_share_bps: Option<u16>,
+    ) -> anyhow::Result<OperationId> {
+        let arbiter_decision = match (decision.to_lowercase().as_str(), buyer_share_bps) {
+            ("buyer", None) => ArbiterDecision::BuyerWins,
+            ("seller", None) => ArbiterDecision::SellerWins,
+            ("split", Some(buyer_share_bps)) if buyer_share_bps <= MAX_BPS => {
+                ArbiterDecision::Split { buyer_share_bps }
+            }
+            _ => return Err(anyhow::anyhow!(EscrowError::InvalidArbiterDecision)),
+        };
+
+        let escrow_value: EscrowInfo = self.module_api.get_escrow_info(escrow_id.clone()).await?;
+
+        // only the appeal arbiter decides an appealed escrow
+        if escrow_value.appeal_arbiter != Some(self.key.public_key())
+            || !escrow_value.state.is_appealed()
+        {
+            return Err(anyhow::anyhow!(EscrowError::NotAppealArbiter));
+        }
+        // the decision of the arbiters stands once the appeal deadline has passed
+        if escrow_value.is_appeal_timed_out(unix_time_now()) {
+            return Err(anyhow::anyhow!(EscrowError::AppealTimedOut));
+        }
+
+        // calculating appeal arbiter fee
+        let arbiter_fee = bps_of(escrow_value.amount, arbiter_fee_bps);
+
+        // Sign the message authorizing the action on this escrow
+        let signature = self.sign_action(
+            &escrow_id,
+            EscrowAction::AppealDecision(arbiter_decision.clone()),
+            arbiter_fee,
+        );
+
+        // Transfer the fee to the appeal arbiter by underfunding the transaction
+        let input = EscrowInput::AppealDecision(EscrowInputAppealDecision {
             amount: arbiter_fee,
-            escrow_id: escrow_id,
+            escrow_id: escrow_id.clone(),
             arbiter_decision,
-            hashed_message: hashed_message,
-            signature: signature,
+            signature,
         });
 
-        let operation_id_clone = operation_id.clone();
+        self.submit_escrow_action(
+            &escrow_value,
+            &escrow_id,
+            EscrowOperation::AppealDecision,
+            arbiter_fee,
+            input,
+        )
+        .await
+    }
+
+    /// Submits the transaction of an action of this client on the escrow and
+    /// waits for the federation to accept it. The action is recorded in the
+    /// operation log, and the last known state of the escrow is refreshed once
+    /// it succeeded.
+    async fn submit_escrow_action(
+        &self,
+        escrow_info: &EscrowInfo,
+        escrow_id: &str,
+        operation: EscrowOperation,
+        amount: Amount,
+        input: EscrowInput,
+    ) -> anyhow::Result<OperationId> {
+        self.submit_escrow_claim(
+            escrow_info,
+            escrow_id,
+            operation,
+            amount,
+            input,
+            ClaimPayout::Wallet,
+        )
+        .await
+    }
+
+    /// Submits the transaction of an action of this client on the escrow like
+    /// [`Self::submit_escrow_action`], the ecash released by the action is
+    /// paid out in the same transaction
+    async fn submit_escrow_claim(
+        &self,
+        escrow_info: &EscrowInfo,
+        escrow_id: &str,
+        operation: EscrowOperation,
+        amount: Amount,
+        input: EscrowInput,
+        payout: ClaimPayout,
+    ) -> anyhow::Result<OperationId> {
+        let operation_id = OperationId(thread_rng().gen());
         let client_input = ClientInput {
             input,
             keys: vec![self.key.clone()],
-            state_machines: Arc::new(move |_: TransactionId, _: u64| {
-                vec![EscrowStateMachine {
-                    operation_id: operation_id_clone,
-                }]
+            // the escrow is tracked by its watcher, not by the transaction
+            state_machines: Arc::new(|_: TransactionId, _: u64| {
+                Vec::<EscrowClientStateMachines>::new()
             }),
         };
 
         // Build and send tx to the fed
         // The transaction builder will create mint output to cover the input amount by
-        // itself
-        let tx =
+        // itself, what the payout leaves of it
+        let mut tx =
             TransactionBuilder::new().with_input(self.client_ctx.make_client_input(client_input));
-        let outpoint = |txid, _| OutPoint { txid, out_idx: 0 };
-        let (txid, change) = self
-            .client_ctx
-            .finalize_and_submit_transaction(operation_id, KIND.as_str(), outpoint, tx)
+        let role = escrow_info
+            .role_of(&self.key.public_key())
+            .ok_or(EscrowError::NotParticipant)?;
+        match payout {
+            ClaimPayout::Wallet => {}
+            ClaimPayout::Invoice { invoice, gateway } => {
+                tx = tx.with_output(
+                    self.invoice_payout_output(operation_id, escrow_id, role, invoice, gateway)
+                        .await?,
+                );
+            }
+            ClaimPayout::PegOut {
+                address,
+                amount,
+                fees,
+            } => {
+                tx = tx.with_output(self.peg_out_output(operation_id, address, amount, fees)?);
+            }
+        }
+        let operation_meta =
+            self.operation_meta(escrow_info, escrow_id.to_owned(), operation, amount)?;
+        self.client_ctx
+            .finalize_and_submit_transaction(operation_id, KIND.as_str(), operation_meta, tx)
             .await?;
 
         // Subscribe to transaction updates
         let mut updates = self
-            .subscribe_transactions_output(operation_id, txid, change.clone())
-            .await
-            .unwrap()
+            .subscribe_transactions_output(operation_id)
+            .await?
             .into_stream();
 
         // Process the update stream
@@ -587,16 +1396,391 @@ impl EscrowClientModule {
             }
         }
 
-        Ok(())
+        // remember the last known state of the escrow
+        self.refresh_escrow_or_warn(escrow_id).await;
+
+        Ok(operation_id)
+    }
+
+    /// Prepares the payout of the claimed ecash to the Lightning invoice
+    /// through the gateway of the federation, the claimed ecash has to cover
+    /// the invoice and the routing fee of the gateway
+    pub async fn invoice_payout(
+        &self,
+        invoice: Bolt11Invoice,
+        claimed: Amount,
+    ) -> anyhow::Result<ClaimPayout> {
+        let lightning = self.client_ctx.get_first_module::<LightningClientModule>();
+        let gateway = lightning
+            .select_active_gateway_opt()
+            .await
+            .ok_or(EscrowError::NoGateway)?;
+        check_payout_invoice(&invoice, claimed, &gateway.fees)?;
+        Ok(ClaimPayout::Invoice { invoice, gateway })
+    }
+
+    /// The output funding the outgoing contract paying the invoice, the
+    /// gateway claims it once it paid the invoice, otherwise the client takes
+    /// it back after its timelock
+    async fn invoice_payout_output(
+        &self,
+        operation_id: OperationId,
+        escrow_id: &str,
+        role: EscrowRole,
+        invoice: Bolt11Invoice,
+        gateway: LightningGateway,
+    ) -> anyhow::Result<ClientOutput<DynOutput, DynState>> {
+        let lightning = self.client_ctx.get_first_module::<LightningClientModule>();
+        let invoice_amount = invoice
+            .amount_milli_satoshis()
+            .map(Amount::from_msats)
+            .ok_or(EscrowError::InvalidPayoutInvoice)?;
+        let block_count = lightning
+            .api
+            .fetch_consensus_block_count()
+            .await?
+            .context("Consensus block count is not available")?;
+
+        let contract = OutgoingContractAccount {
+            amount: invoice_amount + gateway.fees.to_amount(&invoice_amount),
+            contract: OutgoingContract {
+                hash: *invoice.payment_hash(),
+                gateway_key: gateway.gateway_redeem_key,
+                timelock: (block_count + PAYOUT_TIMELOCK_BLOCKS) as u32,
+                user_key: self.key.public_key(),
+                cancelled: false,
+            },
+        };
+        let output = LightningOutput::new_v0_contract(ContractOutput {
+            amount: contract.amount,
+            contract: Contract::Outgoing(contract.contract.clone()),
+        });
+
+        // proves to the gateway that the payment comes from this client
+        let mut preimage_auth = sha256::Hash::engine();
+        preimage_auth.input(&self.key.secret_bytes());
+        preimage_auth.input(invoice.payment_hash().as_ref());
+        let payout = InvoicePayoutStateMachine {
+            operation_id,
+            escrow_id: escrow_id.to_owned(),
+            role,
+            contract,
+            invoice,
+            gateway_api: gateway.api,
+            preimage_auth: sha256::Hash::from_engine(preimage_auth),
+            state: InvoicePayoutState::Paying,
+        };
+        let client_ctx = self.client_ctx.clone();
+        Ok(ClientOutput {
+            output: DynOutput::from_typed(lightning.id, output),
+            state_machines: Arc::new(move |txid: TransactionId, _: u64| {
+                vec![
+                    client_ctx.make_dyn_state(EscrowClientStateMachines::InvoicePayout(
+                        InvoicePayoutStateMachine {
+                            state: InvoicePayoutState::Funding { txid },
+                            ..payout.clone()
+                        },
+                    )),
+                ]
+            }),
+        })
+    }
+
+    /// Waits for the gateway to pay the invoice with the ecash claimed by an
+    /// escrow operation, returns the preimage of the invoice. If the gateway
+    /// does not pay it, the claimed ecash is refunded to the wallet.
+    pub async fn await_invoice_payout(
+        &self,
+        claim_operation_id: OperationId,
+    ) -> anyhow::Result<String> {
+        let mut state_machines = self.notifier.subscribe(claim_operation_id).await;
+        while let Some(state_machine) = state_machines.next().await {
+            let EscrowClientStateMachines::InvoicePayout(payout) = state_machine else {
+                continue;
+            };
+            match payout.state {
+                InvoicePayoutState::Funding { .. }
+                | InvoicePayoutState::Paying
+                | InvoicePayoutState::AwaitingRefund { .. } => {}
+                InvoicePayoutState::Paid { preimage } => return Ok(preimage),
+                InvoicePayoutState::Refunded { error } | InvoicePayoutState::Failed { error } => {
+                    return Err(anyhow::anyhow!(
+                        "{}: {}",
+                        EscrowError::InvoicePaymentFailed,
+                        error
+                    ));
+                }
+            }
+        }
+        Err(anyhow::anyhow!(EscrowError::InvoicePaymentFailed))
+    }
+
+    /// Prepares the peg-out of the claimed ecash to the address, estimating
+    /// its fees which are paid from the claim. The address receives the rest.
+    pub async fn peg_out_payout(
+        &self,
+        address: Address,
+        claimed: Amount,
+    ) -> anyhow::Result<ClaimPayout> {
+        let wallet = self.client_ctx.get_first_module::<WalletClientModule>();
+        check_peg_out_address(&address, wallet.get_network())?;
+        // on-chain amounts are whole sats, the msats left over stay in the wallet
+        let claimed = bitcoin::Amount::from_sat(claimed.msats / 1000);
+        let fees = wallet.get_withdraw_fees(address.clone(), claimed).await?;
+        let amount = claimed
+            .checked_sub(fees.amount())
+            .ok_or(EscrowError::ClaimBelowPegOutFees)?;
+        Ok(ClaimPayout::PegOut {
+            address,
+            amount,
+            fees,
+        })
+    }
+
+    /// The output pegging the claimed ecash out to the address
+    fn peg_out_output(
+        &self,
+        operation_id: OperationId,
+        address: Address,
+        amount: bitcoin::Amount,
+        fees: PegOutFees,
+    ) -> anyhow::Result<ClientOutput<DynOutput, DynState>> {
+        let wallet = self.client_ctx.get_first_module::<WalletClientModule>();
+        check_peg_out_address(&address, wallet.get_network())?;
+        let output = WalletOutput::new_v0_peg_out(address, amount, fees);
+        let client_ctx = self.client_ctx.clone();
+        Ok(ClientOutput {
+            output: DynOutput::from_typed(wallet.id, output),
+            state_machines: Arc::new(move |txid: TransactionId, out_idx: u64| {
+                vec![client_ctx.make_dyn_state(EscrowClientStateMachines::PegOut(
+                    PegOutStateMachine {
+                        operation_id,
+                        out_point: OutPoint { txid, out_idx },
+                        state: PegOutState::Created,
+                    },
+                ))]
+            }),
+        })
+    }
+
+    /// Waits for the federation to peg out the ecash claimed by an escrow
+    /// operation, returns the id of the on-chain transaction
+    pub async fn await_peg_out(&self, claim_operation_id: OperationId) -> anyhow::Result<Txid> {
+        let mut state_machines = self.notifier.subscribe(claim_operation_id).await;
+        while let Some(state_machine) = state_machines.next().await {
+            let EscrowClientStateMachines::PegOut(peg_out) = state_machine else {
+                continue;
+            };
+            match peg_out.state {
+                PegOutState::Created => {}
+                PegOutState::Succeeded { txid } => return Ok(txid),
+                PegOutState::Failed { error } => {
+                    return Err(anyhow::anyhow!("{}: {}", EscrowError::PegOutFailed, error));
+                }
+            }
+        }
+        Err(anyhow::anyhow!(EscrowError::PegOutFailed))
+    }
+
+    /// Gets the escrow info from the federation and stores its last known state
+    /// if this client takes part in the escrow
+    pub async fn refresh_escrow(&self, escrow_id: &str) -> anyhow::Result<EscrowInfo> {
+        let escrow_info: EscrowInfo = self
+            .module_api
+            .get_escrow_info(escrow_id.to_owned())
+            .await?;
+
+        if let Some(role) = escrow_info.role_of(&self.key.public_key()) {
+            let escrow_key = EscrowKey {
+                escrow_id: escrow_id.to_owned(),
+            };
+            let mut dbtx = self.db.begin_transaction().await;
+            // the secret codes are never stored by the federation, keep the ones we know
+            let secret_codes = dbtx
+                .get_value(&escrow_key)
+                .await
+                .map(|escrow_value| escrow_value.secret_codes)
+                .unwrap_or_default();
+            dbtx.insert_entry(
+                &escrow_key,
+                &EscrowValue::new(role, &escrow_info, secret_codes),
+            )
+            .await;
+            dbtx.commit_tx().await;
+
+            self.watch_escrow(escrow_id).await;
+        }
+
+        Ok(escrow_info)
+    }
+
+    /// Starts the watcher of an escrow this client takes part in, unless it
+    /// was already started
+    async fn watch_escrow(&self, escrow_id: &str) {
+        let watcher = self
+            .client_ctx
+            .make_dyn_state(EscrowClientStateMachines::Watcher(EscrowStateMachine::new(
+                escrow_id.to_owned(),
+            )));
+        let mut dbtx = self.client_ctx.global_db().begin_transaction().await;
+        match self
+            .client_ctx
+            .add_state_machines(&mut dbtx.to_ref_nc(), vec![watcher])
+            .await
+        {
+            Ok(()) => dbtx.commit_tx().await,
+            // the watcher of the escrow is running or has seen it resolved
+            Err(AddStateMachinesError::StateAlreadyExists) => {}
+            Err(e) => tracing::warn!("Failed to watch escrow {}: {:?}", escrow_id, e),
+        }
+    }
+
+    /// Refreshes the escrow after an action, the action already succeeded thus
+    /// a failure is only logged
+    async fn refresh_escrow_or_warn(&self, escrow_id: &str) {
+        if let Err(e) = self.refresh_escrow(escrow_id).await {
+            tracing::warn!("Failed to refresh escrow {}: {:?}", escrow_id, e);
+        }
+    }
+
+    /// Lists the escrows this client created or takes part in, with their last
+    /// known state
+    pub async fn list_escrows(&self) -> Vec<(String, EscrowValue)> {
+        let mut dbtx = self.db.begin_transaction_nc().await;
+        dbtx.find_by_prefix(&EscrowKeyPrefix)
+            .await
+            .map(|(escrow_key, escrow_value)| (escrow_key.escrow_id, escrow_value))
+            .collect::<Vec<_>>()
+            .await
+    }
+
+    /// Signs the message authorizing the action on the escrow, see
+    /// [`escrow_signing_message`]
+    fn sign_action(&self, escrow_id: &str, action: EscrowAction, amount: Amount) -> Signature {
+        let secp = Secp256k1::new();
+        secp.sign_schnorr(
+            &escrow_signing_message(&self.cfg.signing_domain, escrow_id, action, amount),
+            &self.key,
+        )
+    }
+
+    /// Encrypts the evidence of this client for every arbiter and the
+    /// counterparty of the escrow
+    fn encrypt_evidence(
+        &self,
+        escrow_info: &EscrowInfo,
+        escrow_id: &str,
+        text: &str,
+    ) -> anyhow::Result<EncryptedEvidence> {
+        let counterparty = match escrow_info.role_of(&self.key.public_key()) {
+            Some(EscrowRole::Buyer) => escrow_info.seller_pubkey,
+            Some(EscrowRole::Seller) => escrow_info.buyer_pubkey,
+            _ => return Err(anyhow::anyhow!(EscrowError::NotParticipant)),
+        };
+        let evidence = EncryptedEvidence {
+            for_arbiters: escrow_info
+                .arbiter_pubkeys
+                .iter()
+                .map(|arbiter_pubkey| {
+                    encrypt_evidence(text, &evidence_key(&self.key, arbiter_pubkey, escrow_id))
+                })
+                .collect(),
+            for_counterparty: encrypt_evidence(
+                text,
+                &evidence_key(&self.key, &counterparty, escrow_id),
+            ),
+        };
+        if !evidence.is_within_size_limit() {
+            return Err(anyhow::anyhow!(EscrowError::EvidenceTooLarge));
+        }
+        Ok(evidence)
+    }
+
+    /// The operation meta recorded for an action of this client on the escrow
+    fn operation_meta(
+        &self,
+        escrow_info: &EscrowInfo,
+        escrow_id: String,
+        operation: EscrowOperation,
+        amount: Amount,
+    ) -> anyhow::Result<
+        impl Fn(TransactionId, Vec<OutPoint>) -> EscrowOperationMeta + Clone + MaybeSend + MaybeSync,
+    > {
+        let role = escrow_info
+            .role_of(&self.key.public_key())
+            .ok_or(EscrowError::NotParticipant)?;
+        let counterparty = match role {
+            EscrowRole::Buyer => Some(escrow_info.seller_pubkey),
+            EscrowRole::Seller => Some(escrow_info.buyer_pubkey),
+            EscrowRole::Arbiter => None,
+        };
+        Ok(operation_meta_fn(
+            escrow_id,
+            operation,
+            role,
+            amount,
+            counterparty,
+        ))
+    }
+
+    /// Gets the meta of an escrow operation from the operation log
+    async fn get_operation_meta(
+        &self,
+        operation_id: OperationId,
+    ) -> anyhow::Result<EscrowOperationMeta> {
+        let operation = self.client_ctx.get_operation(operation_id).await?;
+        if operation.operation_module_kind() != KIND.as_str() {
+            bail!("Operation is not an escrow operation");
+        }
+        Ok(operation.meta())
+    }
+
+    /// Subscribes to the updates of an escrow this client takes part in, the
+    /// stream ends once the escrow is resolved. The updates seen by the
+    /// watcher of the escrow are replayed first, thus the escrow can be
+    /// watched again after a restart of the client
+    pub async fn subscribe_escrow_updates(
+        &self,
+        escrow_id: &str,
+    ) -> anyhow::Result<UpdateStreamOrOutcome<EscrowUpdate>> {
+        let escrow_info = self.refresh_escrow(escrow_id).await?;
+        if escrow_info.role_of(&self.key.public_key()).is_none() {
+            return Err(anyhow::anyhow!(EscrowError::NotParticipant));
+        }
+        let mut state_machines = self
+            .notifier
+            .subscribe(watcher_operation_id(escrow_id))
+            .await;
+
+        Ok(UpdateStreamOrOutcome::UpdateStream(Box::pin(stream! {
+            while let Some(state_machine) = state_machines.next().await {
+                let EscrowClientStateMachines::Watcher(state_machine) = state_machine else {
+                    continue;
+                };
+                match state_machine.state {
+                    EscrowClientState::Watching { escrow_info: None } => {}
+                    EscrowClientState::Watching {
+                        escrow_info: Some(escrow_info),
+                    } => {
+                        yield EscrowUpdate::Changed(escrow_info);
+                    }
+                    EscrowClientState::Resolved { escrow_info } => {
+                        yield EscrowUpdate::Resolved(escrow_info);
+                        break;
+                    }
+                }
+            }
+        })))
     }
 
     /// Subscribes to the transaction updates and yields the state of operation,
-    /// when the transaction has input attached not output!
+    /// when the transaction has input attached not output! The transaction is
+    /// looked up in the operation log, thus it also works after a restart
     pub async fn subscribe_transactions_input(
         &self,
         operation_id: OperationId,
-        txid: TransactionId,
     ) -> anyhow::Result<UpdateStreamOrOutcome<EscrowOperationState>> {
+        let txid = self.get_operation_meta(operation_id).await?.txid;
         let tx_subscription = self.client_ctx.transaction_updates(operation_id).await;
 
         Ok(UpdateStreamOrOutcome::UpdateStream(Box::pin(stream! {
@@ -615,13 +1799,14 @@ impl EscrowClientModule {
     }
 
     /// Subscribes to the transaction updates and yields the state of operation
-    /// when the transaction has output attached not input!
+    /// when the transaction has output attached not input! The transaction is
+    /// looked up in the operation log, thus it also works after a restart
     pub async fn subscribe_transactions_output(
         &self,
         operation_id: OperationId,
-        txid: TransactionId,
-        change: Vec<OutPoint>,
     ) -> anyhow::Result<UpdateStreamOrOutcome<EscrowOperationState>> {
+        let EscrowOperationMeta { txid, change, .. } =
+            self.get_operation_meta(operation_id).await?;
         let tx_subscription = self.client_ctx.transaction_updates(operation_id).await;
         let client_ctx = self.client_ctx.clone();
 
@@ -657,12 +1842,42 @@ impl ModuleInit for EscrowClientInit {
     type Common = EscrowCommonInit;
     const DATABASE_VERSION: DatabaseVersion = DatabaseVersion(0);
 
+    /// Dumps all database items for debugging
     async fn dump_database(
         &self,
-        _dbtx: &mut DatabaseTransaction<'_>,
-        _prefix_names: Vec<String>,
+        dbtx: &mut DatabaseTransaction<'_>,
+        prefix_names: Vec<String>,
     ) -> Box<dyn Iterator<Item = (String, Box<dyn erased_serde::Serialize + Send>)> + '_> {
-        Box::new(std::iter::empty())
+        let mut items: BTreeMap<String, Box<dyn erased_serde::Serialize + Send>> = BTreeMap::new();
+        let filtered_prefixes = DbKeyPrefix::iter().filter(|f| {
+            prefix_names.is_empty() || prefix_names.contains(&f.to_string().to_lowercase())
+        });
+
+        for prefix in filtered_prefixes {
+            match prefix {
+                DbKeyPrefix::Escrow => {
+                    push_db_pair_items!(
+                        dbtx,
+                        EscrowKeyPrefix,
+                        EscrowKey,
+                        EscrowValue,
+                        items,
+                        "Escrow"
+                    );
+                }
+                DbKeyPrefix::InvoiceEscrow => {
+                    push_db_pair_items!(
+                        dbtx,
+                        InvoiceEscrowKeyPrefix,
+                        InvoiceEscrowKey,
+                        NewEscrow,
+                        items,
+                        "Invoice Escrow"
+                    );
+                }
+            }
+        }
+        Box::new(items.into_iter())
     }
 }
 
@@ -676,6 +1891,78 @@ impl ClientModuleInit for EscrowClientInit {
             .expect("no version conflicts")
     }
 
+    /// Rediscovers the escrows this client takes part in from the federation,
+    /// the secret codes can only be restored from the backup
+    async fn recover(
+        &self,
+        args: &ClientModuleRecoverArgs<Self>,
+        snapshot: Option<&EscrowModuleBackup>,
+    ) -> anyhow::Result<()> {
+        let pubkey = args
+            .module_root_secret()
+            .clone()
+            .to_secp_key(&Secp256k1::new())
+            .public_key();
+
+        let mut secret_codes: BTreeMap<String, Vec<String>> = snapshot
+            .map(|backup| {
+                backup
+                    .escrows
+                    .iter()
+                    .map(|escrow| (escrow.escrow_id.clone(), escrow.secret_codes.clone()))
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        // the escrows of the backup and the ones known by the federation, the latter
+        // includes the escrows created after the backup was taken
+        let mut escrow_ids: BTreeSet<String> = secret_codes.keys().cloned().collect();
+        let mut offset = 0;
+        loop {
+            let response = args
+                .module_api()
+                .list_escrows_by_pubkey(ListEscrowsRequest {
+                    pubkey,
+                    role: None,
+                    offset,
+                    limit: MAX_LIST_ESCROWS_LIMIT,
+                })
+                .await?;
+            escrow_ids.extend(
+                response
+                    .escrows
+                    .into_iter()
+                    .map(|escrow_summary| escrow_summary.escrow_id),
+            );
+            match response.next_offset {
+                Some(next_offset) => offset = next_offset,
+                None => break,
+            }
+        }
+
+        let mut dbtx = args.db().begin_transaction().await;
+        for escrow_id in escrow_ids {
+            let escrow_info = args.module_api().get_escrow_info(escrow_id.clone()).await?;
+            let Some(role) = escrow_info.role_of(&pubkey) else {
+                continue;
+            };
+            dbtx.insert_entry(
+                &EscrowKey {
+                    escrow_id: escrow_id.clone(),
+                },
+                &EscrowValue::new(
+                    role,
+                    &escrow_info,
+                    secret_codes.remove(&escrow_id).unwrap_or_default(),
+                ),
+            )
+            .await;
+        }
+        dbtx.commit_tx().await;
+
+        Ok(())
+    }
+
     async fn init(&self, args: &ClientModuleInitArgs<Self>) -> anyhow::Result<Self::Module> {
         let cfg = args.cfg().clone();
         Ok(EscrowClientModule {
@@ -687,6 +1974,114 @@ impl ClientModuleInit for EscrowClientInit {
                 .to_secp_key(&Secp256k1::new()),
             client_ctx: args.context(),
             db: args.db().clone(),
+            notifier: args.notifier().clone(),
         })
     }
 }
+
+/// Creates the operation meta of the transaction of an escrow action
+fn operation_meta_fn(
+    escrow_id: String,
+    operation: EscrowOperation,
+    role: EscrowRole,
+    amount: Amount,
+    counterparty: Option<PublicKey>,
+) -> impl Fn(TransactionId, Vec<OutPoint>) -> EscrowOperationMeta + Clone + MaybeSend + MaybeSync {
+    move |txid, change| EscrowOperationMeta {
+        escrow_id: escrow_id.clone(),
+        operation: operation.clone(),
+        role,
+        amount,
+        counterparty,
+        txid,
+        change,
+    }
+}
+
+/// The current unix time (in seconds) of this client
+pub fn unix_time_now() -> u64 {
+    fedimint_core::time::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("time is after unix epoch")
+        .as_secs()
+}
+
+/// Checks the terms of a new escrow of the buyer against the limits of the
+/// federation
+pub fn check_new_escrow(
+    cfg: &EscrowClientConfig,
+    buyer_pubkey: &PublicKey,
+    new_escrow: &NewEscrow,
+) -> anyhow::Result<()> {
+    // the federation limits the amount and the id of an escrow
+    cfg.limit_escrow_amount(new_escrow.amount)?;
+    cfg.limit_arbitration_timeout_secs(new_escrow.arbitration_timeout_secs)?;
+    if new_escrow.escrow_id.len() > cfg.max_escrow_id_length as usize {
+        return Err(anyhow::anyhow!(EscrowError::EscrowIdTooLong));
+    }
+
+    // the milestones should add up to the amount locked in the escrow
+    if new_escrow.milestone_amounts.is_empty()
+        || new_escrow
+            .milestone_amounts
+            .iter()
+            .fold(Amount::ZERO, |total, amount| total + *amount)
+            != new_escrow.amount
+    {
+        return Err(anyhow::anyhow!(EscrowError::InvalidMilestones));
+    }
+    if !new_escrow.payment_hashes.is_empty()
+        && new_escrow.payment_hashes.len() != new_escrow.milestone_amounts.len()
+    {
+        return Err(anyhow::anyhow!(EscrowError::InvalidPaymentHashes));
+    }
+
+    // the panel needs distinct arbiters other than the buyer and the seller, and a
+    // reachable threshold
+    let arbiter_pubkeys = &new_escrow.arbiter_pubkeys;
+    if !is_valid_panel(
+        arbiter_pubkeys,
+        new_escrow.arbiter_threshold,
+        buyer_pubkey,
+        &new_escrow.seller_pubkey,
+    ) {
+        return Err(anyhow::anyhow!(EscrowError::InvalidArbiters));
+    }
+    // the appeal arbiter reviews the decision of the panel, thus it is not part of
+    // it
+    if let Some(appeal_arbiter) = &new_escrow.appeal_arbiter {
+        if arbiter_pubkeys.contains(appeal_arbiter)
+            || appeal_arbiter == &new_escrow.seller_pubkey
+            || appeal_arbiter == buyer_pubkey
+        {
+            return Err(anyhow::anyhow!(EscrowError::InvalidAppealArbiter));
+        }
+    }
+
+    // the max_arbiter_fee_bps should be in range 10 (0.1%) to the maximum of the
+    // federation
+    cfg.limit_max_arbiter_fee_bps(new_escrow.max_arbiter_fee_bps)?;
+    Ok(())
+}
+
+/// Checks that the address of a peg-out is on the network of the federation
+pub fn check_peg_out_address(address: &Address, network: Network) -> anyhow::Result<()> {
+    if !address.is_valid_for_network(network) {
+        return Err(anyhow::anyhow!(EscrowError::WrongAddressNetwork));
+    }
+    Ok(())
+}
+
+/// Checks that the claimed ecash covers the amount of the invoice it is paid
+/// out to, which has to name an amount, and the routing fee the gateway takes
+/// from it
+pub fn check_payout_invoice(
+    invoice: &Bolt11Invoice,
+    claimed: Amount,
+    fees: &RoutingFees,
+) -> anyhow::Result<()> {
+    match invoice.amount_milli_satoshis().map(Amount::from_msats) {
+        Some(amount) if amount + fees.to_amount(&amount) <= claimed => Ok(()),
+        _ => Err(anyhow::anyhow!(EscrowError::InvalidPayoutInvoice)),
+    }
+}
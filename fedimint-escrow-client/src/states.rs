@@ -1,42 +1,733 @@
-use fedimint_client::sm::{Context, DynState, State, StateTransition};
-use fedimint_client::DynGlobalClientContext;
-use fedimint_core::core::{Decoder, IntoDynInstance, ModuleInstanceId, OperationId};
+use std::sync::Arc;
+use std::time::Duration;
+
+use bitcoin::hashes::{sha256, Hash, HashEngine};
+use fedimint_client::module::ClientContext;
+use fedimint_client::sm::{ClientSMDatabaseTransaction, Context, DynState, State, StateTransition};
+use fedimint_client::transaction::{ClientInput, ClientOutput, TransactionBuilder};
+use fedimint_client::{sm_enum_variant_translation, DynGlobalClientContext};
+use fedimint_core::api::{DynModuleApi, GlobalFederationApi};
+use fedimint_core::config::FederationId;
+use fedimint_core::core::{
+    Decoder, DynInput, IntoDynInstance, KeyPair, ModuleInstanceId, OperationId,
+};
+use fedimint_core::db::IDatabaseTransactionOpsCoreTyped;
 use fedimint_core::encoding::{Decodable, Encodable};
+use fedimint_core::module::ModuleCommon;
+use fedimint_core::util::SafeUrl;
+use fedimint_core::{OutPoint, TransactionId};
+use fedimint_escrow_common::config::EscrowClientConfig;
+use fedimint_escrow_common::endpoints::EscrowInfo;
+use fedimint_escrow_common::{EscrowError, EscrowRole, KIND};
+use fedimint_ln_client::api::LnFederationApi;
+use fedimint_ln_client::{LightningClientModule, LnReceiveState};
+use fedimint_ln_common::contracts::outgoing::OutgoingContractAccount;
+use fedimint_ln_common::contracts::IdentifiableContract;
+use fedimint_ln_common::{PayInvoicePayload, PaymentData};
+use fedimint_wallet_common::{WalletModuleTypes, WalletOutputOutcome, WalletOutputOutcomeV0};
+use futures::StreamExt;
+use lightning_invoice::Bolt11Invoice;
+use rand::{thread_rng, Rng};
+use tracing::{debug, warn};
+
+use crate::api::EscrowFederationApi;
+use crate::db::{EscrowKey, EscrowValue, InvoiceEscrowKey};
+use crate::{
+    check_new_escrow, operation_meta_fn, unix_time_now, EscrowClientModule, EscrowOperation,
+    NewEscrow,
+};
+
+/// How often the federation is asked whether the escrow changed
+const ESCROW_POLL_INTERVAL: Duration = Duration::from_secs(10);
+
+/// The number of blocks after which the ecash of a Lightning payout that the
+/// gateway did not pay can be taken back
+pub const PAYOUT_TIMELOCK_BLOCKS: u64 = 500;
 
+/// The state machines of the escrow client module
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Decodable, Encodable)]
+pub enum EscrowClientStateMachines {
+    /// watches an escrow this client takes part in
+    Watcher(EscrowStateMachine),
+    /// funds an escrow of this client through a Lightning invoice
+    InvoiceFunding(InvoiceEscrowStateMachine),
+    /// pays the ecash claimed from an escrow out to a Lightning invoice
+    InvoicePayout(InvoicePayoutStateMachine),
+    /// follows the peg-out of the ecash claimed from an escrow
+    PegOut(PegOutStateMachine),
+}
+
+/// The watcher of an escrow this client takes part in, there is a single one
+/// per escrow whatever the number of actions of this client on it
 #[derive(Debug, Clone, Eq, PartialEq, Hash, Decodable, Encodable)]
 pub struct EscrowStateMachine {
     pub operation_id: OperationId,
+    pub escrow_id: String,
+    pub state: EscrowClientState,
+}
+
+/// The states of the escrow as tracked by the client
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Decodable, Encodable)]
+pub enum EscrowClientState {
+    /// waiting for the escrow to change from the last known one, none until
+    /// the escrow is looked up for the first time
+    Watching { escrow_info: Option<EscrowInfo> },
+    /// the escrow is resolved and cannot change anymore
+    Resolved { escrow_info: EscrowInfo },
+}
+
+impl EscrowStateMachine {
+    /// The watcher of the escrow before it is looked up for the first time.
+    /// Its operation is derived from the escrow id, thus the client refuses
+    /// to add a second watcher of the same escrow.
+    pub fn new(escrow_id: String) -> Self {
+        EscrowStateMachine {
+            operation_id: watcher_operation_id(&escrow_id),
+            escrow_id,
+            state: EscrowClientState::Watching { escrow_info: None },
+        }
+    }
+}
+
+/// The operation of the watcher of the escrow
+pub fn watcher_operation_id(escrow_id: &str) -> OperationId {
+    let mut engine = sha256::Hash::engine();
+    engine.input(b"escrow-watcher");
+    engine.input(escrow_id.as_bytes());
+    OperationId(sha256::Hash::from_engine(engine).into_inner())
+}
+
+/// Funds an escrow of this client with the ecash received through a
+/// Lightning invoice, so that the escrow is created once the invoice is paid
+/// even if nobody waits for it. The funding is not atomic: the Lightning
+/// module first claims the incoming contract of the invoice into the wallet,
+/// then a second transaction locks the ecash in the escrow. If the second one
+/// fails, the ecash received through the invoice stays in the wallet.
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Decodable, Encodable)]
+pub struct InvoiceEscrowStateMachine {
+    /// the operation of the invoice
+    pub operation_id: OperationId,
+    pub new_escrow: NewEscrow,
+    /// the secret codes of the milestones, only known by the buyer
+    pub secret_codes: Vec<String>,
+    pub state: InvoiceEscrowState,
+}
+
+/// The states of the funding of an escrow through a Lightning invoice
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Decodable, Encodable)]
+pub enum InvoiceEscrowState {
+    /// waiting for the invoice to be paid
+    AwaitingPayment,
+    /// the transaction locking the received ecash in the escrow was submitted
+    Funding { txid: TransactionId },
+    /// the escrow was created
+    Funded,
+    /// the escrow could not be created, the ecash received through the invoice,
+    /// if it was paid, stays in the wallet of the buyer
+    Failed { error: String, paid: bool },
+}
+
+impl InvoiceEscrowStateMachine {
+    fn failed(self, error: anyhow::Error, paid: bool) -> Self {
+        warn!(
+            "Failed to fund escrow {} through an invoice: {:?}",
+            self.new_escrow.escrow_id, error
+        );
+        InvoiceEscrowStateMachine {
+            state: InvoiceEscrowState::Failed {
+                error: error.to_string(),
+                paid,
+            },
+            ..self
+        }
+    }
+}
+
+/// Pays an invoice with the ecash claimed from an escrow. The transaction of
+/// the claim funds the outgoing contract of the payment, which the gateway
+/// claims once it paid the invoice.
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Decodable, Encodable)]
+pub struct InvoicePayoutStateMachine {
+    /// the operation of the claim
+    pub operation_id: OperationId,
+    pub escrow_id: String,
+    /// the role of this client in the escrow
+    pub role: EscrowRole,
+    /// the contract locking the invoice amount and the fee of the gateway
+    pub contract: OutgoingContractAccount,
+    pub invoice: Bolt11Invoice,
+    pub gateway_api: SafeUrl,
+    pub preimage_auth: sha256::Hash,
+    pub state: InvoicePayoutState,
+}
+
+/// The states of the payout of a claim to a Lightning invoice
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Decodable, Encodable)]
+pub enum InvoicePayoutState {
+    /// the transaction of the claim funding the contract was submitted
+    Funding { txid: TransactionId },
+    /// the gateway was asked to pay the invoice
+    Paying,
+    /// the invoice was paid
+    Paid { preimage: String },
+    /// the gateway did not pay the invoice, the contract is refunded once its
+    /// timelock expired
+    AwaitingRefund { error: String },
+    /// the ecash of the contract is back in the wallet
+    Refunded { error: String },
+    /// the claim was rejected, or the contract could not be refunded
+    Failed { error: String },
+}
+
+/// Follows the peg-out of the ecash claimed from an escrow, the transaction of
+/// the claim carries the peg-out output
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Decodable, Encodable)]
+pub struct PegOutStateMachine {
+    /// the operation of the claim
+    pub operation_id: OperationId,
+    /// the peg-out output of the transaction of the claim
+    pub out_point: OutPoint,
+    pub state: PegOutState,
+}
+
+/// The states of the peg-out of a claim
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Decodable, Encodable)]
+pub enum PegOutState {
+    /// the transaction of the claim was submitted
+    Created,
+    /// the federation broadcast the on-chain transaction of the peg-out
+    Succeeded { txid: bitcoin::Txid },
+    /// the claim was rejected or the peg-out failed
+    Failed { error: String },
 }
 
 /// Data needed by the state machine as context
 #[derive(Debug, Clone)]
 pub struct EscrowClientContext {
     pub escrow_decoder: Decoder,
+    pub cfg: EscrowClientConfig,
+    /// the key of this client, the buyer of the escrows it funds and the
+    /// owner of the contracts of its payouts
+    pub key: KeyPair,
+    /// the client, to follow the invoices funding escrows and to refund the
+    /// payouts
+    pub client_ctx: ClientContext<EscrowClientModule>,
 }
 
-// escrow module doesn't need local context
 impl Context for EscrowClientContext {}
 
+impl State for EscrowClientStateMachines {
+    type ModuleContext = EscrowClientContext;
+
+    fn transitions(
+        &self,
+        context: &Self::ModuleContext,
+        global_context: &DynGlobalClientContext,
+    ) -> Vec<StateTransition<Self>> {
+        match self {
+            EscrowClientStateMachines::Watcher(state_machine) => sm_enum_variant_translation!(
+                state_machine.transitions(context, global_context),
+                EscrowClientStateMachines::Watcher
+            ),
+            EscrowClientStateMachines::InvoiceFunding(state_machine) => {
+                sm_enum_variant_translation!(
+                    state_machine.transitions(context, global_context),
+                    EscrowClientStateMachines::InvoiceFunding
+                )
+            }
+            EscrowClientStateMachines::InvoicePayout(state_machine) => {
+                sm_enum_variant_translation!(
+                    state_machine.transitions(context, global_context),
+                    EscrowClientStateMachines::InvoicePayout
+                )
+            }
+            EscrowClientStateMachines::PegOut(state_machine) => sm_enum_variant_translation!(
+                state_machine.transitions(context, global_context),
+                EscrowClientStateMachines::PegOut
+            ),
+        }
+    }
+
+    fn operation_id(&self) -> OperationId {
+        match self {
+            EscrowClientStateMachines::Watcher(state_machine) => state_machine.operation_id(),
+            EscrowClientStateMachines::InvoiceFunding(state_machine) => {
+                state_machine.operation_id()
+            }
+            EscrowClientStateMachines::InvoicePayout(state_machine) => state_machine.operation_id(),
+            EscrowClientStateMachines::PegOut(state_machine) => state_machine.operation_id(),
+        }
+    }
+}
+
 impl State for EscrowStateMachine {
     type ModuleContext = EscrowClientContext;
     fn transitions(
         &self,
         _context: &Self::ModuleContext,
-        _global_context: &DynGlobalClientContext,
+        global_context: &DynGlobalClientContext,
+    ) -> Vec<StateTransition<Self>> {
+        match &self.state {
+            EscrowClientState::Watching { escrow_info } => {
+                let module_api = global_context.module_api();
+                let escrow_id = self.escrow_id.clone();
+                let escrow_info = escrow_info.clone();
+                vec![StateTransition::new(
+                    await_escrow_info(module_api, escrow_id, escrow_info),
+                    |dbtx, escrow_info, old_state: Self| {
+                        Box::pin(transition_escrow_state(dbtx, old_state, escrow_info))
+                    },
+                )]
+            }
+            // terminal state
+            EscrowClientState::Resolved { .. } => vec![],
+        }
+    }
+
+    fn operation_id(&self) -> OperationId {
+        self.operation_id
+    }
+}
+
+/// Polls the federation until the escrow differs from the last known one, be
+/// it its state, its amount, the votes of the arbiters or its withdrawals
+async fn await_escrow_info(
+    module_api: DynModuleApi,
+    escrow_id: String,
+    last_escrow_info: Option<EscrowInfo>,
+) -> EscrowInfo {
+    loop {
+        match module_api.get_escrow_info(escrow_id.clone()).await {
+            Ok(escrow_info) if Some(&escrow_info) != last_escrow_info.as_ref() => {
+                return escrow_info;
+            }
+            Ok(_) => {}
+            Err(e) => debug!("Failed to get escrow {}: {:?}", escrow_id, e),
+        }
+        fedimint_core::task::sleep(ESCROW_POLL_INTERVAL).await;
+    }
+}
+
+/// Records the new state of the escrow in the client database and moves the
+/// state machine along
+async fn transition_escrow_state(
+    dbtx: &mut ClientSMDatabaseTransaction<'_, '_>,
+    old_state: EscrowStateMachine,
+    escrow_info: EscrowInfo,
+) -> EscrowStateMachine {
+    let escrow_key = EscrowKey {
+        escrow_id: old_state.escrow_id.clone(),
+    };
+    if let Some(mut escrow_value) = dbtx.module_tx().get_value(&escrow_key).await {
+        escrow_value.amount = escrow_info.amount;
+        escrow_value.state = escrow_info.state.clone();
+        dbtx.module_tx()
+            .insert_entry(&escrow_key, &escrow_value)
+            .await;
+    }
+
+    let state = if escrow_info.state.is_resolved() {
+        EscrowClientState::Resolved { escrow_info }
+    } else {
+        EscrowClientState::Watching {
+            escrow_info: Some(escrow_info),
+        }
+    };
+    EscrowStateMachine { state, ..old_state }
+}
+
+impl State for InvoiceEscrowStateMachine {
+    type ModuleContext = EscrowClientContext;
+
+    fn transitions(
+        &self,
+        context: &Self::ModuleContext,
+        global_context: &DynGlobalClientContext,
+    ) -> Vec<StateTransition<Self>> {
+        match &self.state {
+            InvoiceEscrowState::AwaitingPayment => {
+                let context = context.clone();
+                let global_context = global_context.clone();
+                vec![StateTransition::new(
+                    await_invoice_paid(context.client_ctx.clone(), self.operation_id),
+                    move |dbtx, paid, old_state: Self| {
+                        Box::pin(fund_escrow(
+                            dbtx,
+                            context.clone(),
+                            global_context.clone(),
+                            old_state,
+                            paid,
+                        ))
+                    },
+                )]
+            }
+            InvoiceEscrowState::Funding { txid } => {
+                let global_context = global_context.clone();
+                let txid = *txid;
+                vec![StateTransition::new(
+                    async move { global_context.await_tx_accepted(txid).await },
+                    |dbtx, accepted, old_state: Self| {
+                        Box::pin(transition_funding_outcome(dbtx, old_state, accepted))
+                    },
+                )]
+            }
+            // terminal states
+            InvoiceEscrowState::Funded | InvoiceEscrowState::Failed { .. } => vec![],
+        }
+    }
+
+    fn operation_id(&self) -> OperationId {
+        self.operation_id
+    }
+}
+
+/// Waits for the invoice to be paid and its ecash to be received, false if the
+/// invoice was canceled
+async fn await_invoice_paid(
+    client_ctx: ClientContext<EscrowClientModule>,
+    operation_id: OperationId,
+) -> bool {
+    let lightning = client_ctx.get_first_module::<LightningClientModule>();
+    let mut updates = match lightning.subscribe_ln_receive(operation_id).await {
+        Ok(updates) => updates.into_stream(),
+        Err(e) => {
+            warn!("Failed to follow invoice {:?}: {:?}", operation_id, e);
+            return false;
+        }
+    };
+    while let Some(update) = updates.next().await {
+        match update {
+            LnReceiveState::Claimed => return true,
+            LnReceiveState::Canceled { reason } => {
+                warn!("Invoice {:?} canceled: {:?}", operation_id, reason);
+                return false;
+            }
+            _ => {}
+        }
+    }
+    false
+}
+
+/// Locks the ecash received through the invoice in the escrow. The invoice may
+/// have been paid long after it was created, thus the terms are checked again
+/// against the deadline and the limits of the federation.
+async fn fund_escrow(
+    dbtx: &mut ClientSMDatabaseTransaction<'_, '_>,
+    context: EscrowClientContext,
+    global_context: DynGlobalClientContext,
+    old_state: InvoiceEscrowStateMachine,
+    paid: bool,
+) -> InvoiceEscrowStateMachine {
+    let invoice_escrow_key = InvoiceEscrowKey {
+        operation_id: old_state.operation_id,
+    };
+    let new_escrow = &old_state.new_escrow;
+    let checked = if !paid {
+        Err(anyhow::anyhow!(EscrowError::InvoiceNotPaid))
+    } else if new_escrow.deadline <= unix_time_now() {
+        Err(anyhow::anyhow!(EscrowError::DeadlineInPast))
+    } else {
+        check_new_escrow(&context.cfg, &context.key.public_key(), new_escrow)
+    };
+    if let Err(e) = checked {
+        dbtx.module_tx().remove_entry(&invoice_escrow_key).await;
+        return old_state.failed(e, paid);
+    }
+
+    // the secret codes are stored before the escrow is created so that they cannot
+    // get lost
+    let output = new_escrow.to_output(context.key.public_key(), &old_state.secret_codes);
+    let escrow_key = EscrowKey {
+        escrow_id: new_escrow.escrow_id.clone(),
+    };
+    dbtx.module_tx()
+        .insert_entry(
+            &escrow_key,
+            &EscrowValue::of_buyer(&output, old_state.secret_codes.clone()),
+        )
+        .await;
+
+    let client_output = ClientOutput {
+        output,
+        state_machines: Arc::new(|_: TransactionId, _: u64| {
+            Vec::<EscrowClientStateMachines>::new()
+        }),
+    };
+    match global_context.fund_output(dbtx, client_output).await {
+        Ok((txid, _change)) => InvoiceEscrowStateMachine {
+            state: InvoiceEscrowState::Funding { txid },
+            ..old_state
+        },
+        Err(e) => {
+            dbtx.module_tx().remove_entry(&escrow_key).await;
+            dbtx.module_tx().remove_entry(&invoice_escrow_key).await;
+            old_state.failed(e, true)
+        }
+    }
+}
+
+/// Records whether the federation accepted the transaction creating the
+/// escrow, the terms waiting for the invoice are kept until then
+async fn transition_funding_outcome(
+    dbtx: &mut ClientSMDatabaseTransaction<'_, '_>,
+    old_state: InvoiceEscrowStateMachine,
+    accepted: Result<(), String>,
+) -> InvoiceEscrowStateMachine {
+    dbtx.module_tx()
+        .remove_entry(&InvoiceEscrowKey {
+            operation_id: old_state.operation_id,
+        })
+        .await;
+    match accepted {
+        Ok(()) => InvoiceEscrowStateMachine {
+            state: InvoiceEscrowState::Funded,
+            ..old_state
+        },
+        Err(e) => {
+            // the escrow was never created
+            dbtx.module_tx()
+                .remove_entry(&EscrowKey {
+                    escrow_id: old_state.new_escrow.escrow_id.clone(),
+                })
+                .await;
+            old_state.failed(
+                anyhow::anyhow!("{}: {}", EscrowError::TransactionRejected, e),
+                true,
+            )
+        }
+    }
+}
+
+impl InvoicePayoutStateMachine {
+    fn with_state(self, state: InvoicePayoutState) -> Self {
+        InvoicePayoutStateMachine { state, ..self }
+    }
+}
+
+impl State for InvoicePayoutStateMachine {
+    type ModuleContext = EscrowClientContext;
+
+    fn transitions(
+        &self,
+        context: &Self::ModuleContext,
+        global_context: &DynGlobalClientContext,
+    ) -> Vec<StateTransition<Self>> {
+        match &self.state {
+            InvoicePayoutState::Funding { txid } => {
+                let global_context = global_context.clone();
+                let txid = *txid;
+                vec![StateTransition::new(
+                    async move { global_context.await_tx_accepted(txid).await },
+                    |_dbtx, accepted, old_state: Self| {
+                        Box::pin(async move {
+                            match accepted {
+                                Ok(()) => old_state.with_state(InvoicePayoutState::Paying),
+                                Err(e) => old_state.with_state(InvoicePayoutState::Failed {
+                                    error: format!("{}: {}", EscrowError::TransactionRejected, e),
+                                }),
+                            }
+                        })
+                    },
+                )]
+            }
+            InvoicePayoutState::Paying => {
+                let federation_id = global_context
+                    .client_config()
+                    .global
+                    .calculate_federation_id();
+                vec![StateTransition::new(
+                    gateway_pay_invoice(federation_id, self.clone()),
+                    |_dbtx, paid, old_state: Self| {
+                        Box::pin(async move {
+                            match paid {
+                                Ok(preimage) => {
+                                    old_state.with_state(InvoicePayoutState::Paid { preimage })
+                                }
+                                Err(error) => {
+                                    warn!(
+                                        "Gateway failed to pay out the claim of escrow {}: {}",
+                                        old_state.escrow_id, error
+                                    );
+                                    old_state
+                                        .with_state(InvoicePayoutState::AwaitingRefund { error })
+                                }
+                            }
+                        })
+                    },
+                )]
+            }
+            InvoicePayoutState::AwaitingRefund { error } => {
+                let error = error.clone();
+                vec![StateTransition::new(
+                    refund_contract(context.clone(), self.clone()),
+                    move |_dbtx, refunded, old_state: Self| {
+                        let error = error.clone();
+                        Box::pin(async move {
+                            match refunded {
+                                Ok(()) => {
+                                    old_state.with_state(InvoicePayoutState::Refunded { error })
+                                }
+                                Err(e) => old_state.with_state(InvoicePayoutState::Failed {
+                                    error: format!("{error}, refund failed: {e}"),
+                                }),
+                            }
+                        })
+                    },
+                )]
+            }
+            // terminal states
+            InvoicePayoutState::Paid { .. }
+            | InvoicePayoutState::Refunded { .. }
+            | InvoicePayoutState::Failed { .. } => vec![],
+        }
+    }
+
+    fn operation_id(&self) -> OperationId {
+        self.operation_id
+    }
+}
+
+/// Asks the gateway to pay the invoice with the funded contract, returns the
+/// preimage of the invoice
+async fn gateway_pay_invoice(
+    federation_id: FederationId,
+    payout: InvoicePayoutStateMachine,
+) -> Result<String, String> {
+    let payload = PayInvoicePayload::new(
+        federation_id,
+        payout.contract.contract.contract_id(),
+        PaymentData::Invoice(payout.invoice),
+        payout.preimage_auth,
+    );
+    let url = payout
+        .gateway_api
+        .join("pay_invoice")
+        .map_err(|e| e.to_string())?;
+    let response = reqwest::Client::new()
+        .post(url.as_str())
+        .json(&payload)
+        .send()
+        .await
+        .map_err(|e| e.to_string())?;
+    if !response.status().is_success() {
+        return Err(response
+            .text()
+            .await
+            .unwrap_or_else(|e| format!("Gateway error: {e}")));
+    }
+    response.json::<String>().await.map_err(|e| e.to_string())
+}
+
+/// Takes the ecash of a contract the gateway did not pay back to the wallet
+/// once the timelock of the contract expired
+async fn refund_contract(
+    context: EscrowClientContext,
+    payout: InvoicePayoutStateMachine,
+) -> Result<(), String> {
+    let lightning = context
+        .client_ctx
+        .get_first_module::<LightningClientModule>();
+    let timelock = u64::from(payout.contract.contract.timelock);
+    loop {
+        match lightning.api.fetch_consensus_block_count().await {
+            Ok(Some(block_count)) if block_count >= timelock => break,
+            Ok(_) => {}
+            Err(e) => debug!("Failed to get the consensus block count: {:?}", e),
+        }
+        fedimint_core::task::sleep(ESCROW_POLL_INTERVAL).await;
+    }
+
+    let operation_id = OperationId(thread_rng().gen());
+    let refund = ClientInput {
+        input: DynInput::from_typed(lightning.id, payout.contract.refund()),
+        keys: vec![context.key],
+        state_machines: Arc::new(|_: TransactionId, _: u64| Vec::<DynState>::new()),
+    };
+    let operation_meta = operation_meta_fn(
+        payout.escrow_id.clone(),
+        EscrowOperation::RefundPayout,
+        payout.role,
+        payout.contract.amount,
+        None,
+    );
+    let (txid, _change) = context
+        .client_ctx
+        .finalize_and_submit_transaction(
+            operation_id,
+            KIND.as_str(),
+            operation_meta,
+            TransactionBuilder::new().with_input(refund),
+        )
+        .await
+        .map_err(|e| e.to_string())?;
+    context
+        .client_ctx
+        .transaction_updates(operation_id)
+        .await
+        .await_tx_accepted(txid)
+        .await
+}
+
+impl State for PegOutStateMachine {
+    type ModuleContext = EscrowClientContext;
+
+    fn transitions(
+        &self,
+        _context: &Self::ModuleContext,
+        global_context: &DynGlobalClientContext,
     ) -> Vec<StateTransition<Self>> {
-        // transition to the same state on the client side
-        vec![StateTransition::new(
-            async { () },
-            |_db_tx, _, old_state: Self| Box::pin(async move { old_state }),
-        )]
+        match &self.state {
+            PegOutState::Created => vec![StateTransition::new(
+                await_peg_out(global_context.clone(), self.out_point),
+                |_dbtx, peg_out, old_state: Self| {
+                    Box::pin(async move {
+                        let state = match peg_out {
+                            Ok(txid) => PegOutState::Succeeded { txid },
+                            Err(error) => {
+                                warn!("Peg-out of claim failed: {}", error);
+                                PegOutState::Failed { error }
+                            }
+                        };
+                        PegOutStateMachine { state, ..old_state }
+                    })
+                },
+            )],
+            // terminal states
+            PegOutState::Succeeded { .. } | PegOutState::Failed { .. } => vec![],
+        }
     }
 
     fn operation_id(&self) -> OperationId {
-        self.operation_id.clone()
+        self.operation_id
+    }
+}
+
+/// Waits for the federation to accept the claim and to broadcast the on-chain
+/// transaction of its peg-out, returns the id of the on-chain transaction
+async fn await_peg_out(
+    global_context: DynGlobalClientContext,
+    out_point: OutPoint,
+) -> Result<bitcoin::Txid, String> {
+    global_context
+        .await_tx_accepted(out_point.txid)
+        .await
+        .map_err(|e| format!("{}: {}", EscrowError::TransactionRejected, e))?;
+    let outcome: WalletOutputOutcome = global_context
+        .api()
+        .await_output_outcome(
+            out_point,
+            Duration::from_secs(u64::from(u32::MAX)),
+            &WalletModuleTypes::decoder(),
+        )
+        .await
+        .map_err(|e| e.to_string())?;
+    match outcome.ensure_v0_ref() {
+        Ok(WalletOutputOutcomeV0::PegOut(txid)) => Ok(*txid),
+        _ => Err(EscrowError::PegOutFailed.to_string()),
     }
 }
 
-impl IntoDynInstance for EscrowStateMachine {
+impl IntoDynInstance for EscrowClientStateMachines {
     type DynType = DynState;
 
     fn into_dyn(self, instance_id: ModuleInstanceId) -> Self::DynType {
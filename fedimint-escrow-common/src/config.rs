@@ -3,7 +3,14 @@ use fedimint_core::encoding::{Decodable, Encodable};
 use fedimint_core::{plugin_types_trait_impl_config, Amount};
 use serde::{Deserialize, Serialize};
 
-use crate::EscrowCommonInit;
+use crate::{bps_of, EscrowCommonInit, MAX_BPS};
+
+/// The lowest maximum arbiter fee the buyer can offer, 10 bps (0.1%)
+pub const MIN_ARBITER_FEE_BPS: u16 = 10;
+
+/// The length of the escrow ids generated by the client, which the federation
+/// has to allow
+pub const ESCROW_ID_LENGTH: u32 = 32;
 
 /// Parameters necessary to generate this module's configuration
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -20,7 +27,18 @@ pub struct EscrowGenParamsLocal;
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct EscrowGenParamsConsensus {
     pub deposit_fee: Amount,
+    /// the highest maximum arbiter fee the buyer can offer
     pub max_arbiter_fee_bps: u16,
+    /// the smallest amount that can be locked in an escrow
+    pub min_escrow_amount: Amount,
+    /// the largest amount that can be locked in an escrow
+    pub max_escrow_amount: Amount,
+    /// the number of escrows a buyer key can have unresolved at once
+    pub max_open_escrows_per_buyer: u64,
+    /// the longest escrow id in bytes
+    pub max_escrow_id_length: u32,
+    /// the shortest time the buyer can give the arbiters to decide a dispute
+    pub min_arbitration_timeout_secs: u64,
 }
 
 impl Default for EscrowGenParams {
@@ -29,12 +47,34 @@ impl Default for EscrowGenParams {
             local: EscrowGenParamsLocal,
             consensus: EscrowGenParamsConsensus {
                 deposit_fee: Amount::ZERO,
-                max_arbiter_fee_bps: 0,
+                max_arbiter_fee_bps: 1000,
+                min_escrow_amount: Amount::from_sats(1),
+                max_escrow_amount: Amount::from_sats(10_000_000),
+                max_open_escrows_per_buyer: 100,
+                max_escrow_id_length: 64,
+                min_arbitration_timeout_secs: 1,
             },
         }
     }
 }
 
+impl EscrowGenParamsConsensus {
+    /// The consensus config generated from the params, for the escrow module
+    /// of the federation identified by `signing_domain`
+    pub fn to_config(&self, signing_domain: [u8; 32]) -> EscrowConfigConsensus {
+        EscrowConfigConsensus {
+            deposit_fee: self.deposit_fee,
+            max_arbiter_fee_bps: self.max_arbiter_fee_bps,
+            min_escrow_amount: self.min_escrow_amount,
+            max_escrow_amount: self.max_escrow_amount,
+            max_open_escrows_per_buyer: self.max_open_escrows_per_buyer,
+            max_escrow_id_length: self.max_escrow_id_length,
+            min_arbitration_timeout_secs: self.min_arbitration_timeout_secs,
+            signing_domain,
+        }
+    }
+}
+
 /// Contains all the configuration for the server
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct EscrowConfig {
@@ -49,6 +89,13 @@ pub struct EscrowClientConfig {
     /// Accessible to clients
     pub deposit_fee: Amount,
     pub max_arbiter_fee_bps: u16,
+    pub min_escrow_amount: Amount,
+    pub max_escrow_amount: Amount,
+    pub max_escrow_id_length: u32,
+    pub min_arbitration_timeout_secs: u64,
+    /// The id of the escrow module of the federation the signed escrow
+    /// actions commit to
+    pub signing_domain: [u8; 32],
 }
 
 impl EscrowClientConfig {
@@ -56,13 +103,48 @@ impl EscrowClientConfig {
         &self,
         max_arbiter_fee_bps: u16,
     ) -> Result<u16, anyhow::Error> {
-        // the max_arbiter_fee_bps should be in range 10 (0.1%) to 1000 (10%)
-        if max_arbiter_fee_bps < 10 || max_arbiter_fee_bps > 1000 {
-            Err(anyhow::anyhow!("max_arbiter_fee_bps is out of bounds"))
+        // the max_arbiter_fee_bps should be in range 10 (0.1%) to the maximum of the
+        // federation
+        if max_arbiter_fee_bps < MIN_ARBITER_FEE_BPS
+            || max_arbiter_fee_bps > self.max_arbiter_fee_bps
+        {
+            Err(anyhow::anyhow!(
+                "max_arbiter_fee_bps is out of bounds, it should be between {} and {}",
+                MIN_ARBITER_FEE_BPS,
+                self.max_arbiter_fee_bps
+            ))
         } else {
             Ok(max_arbiter_fee_bps)
         }
     }
+
+    pub fn limit_escrow_amount(&self, amount: Amount) -> Result<Amount, anyhow::Error> {
+        // the federation only locks amounts within its limits in an escrow
+        if amount < self.min_escrow_amount || amount > self.max_escrow_amount {
+            Err(anyhow::anyhow!(
+                "escrow amount is out of bounds, it should be between {} and {}",
+                self.min_escrow_amount,
+                self.max_escrow_amount
+            ))
+        } else {
+            Ok(amount)
+        }
+    }
+
+    pub fn limit_arbitration_timeout_secs(
+        &self,
+        arbitration_timeout_secs: u64,
+    ) -> Result<u64, anyhow::Error> {
+        // the arbiters get at least the time the federation requires to decide
+        if arbitration_timeout_secs < self.min_arbitration_timeout_secs {
+            Err(anyhow::anyhow!(
+                "arbitration timeout is too short, it should be at least {} seconds",
+                self.min_arbitration_timeout_secs
+            ))
+        } else {
+            Ok(arbitration_timeout_secs)
+        }
+    }
 }
 
 /// Locally unencrypted config unique to each member
@@ -70,11 +152,75 @@ impl EscrowClientConfig {
 pub struct EscrowConfigLocal;
 
 /// Will be the same for every federation member
-#[derive(Clone, Debug, Serialize, Deserialize, Decodable, Encodable)]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, Decodable, Encodable)]
 pub struct EscrowConfigConsensus {
     /// Will be the same for all peers
     pub deposit_fee: Amount,
     pub max_arbiter_fee_bps: u16,
+    pub min_escrow_amount: Amount,
+    pub max_escrow_amount: Amount,
+    pub max_open_escrows_per_buyer: u64,
+    pub max_escrow_id_length: u32,
+    pub min_arbitration_timeout_secs: u64,
+    /// Random id of the escrow module of this federation, agreed upon by the
+    /// guardians, which every signed escrow action commits to
+    pub signing_domain: [u8; 32],
+}
+
+impl EscrowConfigConsensus {
+    /// The highest maximum arbiter fee the buyer can offer for the amount
+    pub fn max_arbiter_fee(&self, amount: Amount) -> Amount {
+        bps_of(amount, self.max_arbiter_fee_bps)
+    }
+
+    /// Checks that the limits of the federation can be met by an escrow
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if self.max_arbiter_fee_bps < MIN_ARBITER_FEE_BPS || self.max_arbiter_fee_bps > MAX_BPS {
+            return Err(anyhow::anyhow!(
+                "max_arbiter_fee_bps {} is out of bounds, it should be between {} and {}",
+                self.max_arbiter_fee_bps,
+                MIN_ARBITER_FEE_BPS,
+                MAX_BPS
+            ));
+        }
+        if self.min_escrow_amount == Amount::ZERO {
+            return Err(anyhow::anyhow!("min_escrow_amount should not be zero"));
+        }
+        if self.min_escrow_amount > self.max_escrow_amount {
+            return Err(anyhow::anyhow!(
+                "min_escrow_amount {} is above max_escrow_amount {}",
+                self.min_escrow_amount,
+                self.max_escrow_amount
+            ));
+        }
+        // the deposit fee is paid on top of the escrow, it should not cost more than
+        // the smallest escrow locks
+        if self.deposit_fee > self.min_escrow_amount {
+            return Err(anyhow::anyhow!(
+                "deposit_fee {} is above min_escrow_amount {}",
+                self.deposit_fee,
+                self.min_escrow_amount
+            ));
+        }
+        if self.max_open_escrows_per_buyer == 0 {
+            return Err(anyhow::anyhow!(
+                "max_open_escrows_per_buyer should not be zero"
+            ));
+        }
+        if self.max_escrow_id_length < ESCROW_ID_LENGTH {
+            return Err(anyhow::anyhow!(
+                "max_escrow_id_length {} is below the length {} of the generated escrow ids",
+                self.max_escrow_id_length,
+                ESCROW_ID_LENGTH
+            ));
+        }
+        if self.min_arbitration_timeout_secs == 0 {
+            return Err(anyhow::anyhow!(
+                "min_arbitration_timeout_secs should not be zero"
+            ));
+        }
+        Ok(())
+    }
 }
 
 /// Will be encrypted and not shared such as private key material
@@ -93,3 +239,87 @@ plugin_types_trait_impl_config!(
     EscrowConfigConsensus,
     EscrowClientConfig
 );
+
+#[cfg(test)]
+mod tests {
+    use fedimint_core::Amount;
+
+    use super::{EscrowConfigConsensus, EscrowGenParams};
+
+    fn consensus() -> EscrowConfigConsensus {
+        EscrowGenParams::default().consensus.to_config([0; 32])
+    }
+
+    #[test]
+    fn default_consensus_config_is_valid() {
+        consensus().validate().expect("default config is valid");
+    }
+
+    #[test]
+    fn consensus_config_limits_have_to_be_reachable() {
+        let invalid_configs = [
+            EscrowConfigConsensus {
+                max_arbiter_fee_bps: 9,
+                ..consensus()
+            },
+            EscrowConfigConsensus {
+                max_arbiter_fee_bps: 10_001,
+                ..consensus()
+            },
+            EscrowConfigConsensus {
+                min_escrow_amount: Amount::ZERO,
+                ..consensus()
+            },
+            EscrowConfigConsensus {
+                min_escrow_amount: Amount::from_sats(2_000),
+                max_escrow_amount: Amount::from_sats(1_000),
+                ..consensus()
+            },
+            EscrowConfigConsensus {
+                deposit_fee: Amount::from_sats(2),
+                min_escrow_amount: Amount::from_sats(1),
+                ..consensus()
+            },
+            EscrowConfigConsensus {
+                max_open_escrows_per_buyer: 0,
+                ..consensus()
+            },
+            EscrowConfigConsensus {
+                max_escrow_id_length: 31,
+                ..consensus()
+            },
+            EscrowConfigConsensus {
+                min_arbitration_timeout_secs: 0,
+                ..consensus()
+            },
+        ];
+        for config in invalid_configs {
+            assert!(config.validate().is_err(), "{config:?} should be invalid");
+        }
+
+        // the bounds themselves are valid
+        let bounds = [
+            EscrowConfigConsensus {
+                max_arbiter_fee_bps: 10,
+                ..consensus()
+            },
+            EscrowConfigConsensus {
+                max_arbiter_fee_bps: 10_000,
+                ..consensus()
+            },
+            EscrowConfigConsensus {
+                min_escrow_amount: Amount::from_sats(1_000),
+                max_escrow_amount: Amount::from_sats(1_000),
+                deposit_fee: Amount::from_sats(1_000),
+                ..consensus()
+            },
+            EscrowConfigConsensus {
+                max_escrow_id_length: 32,
+                ..consensus()
+            },
+        ];
+        for config in bounds {
+            config.validate().expect("config at the bounds is valid");
+        }
+    }
+}
@@ -1,20 +1,289 @@
+use fedimint_core::encoding::{Decodable, Encodable};
 use fedimint_core::Amount;
 use secp256k1::PublicKey;
 use serde::{Deserialize, Serialize};
 
-use super::EscrowStates;
+use super::{
+    ArbiterDecision, ArbiterVote, Disputer, EncryptedEvidence, EscrowMilestone, EscrowRole,
+    EscrowStates, EscrowTerms, FallbackOutcome, HashLock, Milestone, MilestoneState,
+    ACCEPTANCE_WINDOW_SECS, APPEAL_WINDOW_SECS,
+};
 
 /// get escrow information in the client side
 pub const GET_MODULE_INFO: &str = "get_module_info";
+/// list the escrows a public key takes part in
+pub const LIST_ESCROWS_BY_PUBKEY: &str = "list_escrows_by_pubkey";
+/// list the disputes waiting for the decision of an arbiter
+pub const ARBITER_INBOX: &str = "arbiter_inbox";
+/// get the encrypted evidence attached to the dispute of an escrow
+pub const GET_DISPUTE_EVIDENCE: &str = "get_dispute_evidence";
+
+/// The maximum number of escrows returned by a single LIST_ESCROWS_BY_PUBKEY
+/// request
+pub const MAX_LIST_ESCROWS_LIMIT: u64 = 100;
 
 /// EscrowInfo is the response to the GET_MODULE_INFO request
-#[derive(Debug, Serialize, Deserialize, Clone, Eq, PartialEq)]
+#[derive(Debug, Serialize, Deserialize, Clone, Eq, PartialEq, Hash, Encodable, Decodable)]
 pub struct EscrowInfo {
     pub buyer_pubkey: PublicKey,
     pub seller_pubkey: PublicKey,
-    pub arbiter_pubkey: PublicKey,
+    pub arbiter_pubkeys: Vec<PublicKey>,
+    pub arbiter_threshold: u32,
+    /// the ecash left in the escrow, which excludes the released milestones
+    /// and the arbiter fee
     pub amount: Amount,
-    pub secret_code_hash: String,
+    pub milestones: Vec<EscrowMilestone>,
     pub state: EscrowStates,
     pub max_arbiter_fee: Amount,
+    /// the consensus unix time at which the escrow was created
+    pub created_at: u64,
+    pub deadline: u64,
+    /// the seconds after the dispute within which the arbiters have to decide
+    pub arbitration_timeout_secs: u64,
+    /// the votes of the arbiters of the panel on the dispute
+    pub arbiter_votes: Vec<ArbiterVote>,
+    /// the decision of the panel
+    pub arbiter_decision: Option<ArbiterDecision>,
+    /// the consensus unix time at which the panel decided
+    pub decided_at: Option<u64>,
+    /// the vote that decided the dispute, the arbiters voting for the same
+    /// decision and fee share the arbiter fee
+    pub decided_vote: Option<ArbiterVote>,
+    pub appeal_arbiter: Option<PublicKey>,
+    pub appealed_by: Option<Disputer>,
+    /// the decision of the appeal arbiter, superseding the one of the panel
+    pub appeal_decision: Option<ArbiterDecision>,
+    /// the consensus unix time after which the fallback outcome applies, if
+    /// the escrow is disputed
+    pub arbitration_deadline: Option<u64>,
+    /// the consensus unix time after which the decision of the panel stands,
+    /// if the escrow is appealed
+    pub appeal_decision_deadline: Option<u64>,
+    pub fallback: FallbackOutcome,
+    pub hash_lock: HashLock,
+    /// whether the fallback outcome was applied as the panel did not decide
+    /// in time
+    pub fallback_applied: bool,
+    /// the ecash released by the buyer that the seller has not claimed yet
+    pub released_unclaimed: Amount,
+    pub buyer_withdrawn: Amount,
+    pub seller_withdrawn: Amount,
+}
+
+/// ListEscrowsRequest is the request to the LIST_ESCROWS_BY_PUBKEY endpoint
+#[derive(Debug, Serialize, Deserialize, Clone, Eq, PartialEq)]
+pub struct ListEscrowsRequest {
+    pub pubkey: PublicKey,
+    /// only list the escrows the public key has this role in
+    pub role: Option<EscrowRole>,
+    /// the number of escrows to skip
+    pub offset: u64,
+    /// the maximum number of escrows to return, capped at
+    /// MAX_LIST_ESCROWS_LIMIT
+    pub limit: u64,
+}
+
+/// ListEscrowsResponse is the response to the LIST_ESCROWS_BY_PUBKEY request
+#[derive(Debug, Serialize, Deserialize, Clone, Eq, PartialEq)]
+pub struct ListEscrowsResponse {
+    pub escrows: Vec<EscrowSummary>,
+    /// the offset of the next page, if there are more escrows
+    pub next_offset: Option<u64>,
+}
+
+/// The summary of an escrow a public key takes part in
+#[derive(Debug, Serialize, Deserialize, Clone, Eq, PartialEq)]
+pub struct EscrowSummary {
+    pub escrow_id: String,
+    pub role: EscrowRole,
+    pub amount: Amount,
+    pub state: EscrowStates,
+    pub deadline: u64,
+}
+
+/// A dispute waiting for the decision of the arbiter, the response to the
+/// ARBITER_INBOX request is a list of them with the oldest dispute first
+#[derive(Debug, Serialize, Deserialize, Clone, Eq, PartialEq)]
+pub struct DisputeSummary {
+    pub escrow_id: String,
+    /// the ecash of the unreleased milestones the dispute is about
+    pub amount: Amount,
+    /// the fee the arbiter can still charge
+    pub max_arbiter_fee: Amount,
+    /// the party who opened the dispute, or appealed it
+    pub disputer: Disputer,
+    /// the consensus unix time at which the dispute was opened, or appealed
+    pub disputed_at: u64,
+    /// whether the dispute waits for the appeal arbiter
+    pub appeal: bool,
+    /// the consensus unix time after which the fallback outcome applies, or
+    /// the decision of the panel stands for appeals
+    pub arbitration_deadline: Option<u64>,
+}
+
+/// The evidence a party attached to the dispute, the response to the
+/// GET_DISPUTE_EVIDENCE request is a list of them
+#[derive(Debug, Serialize, Deserialize, Clone, Eq, PartialEq, Encodable, Decodable)]
+pub struct DisputeEvidence {
+    /// the party who attached the evidence, either the disputer or its
+    /// counterparty responding to the dispute
+    pub party: Disputer,
+    /// the consensus unix time at which the evidence was attached
+    pub submitted_at: u64,
+    pub evidence: EncryptedEvidence,
+}
+
+impl EscrowInfo {
+    /// The terms the seller agrees to by accepting the escrow
+    pub fn terms(&self) -> EscrowTerms {
+        EscrowTerms {
+            amount: self.amount,
+            buyer_pubkey: self.buyer_pubkey,
+            seller_pubkey: self.seller_pubkey,
+            arbiter_pubkeys: self.arbiter_pubkeys.clone(),
+            arbiter_threshold: self.arbiter_threshold,
+            appeal_arbiter: self.appeal_arbiter,
+            milestones: self
+                .milestones
+                .iter()
+                .map(|milestone| Milestone {
+                    amount: milestone.amount,
+                    secret_code_hash: milestone.secret_code_hash.clone(),
+                })
+                .collect(),
+            max_arbiter_fee: self.max_arbiter_fee,
+            deadline: self.deadline,
+            arbitration_timeout_secs: self.arbitration_timeout_secs,
+            fallback: self.fallback,
+            hash_lock: self.hash_lock,
+        }
+    }
+
+    /// The unix time after which the buyer can withdraw the escrow if the
+    /// seller has not accepted it, at the latest its deadline
+    pub fn acceptance_deadline(&self) -> u64 {
+        self.created_at
+            .saturating_add(ACCEPTANCE_WINDOW_SECS)
+            .min(self.deadline)
+    }
+
+    /// The role of the public key in the escrow, if it takes part in it
+    pub fn role_of(&self, pubkey: &PublicKey) -> Option<EscrowRole> {
+        if pubkey == &self.buyer_pubkey {
+            Some(EscrowRole::Buyer)
+        } else if pubkey == &self.seller_pubkey {
+            Some(EscrowRole::Seller)
+        } else if self.arbiter_pubkeys.contains(pubkey)
+            || self.appeal_arbiter.as_ref() == Some(pubkey)
+        {
+            Some(EscrowRole::Arbiter)
+        } else {
+            None
+        }
+    }
+    /// The vote of the arbiter on the dispute, if it has voted
+    pub fn arbiter_vote(&self, arbiter: &PublicKey) -> Option<&ArbiterVote> {
+        self.arbiter_votes
+            .iter()
+            .find(|arbiter_vote| &arbiter_vote.arbiter == arbiter)
+    }
+
+    /// The number of arbiters that voted for the same decision and fee as the
+    /// vote
+    pub fn agreeing_votes(&self, vote: &ArbiterVote) -> usize {
+        self.arbiter_votes
+            .iter()
+            .filter(|arbiter_vote| arbiter_vote.agrees_with(vote))
+            .count()
+    }
+
+    /// The index and the milestone that can still be claimed with the secret
+    /// code
+    pub fn pending_milestone(&self, secret_code: &str) -> Option<(u32, &EscrowMilestone)> {
+        let secret_code_hash = self.hash_lock.hash(secret_code)?;
+        self.milestones
+            .iter()
+            .enumerate()
+            .find(|(_, milestone)| {
+                milestone.state == MilestoneState::Pending
+                    && milestone.secret_code_hash == secret_code_hash
+            })
+            .map(|(index, milestone)| (index as u32, milestone))
+    }
+
+    /// The decision in force, the one of the appeal arbiter if the decision of
+    /// the panel was appealed
+    pub fn decision(&self) -> Option<&ArbiterDecision> {
+        self.appeal_decision
+            .as_ref()
+            .or(self.arbiter_decision.as_ref())
+    }
+
+    /// The unix time until which the decision of the panel can be appealed, if
+    /// the escrow has an appeal arbiter and the panel decided
+    pub fn appeal_deadline(&self) -> Option<u64> {
+        self.appeal_arbiter?;
+        self.decided_at
+            .map(|decided_at| decided_at + APPEAL_WINDOW_SECS)
+    }
+
+    /// Whether the panel did not decide the dispute before the arbitration
+    /// deadline, judged by the unix time `now`
+    pub fn is_arbitration_timed_out(&self, now: u64) -> bool {
+        self.fallback_applied
+            || (self.state.is_disputed()
+                && self
+                    .arbitration_deadline
+                    .is_some_and(|arbitration_deadline| now >= arbitration_deadline))
+    }
+
+    /// The decision in force at the unix time `now`, the fallback outcome if
+    /// the panel did not decide in time
+    pub fn decision_at(&self, now: u64) -> Option<ArbiterDecision> {
+        self.decision().cloned().or_else(|| {
+            self.is_arbitration_timed_out(now)
+                .then(|| self.fallback.decision())
+        })
+    }
+
+    /// Whether the appeal arbiter did not decide the appeal before the appeal
+    /// deadline, judged by the unix time `now`
+    pub fn is_appeal_timed_out(&self, now: u64) -> bool {
+        self.state.is_appealed()
+            && self
+                .appeal_decision_deadline
+                .is_some_and(|appeal_decision_deadline| now >= appeal_decision_deadline)
+    }
+
+    /// The state of the escrow at the unix time `now`, waiting for the claims
+    /// of the fallback outcome once the panel did not decide in time, or of
+    /// the decision of the panel once the appeal arbiter did not decide in
+    /// time
+    pub fn state_at(&self, now: u64) -> EscrowStates {
+        if self.state.is_disputed() && self.is_arbitration_timed_out(now) {
+            self.fallback.decision().claim_state()
+        } else if self.is_appeal_timed_out(now) {
+            self.arbiter_decision
+                .as_ref()
+                .map_or(self.state.clone(), ArbiterDecision::claim_state)
+        } else {
+            self.state.clone()
+        }
+    }
+
+    /// The share of the escrow the buyer and the seller can still claim at the
+    /// unix time `now`, after the arbiter decided the dispute or the fallback
+    /// outcome applies
+    pub fn claimable_shares(&self, now: u64) -> (Amount, Amount) {
+        match self.decision_at(now) {
+            Some(arbiter_decision) => {
+                let (buyer_share, seller_share) = arbiter_decision.split(self.amount);
+                (
+                    buyer_share.saturating_sub(self.buyer_withdrawn),
+                    seller_share.saturating_sub(self.seller_withdrawn),
+                )
+            }
+            None => (Amount::ZERO, Amount::ZERO),
+        }
+    }
 }
@@ -1,5 +1,6 @@
 pub mod endpoints;
 
+use std::collections::BTreeSet;
 use std::fmt;
 
 use config::EscrowClientConfig;
@@ -9,7 +10,7 @@ use fedimint_core::module::{CommonModuleInit, ModuleCommon, ModuleConsensusVersi
 use fedimint_core::{plugin_types_trait_impl_common, Amount};
 use hex;
 use secp256k1::schnorr::Signature;
-use secp256k1::PublicKey;
+use secp256k1::{Message, PublicKey, Secp256k1};
 use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha256};
 use thiserror::Error;
@@ -20,25 +21,46 @@ pub mod config;
 /// Unique name for this module
 pub const KIND: ModuleKind = ModuleKind::from_static_str("escrow");
 
+/// The basis points making up the whole amount, 10000 bps = 100%
+pub const MAX_BPS: u16 = 10_000;
+
+/// The time after the decision of the arbiters during which the losing party
+/// can appeal to the appeal arbiter, 3 days
+pub const APPEAL_WINDOW_SECS: u64 = 3 * 24 * 60 * 60;
+
+/// The time after the creation of the escrow within which the seller should
+/// accept it, after that the buyer can withdraw the ecash, 7 days
+pub const ACCEPTANCE_WINDOW_SECS: u64 = 7 * 24 * 60 * 60;
+
+/// The maximum size in bytes of an encrypted dispute evidence, for each of its
+/// recipients
+pub const MAX_EVIDENCE_SIZE: usize = 4096;
+
 /// Modules are non-compatible with older versions
-pub const MODULE_CONSENSUS_VERSION: ModuleConsensusVersion = ModuleConsensusVersion::new(2, 0);
+pub const MODULE_CONSENSUS_VERSION: ModuleConsensusVersion = ModuleConsensusVersion::new(3, 0);
 
 /// Non-transaction items that will be submitted to consensus
-/// The Fedimint txn is the only thing that requires consensus from guardians,
-/// other than this we are not proposing any changes.
+/// The guardians vote on the current unix time so that escrow deadlines are
+/// enforced against a time all of them agree on, not the local clock of a
+/// single guardian.
 #[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize, Encodable, Decodable)]
-pub struct EscrowConsensusItem;
+pub struct EscrowConsensusItem {
+    /// The unix time (in seconds) observed by the proposing guardian
+    pub unix_time: u64,
+}
 
 impl std::fmt::Display for EscrowConsensusItem {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "EscrowConsensusItem")
+        write!(f, "EscrowConsensusItem {{ unix_time: {} }}", self.unix_time)
     }
 }
 
 /// The states for the escrow module
 #[derive(Debug, Clone, Eq, PartialEq, Hash, Decodable, Encodable, Serialize, Deserialize)]
 pub enum EscrowStates {
-    /// the escrow is created and not claimed by buyer or seller, thus its open
+    /// the escrow is created and waits for the seller to accept its terms
+    PendingAcceptance,
+    /// the escrow is accepted and not claimed by buyer or seller, thus its open
     Open,
     /// the escrow is resolved without dispute
     ResolvedWithoutDispute,
@@ -52,42 +74,432 @@ pub enum EscrowStates {
     WaitingforBuyerToClaim,
     /// seller has won the dispute and has to claim the escrow
     WaitingforSellerToClaim,
+    /// arbiter has split the escrow and both buyer and seller have to claim
+    /// their share
+    WaitingforBothToClaim,
+    /// the buyer has appealed the decision of the arbiters to the appeal
+    /// arbiter
+    AppealedByBuyer,
+    /// the seller has appealed the decision of the arbiters to the appeal
+    /// arbiter
+    AppealedBySeller,
+    /// the deadline has passed without the escrow being resolved, or the
+    /// seller did not accept it in time, and the buyer has been refunded
+    Expired,
+    /// the buyer and the seller agreed to cancel the escrow and the buyer has
+    /// been refunded
+    Cancelled,
+}
+
+impl EscrowStates {
+    /// Whether the escrow has paid out all of its ecash and cannot change
+    /// anymore
+    pub fn is_resolved(&self) -> bool {
+        matches!(
+            self,
+            EscrowStates::ResolvedWithoutDispute
+                | EscrowStates::ResolvedWithDispute
+                | EscrowStates::Expired
+                | EscrowStates::Cancelled
+        )
+    }
+
+    /// Whether the escrow waits for the decision of the arbiters
+    pub fn is_disputed(&self) -> bool {
+        matches!(
+            self,
+            EscrowStates::DisputedByBuyer | EscrowStates::DisputedBySeller
+        )
+    }
+
+    /// Whether the escrow waits for the decision of the appeal arbiter
+    pub fn is_appealed(&self) -> bool {
+        matches!(
+            self,
+            EscrowStates::AppealedByBuyer | EscrowStates::AppealedBySeller
+        )
+    }
+}
+
+/// A stage of the escrow that the seller can claim on its own by revealing the
+/// secret code of the milestone
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Encodable, Decodable, Serialize, Deserialize)]
+pub struct Milestone {
+    pub amount: Amount,
+    pub secret_code_hash: String,
+}
+
+/// The states for a milestone of the escrow
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Encodable, Decodable, Serialize, Deserialize)]
+pub enum MilestoneState {
+    /// the milestone is locked until the seller reveals its secret code
+    Pending,
+    /// the seller has claimed the milestone with its secret code, or the buyer
+    /// released it
+    Released,
+    /// the milestone was not released when the escrow got disputed, thus it is
+    /// decided by the arbiter
+    Disputed,
+}
+
+/// A milestone of the escrow together with its state, as stored by the
+/// guardians
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Encodable, Decodable, Serialize, Deserialize)]
+pub struct EscrowMilestone {
+    pub amount: Amount,
+    pub secret_code_hash: String,
+    pub state: MilestoneState,
+}
+
+/// How the secret codes of the milestones are hashed, the seller claims a
+/// milestone by revealing the secret code matching its hash
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, Encodable, Decodable, Serialize, Deserialize)]
+pub enum HashLock {
+    /// the secret codes are strings generated by the buyer, hashed with
+    /// `hash256`
+    SecretCode,
+    /// the secret codes are the hex 32 byte preimages of SHA256 payment
+    /// hashes, thus the release can be tied to a Lightning payment or any
+    /// other HTLC locked to the same hash
+    Preimage,
+}
+
+impl HashLock {
+    /// The hex hash of the secret code, if it is a valid secret code for the
+    /// hash lock
+    pub fn hash(&self, secret_code: &str) -> Option<String> {
+        match self {
+            HashLock::SecretCode => Some(hash256(secret_code.to_owned())),
+            HashLock::Preimage => {
+                let preimage: [u8; 32] = hex::decode(secret_code).ok()?.try_into().ok()?;
+                Some(hex::encode(Sha256::digest(preimage)))
+            }
+        }
+    }
+}
+
+/// Whether the secret code hash of a milestone is a lowercase hex 32 byte hash
+pub fn is_valid_hash(secret_code_hash: &str) -> bool {
+    hex::decode(secret_code_hash)
+        .is_ok_and(|hash| hash.len() == 32 && hex::encode(hash) == secret_code_hash)
+}
+
+/// Whether the panel of arbiters can decide a dispute impartially: its
+/// arbiters are distinct and neither the buyer nor the seller, and the
+/// threshold can be reached
+pub fn is_valid_panel(
+    arbiter_pubkeys: &[PublicKey],
+    arbiter_threshold: u32,
+    buyer_pubkey: &PublicKey,
+    seller_pubkey: &PublicKey,
+) -> bool {
+    !arbiter_pubkeys.is_empty()
+        && arbiter_pubkeys.iter().collect::<BTreeSet<_>>().len() == arbiter_pubkeys.len()
+        && !arbiter_pubkeys.contains(buyer_pubkey)
+        && !arbiter_pubkeys.contains(seller_pubkey)
+        && arbiter_threshold != 0
+        && arbiter_threshold as usize <= arbiter_pubkeys.len()
+}
+
+impl From<Milestone> for EscrowMilestone {
+    fn from(milestone: Milestone) -> Self {
+        EscrowMilestone {
+            amount: milestone.amount,
+            secret_code_hash: milestone.secret_code_hash,
+            state: MilestoneState::Pending,
+        }
+    }
+}
+
+/// The role of a participant in the escrow
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, Encodable, Decodable, Serialize, Deserialize)]
+pub enum EscrowRole {
+    Buyer,
+    Seller,
+    Arbiter,
+}
+
+impl std::str::FromStr for EscrowRole {
+    type Err = EscrowError;
+
+    fn from_str(role: &str) -> Result<Self, Self::Err> {
+        match role.to_lowercase().as_str() {
+            "buyer" => Ok(EscrowRole::Buyer),
+            "seller" => Ok(EscrowRole::Seller),
+            "arbiter" => Ok(EscrowRole::Arbiter),
+            _ => Err(EscrowError::InvalidRole),
+        }
+    }
 }
 
 /// The disputer in the escrow, can either be buyer or the seller
-#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, Encodable, Decodable, Serialize, Deserialize)]
 pub enum Disputer {
     Buyer,
     Seller,
 }
 
-/// The arbiter decision on who won the dispute, either the buyer or the seller
-#[derive(Debug, Clone, Eq, PartialEq, Hash, Encodable, Decodable)]
+/// The reason or evidence of a party in a dispute, or the response of the
+/// counterparty to it.
+///
+/// It is encrypted once for every arbiter of the panel and once for the
+/// counterparty, each with a key derived from the ECDH shared secret of the
+/// party and the recipient. The federation only stores it and cannot read it.
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Encodable, Decodable, Serialize, Deserialize)]
+pub struct EncryptedEvidence {
+    /// in the order of the arbiters of the panel
+    pub for_arbiters: Vec<Vec<u8>>,
+    pub for_counterparty: Vec<u8>,
+}
+
+impl EncryptedEvidence {
+    /// Whether the evidence fits in the maximum size allowed for each recipient
+    pub fn is_within_size_limit(&self) -> bool {
+        self.for_arbiters
+            .iter()
+            .all(|for_arbiter| for_arbiter.len() <= MAX_EVIDENCE_SIZE)
+            && self.for_counterparty.len() <= MAX_EVIDENCE_SIZE
+    }
+}
+
+/// The outcome of a dispute the arbiters did not decide before the arbitration
+/// deadline, chosen by the buyer when creating the escrow
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, Encodable, Decodable, Serialize, Deserialize)]
+pub enum FallbackOutcome {
+    RefundBuyer,
+    PaySeller,
+    /// the escrow is split equally between the buyer and the seller
+    Split,
+}
+
+impl FallbackOutcome {
+    /// The decision the fallback outcome stands for
+    pub fn decision(&self) -> ArbiterDecision {
+        match self {
+            FallbackOutcome::RefundBuyer => ArbiterDecision::BuyerWins,
+            FallbackOutcome::PaySeller => ArbiterDecision::SellerWins,
+            FallbackOutcome::Split => ArbiterDecision::Split {
+                buyer_share_bps: MAX_BPS / 2,
+            },
+        }
+    }
+}
+
+impl std::str::FromStr for FallbackOutcome {
+    type Err = EscrowError;
+
+    fn from_str(fallback: &str) -> Result<Self, Self::Err> {
+        match fallback.to_lowercase().as_str() {
+            "refund-buyer" => Ok(FallbackOutcome::RefundBuyer),
+            "pay-seller" => Ok(FallbackOutcome::PaySeller),
+            "split" => Ok(FallbackOutcome::Split),
+            _ => Err(EscrowError::InvalidFallbackOutcome),
+        }
+    }
+}
+
+/// The arbiter decision on who won the dispute, either the buyer or the seller,
+/// or a split of the escrow between both of them
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Encodable, Decodable, Serialize, Deserialize)]
 pub enum ArbiterDecision {
     BuyerWins,
     SellerWins,
+    /// the buyer gets `buyer_share_bps` of the escrow and the seller the rest
+    Split {
+        buyer_share_bps: u16,
+    },
+}
+
+/// The vote of an arbiter of the panel on the dispute, the arbiters agree when
+/// they vote for the same decision and the same fee
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Encodable, Decodable, Serialize, Deserialize)]
+pub struct ArbiterVote {
+    pub arbiter: PublicKey,
+    pub decision: ArbiterDecision,
+    /// the whole arbiter fee, split among the arbiters agreeing on the decision
+    pub fee: Amount,
+    /// whether the arbiter has been paid its share of the fee
+    pub fee_claimed: bool,
+}
+
+impl ArbiterVote {
+    /// Whether both votes are for the same decision and the same fee
+    pub fn agrees_with(&self, other: &ArbiterVote) -> bool {
+        self.decision == other.decision && self.fee == other.fee
+    }
+
+    /// The share of the fee of each of the `arbiter_threshold` arbiters making
+    /// the decision, the remainder of the split stays in the escrow
+    pub fn fee_share(&self, arbiter_threshold: u32) -> Amount {
+        Amount::from_msats(self.fee.msats / u64::from(arbiter_threshold.max(1)))
+    }
+}
+
+impl ArbiterDecision {
+    /// The state of the escrow once the decision is made, waiting for the
+    /// winners to claim their share
+    pub fn claim_state(&self) -> EscrowStates {
+        match self {
+            ArbiterDecision::BuyerWins => EscrowStates::WaitingforBuyerToClaim,
+            ArbiterDecision::SellerWins => EscrowStates::WaitingforSellerToClaim,
+            ArbiterDecision::Split { .. } => EscrowStates::WaitingforBothToClaim,
+        }
+    }
+
+    /// Whether the party gets less than the whole escrow with this decision,
+    /// thus can appeal it
+    pub fn is_lost_by(&self, party: &Disputer) -> bool {
+        !matches!(
+            (self, party),
+            (ArbiterDecision::BuyerWins, Disputer::Buyer)
+                | (ArbiterDecision::SellerWins, Disputer::Seller)
+        )
+    }
+
+    /// Splits the amount into the shares of the buyer and the seller
+    pub fn split(&self, amount: Amount) -> (Amount, Amount) {
+        let buyer_share = match self {
+            ArbiterDecision::BuyerWins => amount,
+            ArbiterDecision::SellerWins => Amount::ZERO,
+            ArbiterDecision::Split { buyer_share_bps } => bps_of(amount, *buyer_share_bps),
+        };
+        (buyer_share, amount.saturating_sub(buyer_share))
+    }
+}
+
+/// The terms of the escrow the seller agrees to by accepting it
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Encodable, Decodable, Serialize, Deserialize)]
+pub struct EscrowTerms {
+    pub amount: Amount,
+    pub buyer_pubkey: PublicKey,
+    pub seller_pubkey: PublicKey,
+    pub arbiter_pubkeys: Vec<PublicKey>,
+    pub arbiter_threshold: u32,
+    pub appeal_arbiter: Option<PublicKey>,
+    /// the amounts of the milestones in order, with the hashes locking them
+    pub milestones: Vec<Milestone>,
+    pub max_arbiter_fee: Amount,
+    pub deadline: u64,
+    pub arbitration_timeout_secs: u64,
+    pub fallback: FallbackOutcome,
+    pub hash_lock: HashLock,
+}
+
+/// The actions on the escrow that have to be authorized by a signature
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Encodable, Decodable)]
+pub enum EscrowAction {
+    Accept(EscrowTerms),
+    ClaimWithoutDispute {
+        milestone: u32,
+    },
+    Dispute {
+        evidence: Option<EncryptedEvidence>,
+    },
+    RespondToDispute {
+        response: EncryptedEvidence,
+    },
+    ArbiterDecision {
+        decision: ArbiterDecision,
+        fee: Amount,
+    },
+    ClaimArbiterFee,
+    Appeal,
+    AppealDecision(ArbiterDecision),
+    ClaimAfterDispute,
+    ClaimAfterExpiry,
+    Cancel,
+    Release,
+    ClaimReleased,
 }
 
 /// The input for the escrow module
 #[derive(Debug, Clone, Eq, PartialEq, Hash, Encodable, Decodable)]
 pub enum EscrowInput {
+    /// The input when seller accepts the terms of the escrow
+    Accepting(EscrowInputAccepting),
     /// The input when seller is claiming the escrow without any dispute
     ClamingWithoutDispute(EscrowInputClamingWithoutDispute),
     /// The input when buyer or seller is disputing the escrow
     Disputing(EscrowInputDisputing),
     /// The input when buyer or seller is claiming the escrow after the dispute
     ClaimingAfterDispute(EscrowInputClaimingAfterDispute),
-    /// The input when arbiter is deciding who won the dispute
+    /// The input when an arbiter of the panel votes on who won the dispute
     ArbiterDecision(EscrowInputArbiterDecision),
+    /// The input when an arbiter of the majority claims its share of the fee
+    ClaimingArbiterFee(EscrowInputClaimingArbiterFee),
+    /// The input when buyer is reclaiming the escrow after its deadline has
+    /// passed
+    ClaimingAfterExpiry(EscrowInputClaimingAfterExpiry),
+    /// The input when buyer and seller mutually agree to cancel the escrow
+    Cancel(EscrowInputCancel),
+    /// The input when the counterparty of the disputer responds to the dispute
+    RespondingToDispute(EscrowInputRespondingToDispute),
+    /// The input when the losing party appeals the decision of the arbiters
+    Appealing(EscrowInputAppealing),
+    /// The input when the appeal arbiter decides the appeal
+    AppealDecision(EscrowInputAppealDecision),
+    /// The input when buyer releases the escrow to the seller without a secret
+    /// code
+    Releasing(EscrowInputReleasing),
+    /// The input when seller claims the escrow released by the buyer
+    ClaimingReleased(EscrowInputClaimingReleased),
+}
+
+impl EscrowInput {
+    /// The id of the escrow the input spends from
+    pub fn escrow_id(&self) -> &str {
+        match self {
+            EscrowInput::Accepting(input) => &input.escrow_id,
+            EscrowInput::ClamingWithoutDispute(input) => &input.escrow_id,
+            EscrowInput::Disputing(input) => &input.escrow_id,
+            EscrowInput::ClaimingAfterDispute(input) => &input.escrow_id,
+            EscrowInput::ArbiterDecision(input) => &input.escrow_id,
+            EscrowInput::ClaimingArbiterFee(input) => &input.escrow_id,
+            EscrowInput::ClaimingAfterExpiry(input) => &input.escrow_id,
+            EscrowInput::Cancel(input) => &input.escrow_id,
+            EscrowInput::RespondingToDispute(input) => &input.escrow_id,
+            EscrowInput::Appealing(input) => &input.escrow_id,
+            EscrowInput::AppealDecision(input) => &input.escrow_id,
+            EscrowInput::Releasing(input) => &input.escrow_id,
+            EscrowInput::ClaimingReleased(input) => &input.escrow_id,
+        }
+    }
+}
+/// The input for the escrow module when the buyer releases the unreleased
+/// milestones of the escrow to the seller, which resolves it
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Encodable, Decodable)]
+pub struct EscrowInputReleasing {
+    pub escrow_id: String,
+    pub signature: Signature,
+}
+
+/// The input for the escrow module when the seller claims the ecash released
+/// by the buyer
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Encodable, Decodable)]
+pub struct EscrowInputClaimingReleased {
+    pub amount: Amount,
+    pub escrow_id: String,
+    pub signature: Signature,
 }
-/// The input for the escrow module when the seller is claiming the escrow using
-/// the secret code
+
+/// The input for the escrow module when the seller accepts the terms of the
+/// escrow, which opens it
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Encodable, Decodable)]
+pub struct EscrowInputAccepting {
+    pub escrow_id: String,
+    pub seller: PublicKey,
+    pub signature: Signature,
+}
+
+/// The input for the escrow module when the seller is claiming a milestone of
+/// the escrow using its secret code
 #[derive(Debug, Clone, Eq, PartialEq, Hash, Encodable, Decodable)]
 pub struct EscrowInputClamingWithoutDispute {
     pub amount: Amount,
     pub escrow_id: String,
+    /// the index of the milestone being claimed
+    pub milestone: u32,
     pub secret_code: String,
-    pub hashed_message: [u8; 32],
     pub signature: Signature,
 }
 
@@ -97,7 +509,8 @@ pub struct EscrowInputClamingWithoutDispute {
 pub struct EscrowInputDisputing {
     pub escrow_id: String,
     pub disputer: PublicKey,
-    pub hashed_message: [u8; 32],
+    /// the reason of the dispute for the arbiter and the counterparty
+    pub evidence: Option<EncryptedEvidence>,
     pub signature: Signature,
 }
 
@@ -107,18 +520,80 @@ pub struct EscrowInputDisputing {
 pub struct EscrowInputClaimingAfterDispute {
     pub amount: Amount,
     pub escrow_id: String,
-    pub hashed_message: [u8; 32],
+    pub claimer: PublicKey,
     pub signature: Signature,
 }
 
-/// The input for the escrow module when the seller is claiming the escrow using
-/// the secret code
+/// The input for the escrow module when an arbiter of the panel votes on the
+/// dispute. The amount is the share of the fee of the arbiter if its vote makes
+/// the decision, zero otherwise
 #[derive(Debug, Clone, Eq, PartialEq, Hash, Encodable, Decodable)]
 pub struct EscrowInputArbiterDecision {
+    pub amount: Amount,
+    pub escrow_id: String,
+    pub arbiter: PublicKey,
+    pub arbiter_decision: ArbiterDecision,
+    /// the whole arbiter fee voted for
+    pub fee: Amount,
+    pub signature: Signature,
+}
+
+/// The input for the escrow module when an arbiter who voted with the majority
+/// before the decision was made claims its share of the fee
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Encodable, Decodable)]
+pub struct EscrowInputClaimingArbiterFee {
+    pub amount: Amount,
+    pub escrow_id: String,
+    pub arbiter: PublicKey,
+    pub signature: Signature,
+}
+
+/// The input for the escrow module when the buyer is reclaiming an escrow that
+/// was neither claimed nor disputed before its deadline
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Encodable, Decodable)]
+pub struct EscrowInputClaimingAfterExpiry {
+    pub amount: Amount,
+    pub escrow_id: String,
+    pub signature: Signature,
+}
+
+/// The input for the escrow module when the buyer and the seller have both
+/// signed the cancellation of the escrow, the buyer is refunded
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Encodable, Decodable)]
+pub struct EscrowInputCancel {
+    pub amount: Amount,
+    pub escrow_id: String,
+    pub buyer_signature: Signature,
+    pub seller_signature: Signature,
+}
+
+/// The input for the escrow module when the party losing the decision of the
+/// arbiters appeals it within the appeal window
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Encodable, Decodable)]
+pub struct EscrowInputAppealing {
+    pub escrow_id: String,
+    pub appellant: PublicKey,
+    pub signature: Signature,
+}
+
+/// The input for the escrow module when the appeal arbiter decides the appeal,
+/// superseding the decision of the arbiters. The amount is the fee of the
+/// appeal arbiter
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Encodable, Decodable)]
+pub struct EscrowInputAppealDecision {
     pub amount: Amount,
     pub escrow_id: String,
     pub arbiter_decision: ArbiterDecision,
-    pub hashed_message: [u8; 32],
+    pub signature: Signature,
+}
+
+/// The input for the escrow module when the counterparty of the disputer
+/// attaches its response to the dispute before the arbiter decides
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Encodable, Decodable)]
+pub struct EscrowInputRespondingToDispute {
+    pub escrow_id: String,
+    pub responder: PublicKey,
+    pub response: EncryptedEvidence,
     pub signature: Signature,
 }
 
@@ -128,10 +603,26 @@ pub struct EscrowOutput {
     pub amount: Amount,
     pub buyer_pubkey: PublicKey,
     pub seller_pubkey: PublicKey,
-    pub arbiter_pubkey: PublicKey,
+    /// the panel of arbiters deciding a dispute
+    pub arbiter_pubkeys: Vec<PublicKey>,
+    /// the number of arbiters that have to agree on a decision
+    pub arbiter_threshold: u32,
+    /// the arbiter the losing party can appeal the decision of the arbiters to
+    pub appeal_arbiter: Option<PublicKey>,
     pub escrow_id: String,
-    pub secret_code_hash: String,
+    /// the ordered milestones of the escrow, their amounts add up to the
+    /// amount of the escrow
+    pub milestones: Vec<Milestone>,
     pub max_arbiter_fee: Amount,
+    /// unix time (in seconds) after which the buyer can reclaim the escrow if
+    /// it is still open
+    pub deadline: u64,
+    /// the seconds after the dispute within which the arbiters have to decide
+    pub arbitration_timeout_secs: u64,
+    /// the outcome of the dispute if the arbiters do not decide in time
+    pub fallback: FallbackOutcome,
+    /// how the secret codes of the milestones are hashed
+    pub hash_lock: HashLock,
 }
 
 /// Errors that might be returned by the server when the buyer awaits guardians
@@ -156,8 +647,6 @@ pub enum EscrowInputError {
     InvalidStateForArbiterDecision,
     #[error("Invalid arbiter signature")]
     InvalidArbiter,
-    #[error("Invalid max arbiter fee in bps, it should be in range 10 to 1000")]
-    InvalidMaxArbiterFeeBps,
     #[error("Invalid seller")]
     InvalidSeller,
     #[error("Invalid buyer")]
@@ -168,6 +657,58 @@ pub enum EscrowInputError {
     InvalidPublicKey(String),
     #[error("Arbiter fee exceeds the maximum allowed")]
     ArbiterFeeExceedsMaximum,
+    #[error("Escrow deadline has not passed yet")]
+    EscrowNotExpired,
+    #[error("Invalid state for cancelling escrow")]
+    InvalidStateForCancellation,
+    #[error("Claimed amount {claimed} does not match the escrow amount {expected}")]
+    ClaimAmountMismatch { claimed: Amount, expected: Amount },
+    #[error("Escrow is already resolved and cannot pay out again")]
+    EscrowAlreadyResolved,
+    #[error("Invalid buyer share, it should be at most 10000 bps")]
+    InvalidBuyerShare,
+    #[error("Unauthorized to claim this escrow")]
+    UnauthorizedToClaim,
+    #[error("Nothing left to claim in this escrow")]
+    NothingToClaim,
+    #[error("Milestone not found")]
+    MilestoneNotFound,
+    #[error("Milestone is already released")]
+    MilestoneAlreadyReleased,
+    #[error("Dispute evidence exceeds the maximum size")]
+    EvidenceTooLarge,
+    #[error("Only the counterparty of the disputer can respond to the dispute")]
+    UnauthorizedToRespond,
+    #[error("Dispute is already responded to")]
+    DisputeAlreadyResponded,
+    #[error("Arbiter has already voted on this dispute")]
+    ArbiterAlreadyVoted,
+    #[error("No arbiter fee to claim for this arbiter")]
+    NoArbiterFeeToClaim,
+    #[error("Escrow cannot be appealed")]
+    NotAppealable,
+    #[error("Appeal window has passed")]
+    AppealWindowClosed,
+    #[error("Escrow can still be appealed, thus it cannot be claimed yet")]
+    AppealWindowOpen,
+    #[error("Only the party losing the decision can appeal it")]
+    UnauthorizedToAppeal,
+    #[error("Escrow is not appealed, thus the appeal arbiter cannot decide")]
+    EscrowNotAppealed,
+    #[error("Arbitration deadline has passed, the fallback outcome applies")]
+    ArbitrationTimedOut,
+    #[error("Escrow is not waiting for the seller to accept it")]
+    EscrowNotPendingAcceptance,
+    #[error("Only the seller can accept the escrow")]
+    UnauthorizedToAccept,
+    #[error("Escrow is not released by the buyer, or the seller already claimed it")]
+    NotReleased,
+    #[error("Escrow id is longer than the federation allows")]
+    EscrowIdTooLong,
+    #[error("Appeal deadline has passed, the decision of the arbiters stands")]
+    AppealTimedOut,
+    #[error("Acceptance deadline has passed, the buyer can withdraw the escrow")]
+    AcceptanceTimedOut,
 }
 
 /// Errors that might be returned by the server
@@ -175,6 +716,29 @@ pub enum EscrowInputError {
 pub enum EscrowOutputError {
     #[error("Escrow already exists")]
     EscrowAlreadyExists,
+    #[error("Escrow deadline is already in the past")]
+    DeadlineInPast,
+    #[error("Invalid milestones, they should be non-zero and add up to the escrow amount")]
+    InvalidMilestones,
+    #[error(
+        "Invalid arbiters, they should be distinct, neither the buyer nor the seller, and the \
+         threshold between 1 and their number"
+    )]
+    InvalidArbiters,
+    #[error("Invalid appeal arbiter, it should not be an arbiter of the panel")]
+    InvalidAppealArbiter,
+    #[error("Escrow amount is below the minimum of the federation")]
+    AmountBelowMinimum,
+    #[error("Escrow amount is above the maximum of the federation")]
+    AmountAboveMaximum,
+    #[error("Maximum arbiter fee is above the maximum fee bps of the federation")]
+    ArbiterFeeAboveMaximum,
+    #[error("Buyer has too many unresolved escrows")]
+    TooManyOpenEscrows,
+    #[error("Escrow id is longer than the federation allows")]
+    EscrowIdTooLong,
+    #[error("Arbitration timeout is below the minimum of the federation")]
+    ArbitrationTimeoutBelowMinimum,
 }
 
 /// The errors for the escrow module in client side
@@ -186,12 +750,87 @@ pub enum EscrowError {
     EscrowDisputed,
     #[error("Arbiter has not decided the ecash to be given to buyer or seller yet!")]
     ArbiterNotDecided,
-    #[error("Invalid arbiter decision, either the winner can be the buyer or the seller")]
+    #[error(
+        "Invalid arbiter decision, either the winner can be the buyer or the seller, or the escrow is split with a buyer share in bps"
+    )]
     InvalidArbiterDecision,
     #[error("Transaction was rejected")]
     TransactionRejected,
     #[error("Escrow not found")]
     EscrowNotFound,
+    #[error("Escrow deadline has not passed yet or the escrow is not open")]
+    EscrowNotExpired,
+    #[error("Only the buyer can submit the cancellation, as the ecash is refunded to the buyer")]
+    NotBuyer,
+    #[error("Invalid cancellation signature of the counterparty")]
+    InvalidCancellationSignature,
+    #[error("Escrow is not open, thus it cannot be claimed with the secret code")]
+    EscrowNotOpen,
+    #[error("You do not take part in this escrow")]
+    NotParticipant,
+    #[error("Invalid role, it can be the buyer, the seller or the arbiter")]
+    InvalidRole,
+    #[error("Invalid milestones, they should add up to the cost of the escrow")]
+    InvalidMilestones,
+    #[error("No unreleased milestone matches the secret code")]
+    MilestoneNotFound,
+    #[error("Escrow is not disputed, thus there is nothing to respond to")]
+    EscrowNotDisputed,
+    #[error("Only the counterparty of the disputer can respond to the dispute")]
+    NotCounterparty,
+    #[error("Dispute evidence is too large")]
+    EvidenceTooLarge,
+    #[error(
+        "Invalid arbiters, they should be distinct, neither the buyer nor the seller, and the \
+         threshold between 1 and their number"
+    )]
+    InvalidArbiters,
+    #[error("You are not an arbiter of this escrow")]
+    NotArbiter,
+    #[error("No arbiter fee left to claim")]
+    NoArbiterFeeToClaim,
+    #[error("Invalid appeal arbiter, it should not be an arbiter of the panel")]
+    InvalidAppealArbiter,
+    #[error("Escrow cannot be appealed, either there is no appeal arbiter, you did not lose the decision or the appeal window has passed")]
+    NotAppealable,
+    #[error("Escrow is not appealed, or you are not its appeal arbiter")]
+    NotAppealArbiter,
+    #[error("Escrow can still be appealed until {0}, thus it cannot be claimed yet")]
+    AppealWindowOpen(u64),
+    #[error("Invalid fallback outcome, it can be refund-buyer, pay-seller or split")]
+    InvalidFallbackOutcome,
+    #[error("Arbitration deadline has passed, the fallback outcome applies")]
+    ArbitrationTimedOut,
+    #[error("Appeal deadline has passed, the decision of the arbiters stands")]
+    AppealTimedOut,
+    #[error("Escrow is not waiting for the seller to accept it")]
+    EscrowNotPendingAcceptance,
+    #[error("Only the seller can accept the escrow")]
+    NotSeller,
+    #[error("Escrow is not released by the buyer, or you already claimed it")]
+    NotReleased,
+    #[error("Escrow id is longer than the federation allows")]
+    EscrowIdTooLong,
+    #[error("No escrow is waiting for the invoice of this operation")]
+    InvoiceEscrowNotFound,
+    #[error("Invoice of the escrow was not paid")]
+    InvoiceNotPaid,
+    #[error("Invoice should have an amount that the claimed ecash covers")]
+    InvalidPayoutInvoice,
+    #[error("Payment of the invoice failed, the claimed ecash stays in the wallet")]
+    InvoicePaymentFailed,
+    #[error("Claimed ecash does not cover the peg-out fees")]
+    ClaimBelowPegOutFees,
+    #[error("Peg-out of the claim failed, the claimed ecash stays in the wallet")]
+    PegOutFailed,
+    #[error("Every milestone should be locked to a payment hash")]
+    InvalidPaymentHashes,
+    #[error("Escrow deadline is already in the past")]
+    DeadlineInPast,
+    #[error("No Lightning gateway is available to pay the invoice")]
+    NoGateway,
+    #[error("Address is not on the Bitcoin network of the federation")]
+    WrongAddressNetwork,
 }
 
 impl From<secp256k1::Error> for EscrowInputError {
@@ -244,8 +883,14 @@ impl fmt::Display for EscrowClientConfig {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(
             f,
-            "EscrowClientConfig {{ deposit_fee: {} }}",
-            self.deposit_fee
+            "EscrowClientConfig {{ deposit_fee: {}, max_arbiter_fee_bps: {}, min_escrow_amount: {}, max_escrow_amount: {}, max_escrow_id_length: {}, min_arbitration_timeout_secs: {}, signing_domain: {} }}",
+            self.deposit_fee,
+            self.max_arbiter_fee_bps,
+            self.min_escrow_amount,
+            self.max_escrow_amount,
+            self.max_escrow_id_length,
+            self.min_arbitration_timeout_secs,
+            hex::encode(self.signing_domain)
         )
     }
 }
@@ -253,10 +898,15 @@ impl fmt::Display for EscrowClientConfig {
 impl fmt::Display for EscrowInput {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
+            EscrowInput::Accepting(input) => write!(
+                f,
+                "EscrowInput::Accepting {{ seller: {:?} }}",
+                input.seller
+            ),
             EscrowInput::ClamingWithoutDispute(input) => write!(
                 f,
-                "EscrowInput::ClamingWithoutDispute {{ amount: {}, secret_code: {} }}",
-                input.amount, input.secret_code
+                "EscrowInput::ClamingWithoutDispute {{ amount: {}, milestone: {}, secret_code: {} }}",
+                input.amount, input.milestone, input.secret_code
             ),
             EscrowInput::Disputing(input) => write!(
                 f,
@@ -265,16 +915,56 @@ impl fmt::Display for EscrowInput {
             ),
             EscrowInput::ClaimingAfterDispute(input) => write!(
                 f,
-                "EscrowInput::ClaimingAfterDispute {{ amount: {} }}",
-                input.amount
+                "EscrowInput::ClaimingAfterDispute {{ amount: {}, claimer: {:?} }}",
+                input.amount, input.claimer
             ),
             EscrowInput::ArbiterDecision(input) => write!(
                 f,
-                "EscrowInput::ArbiterDecision {{ amount: {}, decision: {:?}, signature: {}}}",
+                "EscrowInput::ArbiterDecision {{ amount: {}, arbiter: {:?}, decision: {:?}, fee: {}, signature: {}}}",
                 input.amount,
+                input.arbiter,
                 input.arbiter_decision,
+                input.fee,
                 hex::encode(input.signature.as_ref()),
             ),
+            EscrowInput::ClaimingArbiterFee(input) => write!(
+                f,
+                "EscrowInput::ClaimingArbiterFee {{ amount: {}, arbiter: {:?} }}",
+                input.amount, input.arbiter
+            ),
+            EscrowInput::ClaimingAfterExpiry(input) => write!(
+                f,
+                "EscrowInput::ClaimingAfterExpiry {{ amount: {} }}",
+                input.amount
+            ),
+            EscrowInput::Cancel(input) => {
+                write!(f, "EscrowInput::Cancel {{ amount: {} }}", input.amount)
+            }
+            EscrowInput::Appealing(input) => write!(
+                f,
+                "EscrowInput::Appealing {{ appellant: {:?} }}",
+                input.appellant
+            ),
+            EscrowInput::AppealDecision(input) => write!(
+                f,
+                "EscrowInput::AppealDecision {{ amount: {}, decision: {:?} }}",
+                input.amount, input.arbiter_decision
+            ),
+            EscrowInput::RespondingToDispute(input) => write!(
+                f,
+                "EscrowInput::RespondingToDispute {{ responder: {:?} }}",
+                input.responder
+            ),
+            EscrowInput::Releasing(input) => write!(
+                f,
+                "EscrowInput::Releasing {{ escrow_id: {} }}",
+                input.escrow_id
+            ),
+            EscrowInput::ClaimingReleased(input) => write!(
+                f,
+                "EscrowInput::ClaimingReleased {{ amount: {} }}",
+                input.amount
+            ),
         }
     }
 }
@@ -283,18 +973,77 @@ impl fmt::Display for EscrowOutput {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(
             f,
-            "EscrowOutput {{ amount: {}, buyer_pubkey: {:?}, seller_pubkey: {:?}, arbiter_pubkey: {:?}, escrow_id: {}, secret_code_hash: {}, max_arbiter_fee: {} }}",
+            "EscrowOutput {{ amount: {}, buyer_pubkey: {:?}, seller_pubkey: {:?}, arbiter_pubkeys: {:?}, arbiter_threshold: {}, appeal_arbiter: {:?}, escrow_id: {}, milestones: {:?}, max_arbiter_fee: {}, deadline: {}, arbitration_timeout_secs: {}, fallback: {:?}, hash_lock: {:?} }}",
             self.amount,
             self.buyer_pubkey,
             self.seller_pubkey,
-            self.arbiter_pubkey,
+            self.arbiter_pubkeys,
+            self.arbiter_threshold,
+            self.appeal_arbiter,
             self.escrow_id,
-            self.secret_code_hash,
-            self.max_arbiter_fee
+            self.milestones,
+            self.max_arbiter_fee,
+            self.deadline,
+            self.arbitration_timeout_secs,
+            self.fallback,
+            self.hash_lock
         )
     }
 }
 
+/// The message that has to be signed to authorize an action on the escrow.
+///
+/// It is a BIP-340 style tagged hash, with the tag derived from the module kind
+/// so that the signature cannot be mistaken for one created by the same key for
+/// another purpose. The message commits to the signing domain of the escrow
+/// module of the federation, the escrow id, the action (including the arbiter
+/// decision) and the amount moved by the action, thus a signature cannot be
+/// replayed in another federation, against another escrow or for another
+/// action.
+pub fn escrow_signing_message(
+    signing_domain: &[u8; 32],
+    escrow_id: &str,
+    action: EscrowAction,
+    amount: Amount,
+) -> Message {
+    let tag_hash = Sha256::digest(format!("fedimint/{KIND}/signature").as_bytes());
+
+    let mut data = Vec::new();
+    signing_domain
+        .consensus_encode(&mut data)
+        .expect("encoding to vec can't fail");
+    escrow_id
+        .to_owned()
+        .consensus_encode(&mut data)
+        .expect("encoding to vec can't fail");
+    action
+        .consensus_encode(&mut data)
+        .expect("encoding to vec can't fail");
+    amount
+        .consensus_encode(&mut data)
+        .expect("encoding to vec can't fail");
+
+    let mut hasher = Sha256::new();
+    hasher.update(tag_hash);
+    hasher.update(tag_hash);
+    hasher.update(data);
+    let hashed_message: [u8; 32] = hasher.finalize().into();
+    Message::from_slice(&hashed_message).expect("32 bytes")
+}
+
+/// Verifies the schnorr signature of the public key over the message
+pub fn verify_signature(message: &Message, signature: &Signature, pubkey: &PublicKey) -> bool {
+    let (xonly_pubkey, _parity) = pubkey.x_only_public_key();
+    Secp256k1::verification_only()
+        .verify_schnorr(signature, message, &xonly_pubkey)
+        .is_ok()
+}
+
+/// The share of the amount given in basis points, rounded down
+pub fn bps_of(amount: Amount, bps: u16) -> Amount {
+    Amount::from_msats((u128::from(amount.msats) * u128::from(bps) / u128::from(MAX_BPS)) as u64)
+}
+
 /// Hashes the value using SHA256
 pub fn hash256(value: String) -> String {
     let mut hasher = Sha256::new();
@@ -302,3 +1051,198 @@ pub fn hash256(value: String) -> String {
     let result = hasher.finalize();
     hex::encode(result)
 }
+
+#[cfg(test)]
+mod tests {
+    use fedimint_core::Amount;
+    use secp256k1::{KeyPair, Secp256k1};
+
+    use super::{
+        escrow_signing_message, hash256, is_valid_panel, verify_signature, ArbiterDecision,
+        EscrowAction, EscrowTerms, FallbackOutcome, HashLock, Milestone,
+    };
+
+    const DOMAIN: [u8; 32] = [0; 32];
+
+    #[test]
+    fn signature_is_bound_to_escrow_action_and_amount() {
+        let secp = Secp256k1::new();
+        let key = KeyPair::from_seckey_slice(&secp, &[1; 32]).expect("valid secret key");
+        let pubkey = key.public_key();
+        let amount = Amount::from_sats(50);
+
+        let signature = secp.sign_schnorr(
+            &escrow_signing_message(&DOMAIN, "escrow-a", EscrowAction::ClaimAfterDispute, amount),
+            &key,
+        );
+        assert!(verify_signature(
+            &escrow_signing_message(&DOMAIN, "escrow-a", EscrowAction::ClaimAfterDispute, amount),
+            &signature,
+            &pubkey,
+        ));
+
+        // the signature cannot be replayed on another escrow
+        assert!(!verify_signature(
+            &escrow_signing_message(&DOMAIN, "escrow-b", EscrowAction::ClaimAfterDispute, amount),
+            &signature,
+            &pubkey,
+        ));
+        // nor for another action on the same escrow
+        assert!(!verify_signature(
+            &escrow_signing_message(&DOMAIN, "escrow-a", EscrowAction::ClaimAfterExpiry, amount),
+            &signature,
+            &pubkey,
+        ));
+        // nor for another amount
+        assert!(!verify_signature(
+            &escrow_signing_message(
+                &DOMAIN,
+                "escrow-a",
+                EscrowAction::ClaimAfterDispute,
+                Amount::from_sats(51)
+            ),
+            &signature,
+            &pubkey,
+        ));
+        // nor in another federation
+        assert!(!verify_signature(
+            &escrow_signing_message(
+                &[1; 32],
+                "escrow-a",
+                EscrowAction::ClaimAfterDispute,
+                amount
+            ),
+            &signature,
+            &pubkey,
+        ));
+    }
+
+    #[test]
+    fn signature_is_bound_to_arbiter_decision() {
+        let secp = Secp256k1::new();
+        let key = KeyPair::from_seckey_slice(&secp, &[2; 32]).expect("valid secret key");
+        let fee = Amount::from_sats(1);
+
+        let signature = secp.sign_schnorr(
+            &escrow_signing_message(
+                &DOMAIN,
+                "escrow-a",
+                EscrowAction::ArbiterDecision {
+                    decision: ArbiterDecision::BuyerWins,
+                    fee,
+                },
+                Amount::ZERO,
+            ),
+            &key,
+        );
+
+        // the decision and the fee voted for cannot be swapped
+        for action in [
+            EscrowAction::ArbiterDecision {
+                decision: ArbiterDecision::SellerWins,
+                fee,
+            },
+            EscrowAction::ArbiterDecision {
+                decision: ArbiterDecision::BuyerWins,
+                fee: Amount::from_sats(2),
+            },
+            EscrowAction::AppealDecision(ArbiterDecision::BuyerWins),
+        ] {
+            assert!(!verify_signature(
+                &escrow_signing_message(&DOMAIN, "escrow-a", action, Amount::ZERO),
+                &signature,
+                &key.public_key(),
+            ));
+        }
+    }
+
+    #[test]
+    fn acceptance_is_bound_to_seller_and_milestone_hashes() {
+        let secp = Secp256k1::new();
+        let pubkey = |seed: u8| {
+            KeyPair::from_seckey_slice(&secp, &[seed; 32])
+                .expect("valid secret key")
+                .public_key()
+        };
+        let seller = KeyPair::from_seckey_slice(&secp, &[2; 32]).expect("valid secret key");
+        let terms = EscrowTerms {
+            amount: Amount::from_sats(50),
+            buyer_pubkey: pubkey(1),
+            seller_pubkey: seller.public_key(),
+            arbiter_pubkeys: vec![pubkey(3)],
+            arbiter_threshold: 1,
+            appeal_arbiter: None,
+            milestones: vec![Milestone {
+                amount: Amount::from_sats(50),
+                secret_code_hash: hash256("secret code".to_owned()),
+            }],
+            max_arbiter_fee: Amount::ZERO,
+            deadline: 1_000,
+            arbitration_timeout_secs: 1_000,
+            fallback: FallbackOutcome::RefundBuyer,
+            hash_lock: HashLock::SecretCode,
+        };
+
+        let signature = secp.sign_schnorr(
+            &escrow_signing_message(
+                &DOMAIN,
+                "escrow-a",
+                EscrowAction::Accept(terms.clone()),
+                Amount::ZERO,
+            ),
+            &seller,
+        );
+
+        // the acceptance cannot be replayed for another seller, nor for milestones
+        // locked to other hashes
+        let other_seller = EscrowTerms {
+            seller_pubkey: pubkey(4),
+            ..terms.clone()
+        };
+        let other_hashes = EscrowTerms {
+            milestones: vec![Milestone {
+                amount: Amount::from_sats(50),
+                secret_code_hash: hash256("other secret code".to_owned()),
+            }],
+            ..terms
+        };
+        for terms in [other_seller, other_hashes] {
+            assert!(!verify_signature(
+                &escrow_signing_message(
+                    &DOMAIN,
+                    "escrow-a",
+                    EscrowAction::Accept(terms),
+                    Amount::ZERO
+                ),
+                &signature,
+                &seller.public_key(),
+            ));
+        }
+    }
+
+    #[test]
+    fn panel_is_impartial() {
+        let secp = Secp256k1::new();
+        let pubkey = |seed: u8| {
+            KeyPair::from_seckey_slice(&secp, &[seed; 32])
+                .expect("valid secret key")
+                .public_key()
+        };
+        let (buyer, seller) = (pubkey(1), pubkey(2));
+        let (arbiter_a, arbiter_b) = (pubkey(3), pubkey(4));
+
+        assert!(is_valid_panel(&[arbiter_a, arbiter_b], 2, &buyer, &seller));
+
+        // the buyer or the seller cannot decide their own dispute
+        assert!(!is_valid_panel(&[arbiter_a, buyer], 1, &buyer, &seller));
+        assert!(!is_valid_panel(&[seller, arbiter_b], 1, &buyer, &seller));
+
+        // an arbiter cannot vote twice
+        assert!(!is_valid_panel(&[arbiter_a, arbiter_a], 2, &buyer, &seller));
+
+        // the threshold has to be reachable
+        assert!(!is_valid_panel(&[], 0, &buyer, &seller));
+        assert!(!is_valid_panel(&[arbiter_a, arbiter_b], 0, &buyer, &seller));
+        assert!(!is_valid_panel(&[arbiter_a, arbiter_b], 3, &buyer, &seller));
+    }
+}
@@ -1,7 +1,11 @@
 use fedimint_core::db::DatabaseRecord;
 use fedimint_core::encoding::{Decodable, Encodable};
-use fedimint_core::{impl_db_record, Amount};
-use fedimint_escrow_common::EscrowStates;
+use fedimint_core::{impl_db_lookup, impl_db_record, Amount, PeerId};
+use fedimint_escrow_common::endpoints::DisputeEvidence;
+use fedimint_escrow_common::{
+    ArbiterDecision, ArbiterVote, Disputer, EscrowMilestone, EscrowRole, EscrowStates, EscrowTerms,
+    FallbackOutcome, HashLock, Milestone, ACCEPTANCE_WINDOW_SECS, APPEAL_WINDOW_SECS,
+};
 use secp256k1::PublicKey;
 use serde::{Deserialize, Serialize};
 use strum_macros::EnumIter;
@@ -11,6 +15,11 @@ use strum_macros::EnumIter;
 #[derive(Clone, Debug, EnumIter, Encodable, Decodable)]
 pub enum DbKeyPrefix {
     Escrow = 0x04,
+    UnixTimeVote = 0x05,
+    EscrowParticipant = 0x06,
+    DisputeEvidence = 0x07,
+    ArbiterVote = 0x08,
+    OpenEscrowCount = 0x09,
 }
 
 impl DatabaseRecord for DbKeyPrefix {
@@ -31,16 +40,155 @@ pub struct EscrowKey {
     pub escrow_id: String,
 }
 
+/// The prefix to lookup all the escrows
+#[derive(Debug, Encodable, Decodable)]
+pub struct EscrowKeyPrefix;
+
 /// The structure for the database record
 #[derive(Debug, Clone, Eq, PartialEq, Encodable, Decodable, Serialize, Deserialize)]
 pub struct EscrowValue {
     pub buyer_pubkey: PublicKey,
     pub seller_pubkey: PublicKey,
-    pub arbiter_pubkey: PublicKey,
+    /// the panel of arbiters deciding a dispute
+    pub arbiter_pubkeys: Vec<PublicKey>,
+    /// the number of arbiters that have to agree on a decision
+    pub arbiter_threshold: u32,
+    /// the ecash left in the escrow, which excludes the released milestones
+    /// and the arbiter fee
     pub amount: Amount,
-    pub secret_code_hash: String,
+    pub milestones: Vec<EscrowMilestone>,
     pub max_arbiter_fee: Amount,
     pub state: EscrowStates,
+    /// the consensus unix time at which the escrow was created
+    pub created_at: u64,
+    pub deadline: u64,
+    /// who opened the dispute, if the escrow was disputed
+    pub disputer: Option<Disputer>,
+    /// the consensus unix time at which the escrow was disputed
+    pub disputed_at: Option<u64>,
+    /// the seconds after the dispute within which the arbiters have to decide
+    pub arbitration_timeout_secs: u64,
+    /// the outcome of the dispute if the arbiters do not decide in time
+    pub fallback: FallbackOutcome,
+    /// how the secret codes of the milestones are hashed
+    pub hash_lock: HashLock,
+    /// whether the fallback outcome was applied as the arbiters did not decide
+    /// in time
+    pub fallback_applied: bool,
+    /// the decision of the arbiters once the dispute is decided
+    pub arbiter_decision: Option<ArbiterDecision>,
+    /// the consensus unix time at which the arbiters decided
+    pub decided_at: Option<u64>,
+    /// the fee deducted for the decision of the arbiters
+    pub arbiter_fee: Amount,
+    /// the vote that decided the dispute, the arbiters voting for the same
+    /// decision and fee share the arbiter fee
+    pub decided_vote: Option<ArbiterVote>,
+    /// the arbiter the losing party can appeal the decision of the arbiters to
+    pub appeal_arbiter: Option<PublicKey>,
+    /// who appealed the decision of the arbiters, if it was appealed
+    pub appealed_by: Option<Disputer>,
+    /// the consensus unix time at which the decision was appealed
+    pub appealed_at: Option<u64>,
+    /// the decision of the appeal arbiter, superseding the one of the arbiters
+    pub appeal_decision: Option<ArbiterDecision>,
+    /// the shares of the arbiter fee not claimed yet by the arbiters who made
+    /// the decision
+    pub unclaimed_arbiter_fee: Amount,
+    /// the ecash released by the buyer that the seller has not claimed yet
+    pub released_unclaimed: Amount,
+    /// the amount paid out to the buyer so far
+    pub buyer_withdrawn: Amount,
+    /// the amount paid out to the seller so far
+    pub seller_withdrawn: Amount,
+}
+
+impl EscrowValue {
+    /// The terms the seller agrees to by accepting the escrow
+    pub fn terms(&self) -> EscrowTerms {
+        EscrowTerms {
+            amount: self.amount,
+            buyer_pubkey: self.buyer_pubkey,
+            seller_pubkey: self.seller_pubkey,
+            arbiter_pubkeys: self.arbiter_pubkeys.clone(),
+            arbiter_threshold: self.arbiter_threshold,
+            appeal_arbiter: self.appeal_arbiter,
+            milestones: self
+                .milestones
+                .iter()
+                .map(|milestone| Milestone {
+                    amount: milestone.amount,
+                    secret_code_hash: milestone.secret_code_hash.clone(),
+                })
+                .collect(),
+            max_arbiter_fee: self.max_arbiter_fee,
+            deadline: self.deadline,
+            arbitration_timeout_secs: self.arbitration_timeout_secs,
+            fallback: self.fallback,
+            hash_lock: self.hash_lock,
+        }
+    }
+
+    /// The unix time after which the buyer can withdraw the escrow if the
+    /// seller has not accepted it, at the latest its deadline
+    pub fn acceptance_deadline(&self) -> u64 {
+        self.created_at
+            .saturating_add(ACCEPTANCE_WINDOW_SECS)
+            .min(self.deadline)
+    }
+
+    /// The decision in force, the one of the appeal arbiter if the decision of
+    /// the arbiters was appealed, or the fallback outcome if the arbiters did
+    /// not decide in time
+    pub fn decision(&self) -> Option<ArbiterDecision> {
+        self.appeal_decision
+            .clone()
+            .or_else(|| self.arbiter_decision.clone())
+            .or_else(|| self.fallback_applied.then(|| self.fallback.decision()))
+    }
+
+    /// The unix time after which the fallback outcome applies, if the escrow
+    /// was disputed
+    pub fn arbitration_deadline(&self) -> Option<u64> {
+        self.disputed_at
+            .map(|disputed_at| disputed_at.saturating_add(self.arbitration_timeout_secs))
+    }
+
+    /// The unix time until which the decision of the arbiters can be appealed,
+    /// if the escrow has an appeal arbiter and the arbiters decided
+    pub fn appeal_deadline(&self) -> Option<u64> {
+        self.appeal_arbiter?;
+        self.decided_at
+            .map(|decided_at| decided_at + APPEAL_WINDOW_SECS)
+    }
+
+    /// The unix time after which the decision of the arbiters stands, if the
+    /// decision was appealed. The appeal arbiter has as much time to decide as
+    /// the arbiters had.
+    pub fn appeal_decision_deadline(&self) -> Option<u64> {
+        self.appealed_at
+            .map(|appealed_at| appealed_at.saturating_add(self.arbitration_timeout_secs))
+    }
+
+    /// The ecash that is still locked in the escrow
+    pub fn locked_amount(&self) -> Amount {
+        self.amount
+            .saturating_sub(self.buyer_withdrawn)
+            .saturating_sub(self.seller_withdrawn)
+    }
+
+    /// The ecash of the escrow the federation still owes, the locked ecash
+    /// until the escrow is resolved, and even after that the fee shares not
+    /// claimed yet by the arbiters and the ecash released by the buyer but not
+    /// claimed yet by the seller
+    pub fn liability(&self) -> Amount {
+        let locked_amount = if self.state.is_resolved() {
+            Amount::ZERO
+        } else {
+            self.locked_amount()
+        };
+        locked_amount + self.unclaimed_arbiter_fee + self.released_unclaimed
+    }
 }
 
 // Implement database record creation and lookup
@@ -49,3 +197,130 @@ impl_db_record!(
     value = EscrowValue,
     db_prefix = DbKeyPrefix::Escrow,
 );
+impl_db_lookup!(key = EscrowKey, query_prefix = EscrowKeyPrefix);
+
+/// The unix time voted by a guardian, keyed by its peer id
+#[derive(Debug, Clone, Copy, Encodable, Decodable, Eq, PartialEq, Hash, Serialize)]
+pub struct UnixTimeVoteKey(pub PeerId);
+
+/// The prefix to lookup the unix time votes of all guardians
+#[derive(Debug, Encodable, Decodable)]
+pub struct UnixTimeVotePrefix;
+
+impl_db_record!(
+    key = UnixTimeVoteKey,
+    value = u64,
+    db_prefix = DbKeyPrefix::UnixTimeVote,
+);
+impl_db_lookup!(key = UnixTimeVoteKey, query_prefix = UnixTimeVotePrefix);
+
+/// The index of the escrows by the public keys of their participants
+#[derive(Debug, Clone, Encodable, Decodable, Eq, PartialEq, Hash, Serialize)]
+pub struct EscrowParticipantKey {
+    pub pubkey: PublicKey,
+    pub role: EscrowRole,
+    pub escrow_id: String,
+}
+
+/// The prefix to lookup the index of all participants
+#[derive(Debug, Encodable, Decodable)]
+pub struct EscrowParticipantPrefix;
+
+/// The prefix to lookup the escrows a public key takes part in
+#[derive(Debug, Encodable, Decodable)]
+pub struct EscrowParticipantPubkeyPrefix {
+    pub pubkey: PublicKey,
+}
+
+/// The prefix to lookup the escrows a public key takes part in with a role
+#[derive(Debug, Encodable, Decodable)]
+pub struct EscrowParticipantRolePrefix {
+    pub pubkey: PublicKey,
+    pub role: EscrowRole,
+}
+
+impl_db_record!(
+    key = EscrowParticipantKey,
+    value = (),
+    db_prefix = DbKeyPrefix::EscrowParticipant,
+);
+impl_db_lookup!(
+    key = EscrowParticipantKey,
+    query_prefix = EscrowParticipantPrefix,
+    query_prefix = EscrowParticipantPubkeyPrefix,
+    query_prefix = EscrowParticipantRolePrefix
+);
+
+/// The evidence attached by a party to the dispute of an escrow
+#[derive(Debug, Clone, Encodable, Decodable, Eq, PartialEq, Hash, Serialize)]
+pub struct DisputeEvidenceKey {
+    pub escrow_id: String,
+    pub party: Disputer,
+}
+
+/// The prefix to lookup the evidence of all disputes
+#[derive(Debug, Encodable, Decodable)]
+pub struct DisputeEvidencePrefix;
+
+/// The prefix to lookup the evidence attached to the dispute of an escrow
+#[derive(Debug, Encodable, Decodable)]
+pub struct DisputeEvidenceEscrowPrefix {
+    pub escrow_id: String,
+}
+
+impl_db_record!(
+    key = DisputeEvidenceKey,
+    value = DisputeEvidence,
+    db_prefix = DbKeyPrefix::DisputeEvidence,
+);
+impl_db_lookup!(
+    key = DisputeEvidenceKey,
+    query_prefix = DisputeEvidencePrefix,
+    query_prefix = DisputeEvidenceEscrowPrefix
+);
+
+/// The vote of an arbiter of the panel on the dispute of an escrow
+#[derive(Debug, Clone, Encodable, Decodable, Eq, PartialEq, Hash, Serialize)]
+pub struct ArbiterVoteKey {
+    pub escrow_id: String,
+    pub arbiter: PublicKey,
+}
+
+/// The prefix to lookup the votes on all disputes
+#[derive(Debug, Encodable, Decodable)]
+pub struct ArbiterVotePrefix;
+
+/// The prefix to lookup the votes on the dispute of an escrow
+#[derive(Debug, Encodable, Decodable)]
+pub struct ArbiterVoteEscrowPrefix {
+    pub escrow_id: String,
+}
+
+impl_db_record!(
+    key = ArbiterVoteKey,
+    value = ArbiterVote,
+    db_prefix = DbKeyPrefix::ArbiterVote,
+);
+impl_db_lookup!(
+    key = ArbiterVoteKey,
+    query_prefix = ArbiterVotePrefix,
+    query_prefix = ArbiterVoteEscrowPrefix
+);
+
+/// The number of unresolved escrows of a buyer, keyed by its public key
+#[derive(Debug, Clone, Copy, Encodable, Decodable, Eq, PartialEq, Hash, Serialize)]
+pub struct OpenEscrowCountKey(pub PublicKey);
+
+/// The prefix to lookup the open escrow counts of all buyers
+#[derive(Debug, Encodable, Decodable)]
+pub struct OpenEscrowCountPrefix;
+
+impl_db_record!(
+    key = OpenEscrowCountKey,
+    value = u64,
+    db_prefix = DbKeyPrefix::OpenEscrowCount,
+);
+impl_db_lookup!(
+    key = OpenEscrowCountKey,
+    query_prefix = OpenEscrowCountPrefix
+);
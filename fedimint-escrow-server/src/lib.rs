@@ -1,18 +1,26 @@
 mod db;
 
 use std::collections::BTreeMap;
+use std::time::UNIX_EPOCH;
 
-use anyhow::bail;
+use anyhow::{bail, Context};
 use async_trait::async_trait;
 pub use db::EscrowValue;
-use db::{DbKeyPrefix, EscrowKey};
+use db::{
+    ArbiterVoteEscrowPrefix, ArbiterVoteKey, ArbiterVotePrefix, DbKeyPrefix,
+    DisputeEvidenceEscrowPrefix, DisputeEvidenceKey, DisputeEvidencePrefix, EscrowKey,
+    EscrowKeyPrefix, EscrowParticipantKey, EscrowParticipantPrefix, EscrowParticipantPubkeyPrefix,
+    EscrowParticipantRolePrefix, OpenEscrowCountKey, OpenEscrowCountPrefix, UnixTimeVoteKey,
+    UnixTimeVotePrefix,
+};
 use fedimint_core::config::{
     ConfigGenModuleParams, DkgResult, ServerModuleConfig, ServerModuleConsensusConfig,
     TypedServerModuleConfig, TypedServerModuleConsensusConfig,
 };
 use fedimint_core::core::ModuleInstanceId;
 use fedimint_core::db::{
-    DatabaseTransaction, DatabaseVersion, IDatabaseTransactionOpsCoreTyped, NonCommittable,
+    DatabaseTransaction, DatabaseVersion, IDatabaseTransactionOpsCore,
+    IDatabaseTransactionOpsCoreTyped, MigrationFn, NonCommittable,
 };
 use fedimint_core::module::audit::Audit;
 use fedimint_core::module::{
@@ -26,15 +34,22 @@ use fedimint_escrow_common::config::{
     EscrowClientConfig, EscrowConfig, EscrowConfigConsensus, EscrowConfigLocal,
     EscrowConfigPrivate, EscrowGenParams,
 };
-use fedimint_escrow_common::endpoints::{EscrowInfo, GET_MODULE_INFO};
+use fedimint_escrow_common::endpoints::{
+    DisputeEvidence, DisputeSummary, EscrowInfo, EscrowSummary, ListEscrowsRequest,
+    ListEscrowsResponse, ARBITER_INBOX, GET_DISPUTE_EVIDENCE, GET_MODULE_INFO,
+    LIST_ESCROWS_BY_PUBKEY, MAX_LIST_ESCROWS_LIMIT,
+};
 use fedimint_escrow_common::{
-    hash256, ArbiterDecision, Disputer, EscrowCommonInit, EscrowConsensusItem, EscrowInput,
-    EscrowInputError, EscrowModuleTypes, EscrowOutput, EscrowOutputError, EscrowOutputOutcome,
-    EscrowStates, MODULE_CONSENSUS_VERSION,
+    escrow_signing_message, is_valid_hash, is_valid_panel, verify_signature, ArbiterDecision,
+    ArbiterVote, Disputer, EscrowAction, EscrowCommonInit, EscrowConsensusItem, EscrowInput,
+    EscrowInputError, EscrowMilestone, EscrowModuleTypes, EscrowOutput, EscrowOutputError,
+    EscrowOutputOutcome, EscrowRole, EscrowStates, MilestoneState, MAX_BPS,
+    MODULE_CONSENSUS_VERSION,
 };
 use fedimint_server::config::CORE_CONSENSUS_VERSION;
-use futures::StreamExt;
-use secp256k1::{Message, Secp256k1};
+use futures::{FutureExt, StreamExt};
+use secp256k1::PublicKey;
+use sha2::{Digest, Sha256};
 use strum::IntoEnumIterator;
 
 /// Generates the module
@@ -44,7 +59,7 @@ pub struct EscrowInit;
 #[async_trait]
 impl ModuleInit for EscrowInit {
     type Common = EscrowCommonInit;
-    const DATABASE_VERSION: DatabaseVersion = DatabaseVersion(1);
+    const DATABASE_VERSION: DatabaseVersion = DatabaseVersion(2);
 
     /// Dumps all database items for debugging
     async fn dump_database(
@@ -62,13 +77,63 @@ impl ModuleInit for EscrowInit {
                 DbKeyPrefix::Escrow => {
                     push_db_pair_items!(
                         dbtx,
-                        DbKeyPrefix::Escrow,
+                        EscrowKeyPrefix,
                         EscrowKey,
                         EscrowValue,
                         items,
                         "Escrow"
                     );
                 }
+                DbKeyPrefix::EscrowParticipant => {
+                    push_db_pair_items!(
+                        dbtx,
+                        EscrowParticipantPrefix,
+                        EscrowParticipantKey,
+                        (),
+                        items,
+                        "Escrow Participants"
+                    );
+                }
+                DbKeyPrefix::DisputeEvidence => {
+                    push_db_pair_items!(
+                        dbtx,
+                        DisputeEvidencePrefix,
+                        DisputeEvidenceKey,
+                        DisputeEvidence,
+                        items,
+                        "Dispute Evidence"
+                    );
+                }
+                DbKeyPrefix::ArbiterVote => {
+                    push_db_pair_items!(
+                        dbtx,
+                        ArbiterVotePrefix,
+                        ArbiterVoteKey,
+                        ArbiterVote,
+                        items,
+                        "Arbiter Votes"
+                    );
+                }
+                DbKeyPrefix::UnixTimeVote => {
+                    push_db_pair_items!(
+                        dbtx,
+                        UnixTimeVotePrefix,
+                        UnixTimeVoteKey,
+                        u64,
+                        items,
+                        "Unix Time Votes"
+                    );
+                }
+                DbKeyPrefix::OpenEscrowCount => {
+                    push_db_pair_items!(
+                        dbtx,
+                        OpenEscrowCountPrefix,
+                        OpenEscrowCountKey,
+                        u64,
+                        items,
+                        "Open Escrow Counts"
+                    );
+                }
             }
         }
         Box::new(items.into_iter())
@@ -98,7 +163,12 @@ impl ServerModuleInit for EscrowInit {
 
     /// Initialize the module
     async fn init(&self, args: &ServerModuleInitArgs<Self>) -> anyhow::Result<DynServerModule> {
-        Ok(Escrow::new(args.cfg().to_typed()?).into())
+        Ok(Escrow::new(
+            args.cfg().to_typed()?,
+            args.our_peer_id(),
+            args.num_peers().total(),
+        )
+        .into())
     }
 
     /// Generates configs for all peers in a trusted manner for testing
@@ -107,7 +177,11 @@ impl ServerModuleInit for EscrowInit {
         peers: &[PeerId],
         params: &ConfigGenModuleParams,
     ) -> BTreeMap<PeerId, ServerModuleConfig> {
-        let params = self.parse_params(params).unwrap();
+        // the trusted dealer is only used in tests, which should not go on with
+        // invalid params
+        let consensus = self
+            .parse_consensus_params(params, rand::random())
+            .expect("Invalid escrow module params");
         // Generate a config for each peer
         peers
             .iter()
@@ -115,10 +189,7 @@ impl ServerModuleInit for EscrowInit {
                 let config = EscrowConfig {
                     local: EscrowConfigLocal {},
                     private: EscrowConfigPrivate,
-                    consensus: EscrowConfigConsensus {
-                        deposit_fee: params.consensus.deposit_fee,
-                        max_arbiter_fee_bps: params.consensus.max_arbiter_fee_bps,
-                    },
+                    consensus: consensus.clone(),
                 };
                 (peer, config.to_erased())
             })
@@ -128,18 +199,27 @@ impl ServerModuleInit for EscrowInit {
     /// Generates configs for all peers in an untrusted manner
     async fn distributed_gen(
         &self,
-        _peers: &PeerHandle,
+        peers: &PeerHandle,
         params: &ConfigGenModuleParams,
     ) -> DkgResult<ServerModuleConfig> {
-        let params = self.parse_params(params).unwrap();
+        // every guardian contributes to the signing domain, so that none of them
+        // chooses it alone
+        let contribution: [u8; 32] = rand::random();
+        let mut contributions = peers
+            .exchange_with_peers("escrow-signing-domain".to_string(), contribution)
+            .await?;
+        contributions.insert(peers.our_id, contribution);
+        let consensus = self.parse_consensus_params(params, signing_domain(&contributions))?;
+
+        let peer_consensus = peers
+            .exchange_with_peers("escrow-consensus".to_string(), consensus.clone())
+            .await?;
+        check_peer_consensus(&consensus, peer_consensus)?;
 
         Ok(EscrowConfig {
             local: EscrowConfigLocal {},
             private: EscrowConfigPrivate,
-            consensus: EscrowConfigConsensus {
-                deposit_fee: params.consensus.deposit_fee,
-                max_arbiter_fee_bps: params.consensus.max_arbiter_fee_bps,
-            },
+            consensus,
         }
         .to_erased())
     }
@@ -153,15 +233,51 @@ impl ServerModuleInit for EscrowInit {
         Ok(EscrowClientConfig {
             deposit_fee: config.deposit_fee,
             max_arbiter_fee_bps: config.max_arbiter_fee_bps,
+            min_escrow_amount: config.min_escrow_amount,
+            max_escrow_amount: config.max_escrow_amount,
+            max_escrow_id_length: config.max_escrow_id_length,
+            min_arbitration_timeout_secs: config.min_arbitration_timeout_secs,
+            signing_domain: config.signing_domain,
         })
     }
 
+    /// The escrows of database version 1 cannot be decoded anymore, see
+    /// [`migrate_db_v1`]
+    fn get_database_migrations(&self) -> BTreeMap<DatabaseVersion, MigrationFn> {
+        let mut migrations = BTreeMap::<_, MigrationFn>::new();
+        migrations.insert(DatabaseVersion(1), |dbtx| migrate_db_v1(dbtx).boxed());
+        migrations
+    }
+
     fn validate_config(
         &self,
         _identity: &PeerId,
-        _config: ServerModuleConfig,
+        config: ServerModuleConfig,
     ) -> anyhow::Result<()> {
-        Ok(())
+        let config = config.to_typed::<EscrowConfig>()?;
+        config
+            .consensus
+            .validate()
+            .context("Invalid escrow consensus config")
+    }
+}
+
+impl EscrowInit {
+    /// Parses the params into the consensus config with the signing domain and
+    /// checks its limits
+    fn parse_consensus_params(
+        &self,
+        params: &ConfigGenModuleParams,
+        signing_domain: [u8; 32],
+    ) -> anyhow::Result<EscrowConfigConsensus> {
+        let params = self
+            .parse_params(params)
+            .context("Failed to parse escrow module params")?;
+        let consensus = params.consensus.to_config(signing_domain);
+        consensus
+            .validate()
+            .context("Invalid escrow module params")?;
+        Ok(consensus)
     }
 }
 
@@ -169,6 +285,9 @@ impl ServerModuleInit for EscrowInit {
 #[derive(Debug)]
 pub struct Escrow {
     pub cfg: EscrowConfig,
+    our_peer_id: PeerId,
+    /// Number of guardians, needed to find the consensus time from their votes
+    peer_count: usize,
 }
 
 /// Implementation of consensus for the server module
@@ -180,18 +299,39 @@ impl ServerModule for Escrow {
 
     async fn consensus_proposal(
         &self,
-        _dbtx: &mut DatabaseTransaction<'_>,
+        dbtx: &mut DatabaseTransaction<'_>,
     ) -> Vec<EscrowConsensusItem> {
-        Vec::new()
+        let unix_time = unix_time_vote();
+
+        // only propose once our clock has moved past our last accepted vote
+        let our_vote = dbtx
+            .get_value(&UnixTimeVoteKey(self.our_peer_id))
+            .await
+            .unwrap_or(0);
+
+        if unix_time > our_vote {
+            vec![EscrowConsensusItem { unix_time }]
+        } else {
+            Vec::new()
+        }
     }
 
     async fn process_consensus_item<'a, 'b>(
         &'a self,
-        _dbtx: &mut DatabaseTransaction<'b>,
-        _consensus_item: EscrowConsensusItem,
-        _peer_id: PeerId,
+        dbtx: &mut DatabaseTransaction<'b>,
+        consensus_item: EscrowConsensusItem,
+        peer_id: PeerId,
     ) -> anyhow::Result<()> {
-        bail!("The escrow module does not use consensus items");
+        let current_vote = dbtx.get_value(&UnixTimeVoteKey(peer_id)).await.unwrap_or(0);
+
+        if consensus_item.unix_time <= current_vote {
+            bail!("Unix time vote is redundant");
+        }
+
+        dbtx.insert_entry(&UnixTimeVoteKey(peer_id), &consensus_item.unix_time)
+            .await;
+
+        Ok(())
     }
 
     async fn process_input<'a, 'b, 'c>(
@@ -199,37 +339,120 @@ impl ServerModule for Escrow {
         dbtx: &mut DatabaseTransaction<'c>,
         input: &'b EscrowInput,
     ) -> Result<InputMeta, EscrowInputError> {
+        // an id longer than the federation allows can not refer to any escrow
+        if input.escrow_id().len() > self.cfg.consensus.max_escrow_id_length as usize {
+            return Err(EscrowInputError::EscrowIdTooLong);
+        }
         match input {
-            EscrowInput::ClamingWithoutDispute(escrow_input) => {
+            EscrowInput::Accepting(escrow_input) => {
                 let mut escrow_value = self
-                    .get_escrow_value(dbtx, escrow_input.escrow_id.clone())
+                    .get_unresolved_escrow_value(dbtx, escrow_input.escrow_id.clone())
                     .await?;
 
-                // check the signature of seller
-                let secp = Secp256k1::new();
-                let message = Message::from_slice(&escrow_input.hashed_message).expect("32 bytes");
-                let (xonly_pubkey, _parity) = escrow_value.seller_pubkey.x_only_public_key();
+                // the escrow should be waiting for the seller to accept it
+                if escrow_value.state != EscrowStates::PendingAcceptance {
+                    return Err(EscrowInputError::EscrowNotPendingAcceptance);
+                }
 
-                if !secp
-                    .verify_schnorr(&escrow_input.signature, &message, &xonly_pubkey)
-                    .is_ok()
+                // the seller cannot accept anymore once the buyer can withdraw the escrow
+                if self.consensus_unix_time(dbtx).await >= escrow_value.acceptance_deadline() {
+                    return Err(EscrowInputError::AcceptanceTimedOut);
+                }
+
+                // check the signature of seller, which covers the terms of the escrow
+                let message = escrow_signing_message(
+                    &self.cfg.consensus.signing_domain,
+                    &escrow_input.escrow_id,
+                    EscrowAction::Accept(escrow_value.terms()),
+                    Amount::ZERO,
+                );
+                if escrow_input.seller != escrow_value.seller_pubkey
+                    || !verify_signature(&message, &escrow_input.signature, &escrow_input.seller)
                 {
+                    return Err(EscrowInputError::UnauthorizedToAccept);
+                }
+
+                escrow_value.state = EscrowStates::Open;
+
+                // Update the escrow value in the database
+                self.update_escrow_value(dbtx, escrow_input.escrow_id.clone(), &escrow_value)
+                    .await;
+
+                Ok(InputMeta {
+                    amount: TransactionItemAmount {
+                        amount: Amount::ZERO,
+                        fee: Amount::ZERO,
+                    },
+                    pub_key: escrow_input.seller,
+                })
+            }
+            EscrowInput::ClamingWithoutDispute(escrow_input) => {
+                let mut escrow_value = self
+                    .get_unresolved_escrow_value(dbtx, escrow_input.escrow_id.clone())
+                    .await?;
+
+                // the seller can only use the secret code if nobody has disputed the escrow
+                if escrow_value.state != EscrowStates::Open {
+                    return Err(EscrowInputError::InvalidStateForClaimingEscrow);
+                }
+
+                let milestone = escrow_value
+                    .milestones
+                    .get_mut(escrow_input.milestone as usize)
+                    .ok_or(EscrowInputError::MilestoneNotFound)?;
+                if milestone.state != MilestoneState::Pending {
+                    return Err(EscrowInputError::MilestoneAlreadyReleased);
+                }
+
+                // the payout is the amount of the milestone, not the one chosen by the client
+                check_claim_amount(escrow_input.amount, milestone.amount)?;
+
+                // check the signature of seller
+                let message = escrow_signing_message(
+                    &self.cfg.consensus.signing_domain,
+                    &escrow_input.escrow_id,
+                    EscrowAction::ClaimWithoutDispute {
+                        milestone: escrow_input.milestone,
+                    },
+                    escrow_input.amount,
+                );
+                if !verify_signature(
+                    &message,
+                    &escrow_input.signature,
+                    &escrow_value.seller_pubkey,
+                ) {
                     return Err(EscrowInputError::InvalidSeller);
                 }
 
-                // the secret code when hashed should be the same as the one in the db
-                if escrow_value.secret_code_hash != hash256(escrow_input.secret_code.clone()) {
+                // the secret code when hashed should be the same as the one of the milestone
+                if escrow_value
+                    .hash_lock
+                    .hash(&escrow_input.secret_code)
+                    .as_ref()
+                    != Some(&milestone.secret_code_hash)
+                {
                     return Err(EscrowInputError::InvalidSecretCode);
                 }
-                escrow_value.state = EscrowStates::ResolvedWithoutDispute;
+                milestone.state = MilestoneState::Released;
+                let milestone_amount = milestone.amount;
+                escrow_value.amount = escrow_value.amount - milestone_amount;
+
+                // the escrow is resolved once all of its milestones are released
+                if escrow_value
+                    .milestones
+                    .iter()
+                    .all(|milestone| milestone.state == MilestoneState::Released)
+                {
+                    escrow_value.state = EscrowStates::ResolvedWithoutDispute;
+                }
 
                 // Update the escrow value in the database
-                let escrow_key = self.get_escrow_key(escrow_input.escrow_id.clone()).await;
-                dbtx.insert_entry(&escrow_key, &escrow_value).await;
+                self.update_escrow_value(dbtx, escrow_input.escrow_id.clone(), &escrow_value)
+                    .await;
 
                 Ok(InputMeta {
                     amount: TransactionItemAmount {
-                        amount: escrow_input.amount,
+                        amount: milestone_amount,
                         fee: Amount::ZERO,
                     },
                     pub_key: escrow_value.seller_pubkey, // the one who is getting the ecash
@@ -237,7 +460,7 @@ impl ServerModule for Escrow {
             }
             EscrowInput::Disputing(escrow_input) => {
                 let mut escrow_value = self
-                    .get_escrow_value(dbtx, escrow_input.escrow_id.clone())
+                    .get_unresolved_escrow_value(dbtx, escrow_input.escrow_id.clone())
                     .await?;
 
                 // Determine who is disputing
@@ -249,25 +472,17 @@ impl ServerModule for Escrow {
                     return Err(EscrowInputError::UnauthorizedToDispute);
                 };
 
-                // check the signature of disputer
-                let secp = Secp256k1::new();
-                let message = Message::from_slice(&escrow_input.hashed_message).expect("32 bytes");
-                let xonly_pubkey = match disputer {
-                    Disputer::Buyer => {
-                        let (xonly, _parity) = escrow_value.buyer_pubkey.x_only_public_key();
-                        xonly
-                    }
-                    Disputer::Seller => {
-                        let (xonly, _parity) = escrow_value.seller_pubkey.x_only_public_key();
-                        xonly
-                    }
-                };
-
-                if !secp
-                    .verify_schnorr(&escrow_input.signature, &message, &xonly_pubkey)
-                    .is_ok()
-                {
-                    return Err(EscrowInputError::InvalidArbiter);
+                // check the signature of disputer, which also covers the evidence
+                let message = escrow_signing_message(
+                    &self.cfg.consensus.signing_domain,
+                    &escrow_input.escrow_id,
+                    EscrowAction::Dispute {
+                        evidence: escrow_input.evidence.clone(),
+                    },
+                    Amount::ZERO,
+                );
+                if !verify_signature(&message, &escrow_input.signature, &escrow_input.disputer) {
+                    return Err(EscrowInputError::UnauthorizedToDispute);
                 }
 
                 match escrow_value.state {
@@ -276,13 +491,44 @@ impl ServerModule for Escrow {
                             Disputer::Buyer => EscrowStates::DisputedByBuyer,
                             Disputer::Seller => EscrowStates::DisputedBySeller,
                         };
+                        // the dispute is only about the milestones not released yet
+                        for milestone in escrow_value
+                            .milestones
+                            .iter_mut()
+                            .filter(|milestone| milestone.state == MilestoneState::Pending)
+                        {
+                            milestone.state = MilestoneState::Disputed;
+                        }
+                        // the arbiter works through the disputes by their age
+                        escrow_value.disputer = Some(disputer);
+                        escrow_value.disputed_at = Some(self.consensus_unix_time(dbtx).await);
                     }
                     _ => return Err(EscrowInputError::InvalidStateForInitiatingDispute),
                 }
 
+                // the reason of the dispute is stored alongside the escrow for the arbiter
+                if let Some(evidence) = &escrow_input.evidence {
+                    if !evidence.is_within_size_limit() {
+                        return Err(EscrowInputError::EvidenceTooLarge);
+                    }
+                    let dispute_evidence = DisputeEvidence {
+                        party: disputer,
+                        submitted_at: self.consensus_unix_time(dbtx).await,
+                        evidence: evidence.clone(),
+                    };
+                    dbtx.insert_entry(
+                        &DisputeEvidenceKey {
+                            escrow_id: escrow_input.escrow_id.clone(),
+                            party: disputer,
+                        },
+                        &dispute_evidence,
+                    )
+                    .await;
+                }
+
                 // Update the escrow value in the database
-                let escrow_key = self.get_escrow_key(escrow_input.escrow_id.clone()).await;
-                dbtx.insert_entry(&escrow_key, &escrow_value).await;
+                self.update_escrow_value(dbtx, escrow_input.escrow_id.clone(), &escrow_value)
+                    .await;
 
                 Ok(InputMeta {
                     amount: TransactionItemAmount {
@@ -294,247 +540,1890 @@ impl ServerModule for Escrow {
             }
             EscrowInput::ArbiterDecision(escrow_input) => {
                 let mut escrow_value = self
-                    .get_escrow_value(dbtx, escrow_input.escrow_id.clone())
+                    .get_unresolved_escrow_value(dbtx, escrow_input.escrow_id.clone())
                     .await?;
 
-                // the escrow state should be disputed for the arbiter to take decision
+                // the escrow state should be disputed for the arbiters to take decision
                 if escrow_value.state != EscrowStates::DisputedByBuyer
                     && escrow_value.state != EscrowStates::DisputedBySeller
                 {
                     return Err(EscrowInputError::EscrowNotDisputed);
                 }
 
-                // check the signature of arbiter
-                let secp = Secp256k1::new();
-                let message = Message::from_slice(&escrow_input.hashed_message).expect("32 bytes");
-                let (xonly_pubkey, _parity) = escrow_value.arbiter_pubkey.x_only_public_key();
+                // the arbiters cannot decide anymore once the arbitration deadline passed
+                if let Some(arbitration_deadline) = escrow_value.arbitration_deadline() {
+                    if self.consensus_unix_time(dbtx).await >= arbitration_deadline {
+                        return Err(EscrowInputError::ArbitrationTimedOut);
+                    }
+                }
 
-                if !secp
-                    .verify_schnorr(&escrow_input.signature, &message, &xonly_pubkey)
-                    .is_ok()
-                {
+                // only the arbiters of the panel can vote
+                if !escrow_value.arbiter_pubkeys.contains(&escrow_input.arbiter) {
+                    return Err(EscrowInputError::ArbiterNotMatched);
+                }
+
+                // check the signature of arbiter, which also covers the decision and the fee
+                let message = escrow_signing_message(
+                    &self.cfg.consensus.signing_domain,
+                    &escrow_input.escrow_id,
+                    EscrowAction::ArbiterDecision {
+                        decision: escrow_input.arbiter_decision.clone(),
+                        fee: escrow_input.fee,
+                    },
+                    escrow_input.amount,
+                );
+                if !verify_signature(&message, &escrow_input.signature, &escrow_input.arbiter) {
                     return Err(EscrowInputError::InvalidArbiter);
                 }
 
-                // Validate arbiter's fee
-                if escrow_input.amount > escrow_value.max_arbiter_fee {
+                // the buyer share of a split can be at most the whole escrow
+                if let ArbiterDecision::Split { buyer_share_bps } = escrow_input.arbiter_decision {
+                    if buyer_share_bps > MAX_BPS {
+                        return Err(EscrowInputError::InvalidBuyerShare);
+                    }
+                }
+
+                // Validate arbiter's fee, which is paid out of the unreleased milestones
+                if escrow_input.fee > escrow_value.max_arbiter_fee
+                    || escrow_input.fee > escrow_value.amount
+                {
                     return Err(EscrowInputError::ArbiterFeeExceedsMaximum);
-                } else {
-                    // the contract amount is the amount of ecash in the contract - arbiter fee
-                    escrow_value.amount = escrow_value.amount - escrow_input.amount;
                 }
 
-                // Update the escrow state based on the arbiter's decision
-                match escrow_input.arbiter_decision {
-                    ArbiterDecision::BuyerWins => {
-                        escrow_value.state = EscrowStates::WaitingforBuyerToClaim;
-                    }
-                    ArbiterDecision::SellerWins => {
-                        escrow_value.state = EscrowStates::WaitingforSellerToClaim;
-                    }
+                // every arbiter votes only once
+                let vote_key = ArbiterVoteKey {
+                    escrow_id: escrow_input.escrow_id.clone(),
+                    arbiter: escrow_input.arbiter,
+                };
+                if dbtx.get_value(&vote_key).await.is_some() {
+                    return Err(EscrowInputError::ArbiterAlreadyVoted);
                 }
+                let mut vote = ArbiterVote {
+                    arbiter: escrow_input.arbiter,
+                    decision: escrow_input.arbiter_decision.clone(),
+                    fee: escrow_input.fee,
+                    fee_claimed: false,
+                };
+
+                // the dispute is decided once enough arbiters agree, this vote included
+                let agreeing_votes = dbtx
+                    .find_by_prefix(&ArbiterVoteEscrowPrefix {
+                        escrow_id: escrow_input.escrow_id.clone(),
+                    })
+                    .await
+                    .filter(|(_, arbiter_vote)| std::future::ready(arbiter_vote.agrees_with(&vote)))
+                    .count()
+                    .await
+                    + 1;
+                let payout = if agreeing_votes as u64 >= u64::from(escrow_value.arbiter_threshold) {
+                    // the fee is split among the arbiters making the decision, the one
+                    // deciding is paid right away and the others claim their share later
+                    let fee_share = vote.fee_share(escrow_value.arbiter_threshold);
+                    let arbiter_fee = fee_share * u64::from(escrow_value.arbiter_threshold);
+                    // the contract amount is the amount of ecash in the contract - arbiter fee
+                    escrow_value.amount = escrow_value.amount - arbiter_fee;
+                    escrow_value.unclaimed_arbiter_fee = arbiter_fee - fee_share;
+
+                    // Update the escrow state based on the arbiters' decision, which can be
+                    // appealed from now on if the escrow has an appeal arbiter
+                    escrow_value.state = escrow_input.arbiter_decision.claim_state();
+                    escrow_value.arbiter_decision = Some(escrow_input.arbiter_decision.clone());
+                    escrow_value.decided_at = Some(self.consensus_unix_time(dbtx).await);
+                    escrow_value.arbiter_fee = arbiter_fee;
+                    escrow_value.decided_vote = Some(vote.clone());
+                    vote.fee_claimed = true;
+                    fee_share
+                } else {
+                    Amount::ZERO
+                };
+
+                // the payout is the share of the fee if this vote decides the dispute
+                check_claim_amount(escrow_input.amount, payout)?;
+
+                dbtx.insert_new_entry(&vote_key, &vote).await;
 
                 // Update the escrow value in the database
-                let escrow_key = self.get_escrow_key(escrow_input.escrow_id.clone()).await;
-                dbtx.insert_entry(&escrow_key, &escrow_value).await;
+                self.update_escrow_value(dbtx, escrow_input.escrow_id.clone(), &escrow_value)
+                    .await;
 
                 Ok(InputMeta {
                     amount: TransactionItemAmount {
-                        amount: escrow_input.amount,
+                        amount: payout,
                         fee: Amount::ZERO,
                     },
-                    pub_key: escrow_value.arbiter_pubkey, // the one who is getting the ecash
+                    pub_key: escrow_input.arbiter, // the one who is getting the ecash
                 })
             }
-            EscrowInput::ClaimingAfterDispute(escrow_input) => {
+            EscrowInput::ClaimingArbiterFee(escrow_input) => {
+                // the fee can be claimed even after the escrow is resolved
                 let mut escrow_value = self
                     .get_escrow_value(dbtx, escrow_input.escrow_id.clone())
                     .await?;
-                match escrow_value.state {
-                    EscrowStates::WaitingforBuyerToClaim => {
-                        // check the signature of buyer
-                        let secp = Secp256k1::new();
-                        let message =
-                            Message::from_slice(&escrow_input.hashed_message).expect("32 bytes");
-                        let (xonly_pubkey, _parity) = escrow_value.buyer_pubkey.x_only_public_key();
-
-                        if !secp
-                            .verify_schnorr(&escrow_input.signature, &message, &xonly_pubkey)
-                            .is_ok()
-                        {
-                            return Err(EscrowInputError::InvalidArbiter);
-                        }
-                        escrow_value.state = EscrowStates::ResolvedWithDispute;
-
-                        // Update the escrow value in the database
-                        let escrow_key = self.get_escrow_key(escrow_input.escrow_id.clone()).await;
-                        dbtx.insert_entry(&escrow_key, &escrow_value).await;
-
-                        Ok(InputMeta {
-                            amount: TransactionItemAmount {
-                                amount: escrow_input.amount,
-                                fee: Amount::ZERO,
-                            },
-                            pub_key: escrow_value.seller_pubkey, // the one who is getting the ecash
-                        })
-                    }
-                    EscrowStates::WaitingforSellerToClaim => {
-                        // check the signature of buyer
-                        let secp = Secp256k1::new();
-                        let message =
-                            Message::from_slice(&escrow_input.hashed_message).expect("32 bytes");
-                        let (xonly_pubkey, _parity) =
-                            escrow_value.seller_pubkey.x_only_public_key();
-
-                        if !secp
-                            .verify_schnorr(&escrow_input.signature, &message, &xonly_pubkey)
-                            .is_ok()
-                        {
-                            return Err(EscrowInputError::InvalidArbiter);
-                        }
-                        escrow_value.state = EscrowStates::ResolvedWithDispute;
-
-                        // Update the escrow value in the database
-                        let escrow_key = self.get_escrow_key(escrow_input.escrow_id.clone()).await;
-                        dbtx.insert_entry(&escrow_key, &escrow_value).await;
-
-                        Ok(InputMeta {
-                            amount: TransactionItemAmount {
-                                amount: escrow_input.amount,
-                                fee: Amount::ZERO,
-                            },
-                            pub_key: escrow_value.seller_pubkey, // the one who is getting the ecash
-                        })
-                    }
-                    _ => return Err(EscrowInputError::InvalidStateForClaimingEscrow),
+
+                // only the arbiters who voted for the decision, and the fee, are paid
+                let decided_vote = escrow_value
+                    .decided_vote
+                    .clone()
+                    .ok_or(EscrowInputError::NoArbiterFeeToClaim)?;
+                let vote_key = ArbiterVoteKey {
+                    escrow_id: escrow_input.escrow_id.clone(),
+                    arbiter: escrow_input.arbiter,
+                };
+                let mut vote = dbtx
+                    .get_value(&vote_key)
+                    .await
+                    .ok_or(EscrowInputError::NoArbiterFeeToClaim)?;
+                if !vote.agrees_with(&decided_vote) || vote.fee_claimed {
+                    return Err(EscrowInputError::NoArbiterFeeToClaim);
+                }
+                let fee_share = vote.fee_share(escrow_value.arbiter_threshold);
+                if fee_share > escrow_value.unclaimed_arbiter_fee {
+                    return Err(EscrowInputError::NoArbiterFeeToClaim);
+                }
+
+                check_claim_amount(escrow_input.amount, fee_share)?;
+
+                // check the signature of arbiter
+                let message = escrow_signing_message(
+                    &self.cfg.consensus.signing_domain,
+                    &escrow_input.escrow_id,
+                    EscrowAction::ClaimArbiterFee,
+                    escrow_input.amount,
+                );
+                if !verify_signature(&message, &escrow_input.signature, &escrow_input.arbiter) {
+                    return Err(EscrowInputError::InvalidArbiter);
                 }
+
+                vote.fee_claimed = true;
+                escrow_value.unclaimed_arbiter_fee = escrow_value.unclaimed_arbiter_fee - fee_share;
+                dbtx.insert_entry(&vote_key, &vote).await;
+
+                // Update the escrow value in the database
+                self.update_escrow_value(dbtx, escrow_input.escrow_id.clone(), &escrow_value)
+                    .await;
+
+                Ok(InputMeta {
+                    amount: TransactionItemAmount {
+                        amount: fee_share,
+                        fee: Amount::ZERO,
+                    },
+                    pub_key: escrow_input.arbiter, // the one who is getting the ecash
+                })
             }
-        }
-    }
+            EscrowInput::Releasing(escrow_input) => {
+                let mut escrow_value = self
+                    .get_unresolved_escrow_value(dbtx, escrow_input.escrow_id.clone())
+                    .await?;
 
-    async fn process_output<'a, 'b>(
-        &'a self,
-        dbtx: &mut DatabaseTransaction<'b>,
-        output: &'a EscrowOutput,
-        _out_point: OutPoint,
-    ) -> Result<TransactionItemAmount, EscrowOutputError> {
-        if self
-            .get_escrow_value(dbtx, output.escrow_id.clone())
-            .await
-            .is_ok()
-        {
-            return Err(EscrowOutputError::EscrowAlreadyExists);
-        }
-        let escrow_key = EscrowKey {
-            escrow_id: output.escrow_id.clone(),
-        };
-        let escrow_value = EscrowValue {
-            buyer_pubkey: output.buyer_pubkey,
-            seller_pubkey: output.seller_pubkey,
-            arbiter_pubkey: output.arbiter_pubkey,
-            amount: output.amount,
-            secret_code_hash: output.secret_code_hash.clone(),
-            max_arbiter_fee: output.max_arbiter_fee,
-            state: EscrowStates::Open,
-        };
+                // the buyer can only release the escrow if nobody has disputed it
+                if escrow_value.state != EscrowStates::Open {
+                    return Err(EscrowInputError::InvalidStateForClaimingEscrow);
+                }
 
-        // guardian db entry
-        dbtx.insert_new_entry(&escrow_key, &escrow_value).await;
+                // check the signature of buyer
+                let message = escrow_signing_message(
+                    &self.cfg.consensus.signing_domain,
+                    &escrow_input.escrow_id,
+                    EscrowAction::Release,
+                    Amount::ZERO,
+                );
+                if !verify_signature(
+                    &message,
+                    &escrow_input.signature,
+                    &escrow_value.buyer_pubkey,
+                ) {
+                    return Err(EscrowInputError::InvalidBuyer);
+                }
 
-        Ok(TransactionItemAmount {
-            amount: output.amount,
-            fee: self.cfg.consensus.deposit_fee,
-        })
-    }
+                // all the milestones not released yet are released at once and kept for the
+                // seller to claim
+                for milestone in escrow_value
+                    .milestones
+                    .iter_mut()
+                    .filter(|milestone| milestone.state == MilestoneState::Pending)
+                {
+                    milestone.state = MilestoneState::Released;
+                }
+                escrow_value.released_unclaimed = escrow_value.amount;
+                escrow_value.amount = Amount::ZERO;
+                escrow_value.state = EscrowStates::ResolvedWithoutDispute;
 
-    async fn output_status(
-        &self,
-        _dbtx: &mut DatabaseTransaction<'_>,
-        _out_point: OutPoint,
-    ) -> Option<EscrowOutputOutcome> {
-        Some(EscrowOutputOutcome {})
-    }
+                // Update the escrow value in the database
+                self.update_escrow_value(dbtx, escrow_input.escrow_id.clone(), &escrow_value)
+                    .await;
 
-    async fn audit(
-        &self,
-        _dbtx: &mut DatabaseTransaction<'_>,
-        _audit: &mut Audit,
-        _module_instance_id: ModuleInstanceId,
-    ) {
-        // unimplemented!()
-        // audit
-        //     .add_items(
-        //         dbtx,
-        //         module_instance_id,
-        //         &EscrowKey,
-        //         |k, v| match k {
-        //             // the fed's test account is considered an asset
-        // (positive)             // should be the bitcoin we own in a
-        // real module             EscrowKey(key)
-        //                 if key == fed_public_key() || key ==
-        // broken_fed_public_key() =>             {
-        //                 v.msats as i64
-        //             }
-        //             // a user's funds are a federation's liability (negative)
-        //             EscrowKey(_) => -(v.msats as i64),
-        //         },
-        //     )
-        //     .await;
-    }
-
-    // api will be called in client by GET_MODULE_INFO endpoint
-    fn api_endpoints(&self) -> Vec<ApiEndpoint<Self>> {
-        vec![api_endpoint! {
-            GET_MODULE_INFO,
-            ApiVersion::new(0, 0),
-            async |module: &Escrow, context, escrow_id: String| -> EscrowInfo {
-                module.handle_get_module_info(&mut context.dbtx().into_nc(), escrow_id).await
+                Ok(InputMeta {
+                    amount: TransactionItemAmount {
+                        amount: Amount::ZERO,
+                        fee: Amount::ZERO,
+                    },
+                    pub_key: escrow_value.buyer_pubkey,
+                })
             }
-        }]
-    }
-}
+            EscrowInput::ClaimingReleased(escrow_input) => {
+                // the released ecash is claimed after the escrow is resolved
+                let mut escrow_value = self
+                    .get_escrow_value(dbtx, escrow_input.escrow_id.clone())
+                    .await?;
 
-impl Escrow {
-    /// Create new module instance
-    pub fn new(cfg: EscrowConfig) -> Escrow {
-        Escrow { cfg }
-    }
+                if escrow_value.released_unclaimed == Amount::ZERO {
+                    return Err(EscrowInputError::NotReleased);
+                }
 
-    async fn handle_get_module_info(
-        &self,
-        dbtx: &mut DatabaseTransaction<'_, NonCommittable>,
-        escrow_id: String,
-    ) -> Result<EscrowInfo, ApiError> {
-        let escrow_value: EscrowValue = dbtx
-            .get_value(&EscrowKey { escrow_id })
-            .await
-            .ok_or_else(|| ApiError::not_found("Escrow not found".to_owned()))?;
-        let escrow_info = EscrowInfo {
-            buyer_pubkey: escrow_value.buyer_pubkey,
-            seller_pubkey: escrow_value.seller_pubkey,
-            arbiter_pubkey: escrow_value.arbiter_pubkey,
-            amount: escrow_value.amount,
-            secret_code_hash: escrow_value.secret_code_hash,
-            state: escrow_value.state,
-            max_arbiter_fee: escrow_value.max_arbiter_fee,
-        };
-        Ok(escrow_info)
-    }
+                // the payout is all the ecash released by the buyer
+                check_claim_amount(escrow_input.amount, escrow_value.released_unclaimed)?;
 
-    // get the escrow value from the database using the escrow id
-    async fn get_escrow_value<'a>(
-        &self,
-        dbtx: &mut DatabaseTransaction<'a>,
-        escrow_id: String,
-    ) -> Result<EscrowValue, EscrowInputError> {
-        let escrow_key = self.get_escrow_key(escrow_id).await;
-        dbtx.get_value(&escrow_key)
-            .await
-            .ok_or_else(|| EscrowInputError::EscrowNotFound)
-    }
+                // check the signature of seller
+                let message = escrow_signing_message(
+                    &self.cfg.consensus.signing_domain,
+                    &escrow_input.escrow_id,
+                    EscrowAction::ClaimReleased,
+                    escrow_input.amount,
+                );
+                if !verify_signature(
+                    &message,
+                    &escrow_input.signature,
+                    &escrow_value.seller_pubkey,
+                ) {
+                    return Err(EscrowInputError::InvalidSeller);
+                }
 
-    // get the escrow key from the escrow id
-    async fn get_escrow_key<'a>(&self, escrow_id: String) -> EscrowKey {
-        EscrowKey { escrow_id }
+                let released_amount = escrow_value.released_unclaimed;
+                escrow_value.released_unclaimed = Amount::ZERO;
+
+                // Update the escrow value in the database
+                self.update_escrow_value(dbtx, escrow_input.escrow_id.clone(), &escrow_value)
+                    .await;
+
+                Ok(InputMeta {
+                    amount: TransactionItemAmount {
+                        amount: released_amount,
+                        fee: Amount::ZERO,
+                    },
+                    pub_key: escrow_value.seller_pubkey, // the one who is getting the ecash
+                })
+            }
+            EscrowInput::Appealing(escrow_input) => {
+                let mut escrow_value = self
+                    .get_unresolved_escrow_value(dbtx, escrow_input.escrow_id.clone())
+                    .await?;
+
+                // only a decision of the arbiters that was not appealed yet can be appealed
+                let arbiter_decision = match (&escrow_value.state, &escrow_value.arbiter_decision) {
+                    (
+                        EscrowStates::WaitingforBuyerToClaim
+                        | EscrowStates::WaitingforSellerToClaim
+                        | EscrowStates::WaitingforBothToClaim,
+                        Some(arbiter_decision),
+                    ) if escrow_value.appealed_by.is_none() => arbiter_decision.clone(),
+                    _ => return Err(EscrowInputError::NotAppealable),
+                };
+                let appeal_deadline = escrow_value
+                    .appeal_deadline()
+                    .ok_or(EscrowInputError::NotAppealable)?;
+                let now = self.consensus_unix_time(dbtx).await;
+                if now >= appeal_deadline {
+                    return Err(EscrowInputError::AppealWindowClosed);
+                }
+
+                // only the party losing the decision can appeal it
+                let appellant = if escrow_input.appellant == escrow_value.buyer_pubkey {
+                    Disputer::Buyer
+                } else if escrow_input.appellant == escrow_value.seller_pubkey {
+                    Disputer::Seller
+                } else {
+                    return Err(EscrowInputError::UnauthorizedToAppeal);
+                };
+                if !arbiter_decision.is_lost_by(&appellant) {
+                    return Err(EscrowInputError::UnauthorizedToAppeal);
+                }
+
+                // check the signature of appellant
+                let message = escrow_signing_message(
+                    &self.cfg.consensus.signing_domain,
+                    &escrow_input.escrow_id,
+                    EscrowAction::Appeal,
+                    Amount::ZERO,
+                );
+                if !verify_signature(&message, &escrow_input.signature, &escrow_input.appellant) {
+                    return Err(EscrowInputError::UnauthorizedToAppeal);
+                }
+
+                escrow_value.state = match appellant {
+                    Disputer::Buyer => EscrowStates::AppealedByBuyer,
+                    Disputer::Seller => EscrowStates::AppealedBySeller,
+                };
+                escrow_value.appealed_by = Some(appellant);
+                escrow_value.appealed_at = Some(now);
+
+                // Update the escrow value in the database
+                self.update_escrow_value(dbtx, escrow_input.escrow_id.clone(), &escrow_value)
+                    .await;
+
+                Ok(InputMeta {
+                    amount: TransactionItemAmount {
+                        amount: Amount::ZERO,
+                        fee: Amount::ZERO,
+                    },
+                    pub_key: escrow_input.appellant,
+                })
+            }
+            EscrowInput::AppealDecision(escrow_input) => {
+                let mut escrow_value = self
+                    .get_unresolved_escrow_value(dbtx, escrow_input.escrow_id.clone())
+                    .await?;
+
+                // the escrow should be appealed for the appeal arbiter to take decision
+                if !escrow_value.state.is_appealed() {
+                    return Err(EscrowInputError::EscrowNotAppealed);
+                }
+                let appeal_arbiter = escrow_value
+                    .appeal_arbiter
+                    .ok_or(EscrowInputError::EscrowNotAppealed)?;
+
+                // the decision of the arbiters stands once the appeal arbiter missed the
+                // appeal deadline
+                if let Some(appeal_decision_deadline) = escrow_value.appeal_decision_deadline() {
+                    if self.consensus_unix_time(dbtx).await >= appeal_decision_deadline {
+                        return Err(EscrowInputError::AppealTimedOut);
+                    }
+                }
+
+                // check the signature of appeal arbiter, which also covers the decision and
+                // the fee
+                let message = escrow_signing_message(
+                    &self.cfg.consensus.signing_domain,
+                    &escrow_input.escrow_id,
+                    EscrowAction::AppealDecision(escrow_input.arbiter_decision.clone()),
+                    escrow_input.amount,
+                );
+                if !verify_signature(&message, &escrow_input.signature, &appeal_arbiter) {
+                    return Err(EscrowInputError::InvalidArbiter);
+                }
+
+                // the buyer share of a split can be at most the whole escrow
+                if let ArbiterDecision::Split { buyer_share_bps } = escrow_input.arbiter_decision {
+                    if buyer_share_bps > MAX_BPS {
+                        return Err(EscrowInputError::InvalidBuyerShare);
+                    }
+                }
+
+                // the fees of the arbiters and of the appeal arbiter together are bounded by
+                // the maximum arbiter fee
+                if escrow_value.arbiter_fee + escrow_input.amount > escrow_value.max_arbiter_fee
+                    || escrow_input.amount > escrow_value.amount
+                {
+                    return Err(EscrowInputError::ArbiterFeeExceedsMaximum);
+                }
+                escrow_value.amount = escrow_value.amount - escrow_input.amount;
+
+                // the decision of the appeal arbiter supersedes the one of the arbiters
+                escrow_value.state = escrow_input.arbiter_decision.claim_state();
+                escrow_value.appeal_decision = Some(escrow_input.arbiter_decision.clone());
+
+                // Update the escrow value in the database
+                self.update_escrow_value(dbtx, escrow_input.escrow_id.clone(), &escrow_value)
+                    .await;
+
+                Ok(InputMeta {
+                    amount: TransactionItemAmount {
+                        amount: escrow_input.amount,
+                        fee: Amount::ZERO,
+                    },
+                    pub_key: appeal_arbiter, // the one who is getting the ecash
+                })
+            }
+            EscrowInput::ClaimingAfterDispute(escrow_input) => {
+                let mut escrow_value = self
+                    .get_unresolved_escrow_value(dbtx, escrow_input.escrow_id.clone())
+                    .await?;
+
+                // once the arbiters missed the arbitration deadline the fallback outcome
+                // applies, without any arbiter fee
+                let now = self.consensus_unix_time(dbtx).await;
+                if escrow_value.state.is_disputed()
+                    && escrow_value
+                        .arbitration_deadline()
+                        .is_some_and(|arbitration_deadline| now >= arbitration_deadline)
+                {
+                    escrow_value.state = escrow_value.fallback.decision().claim_state();
+                    escrow_value.fallback_applied = true;
+                }
+
+                // once the appeal arbiter missed the appeal deadline the decision of the
+                // arbiters stands, the appeal arbiter gets no fee
+                if escrow_value.state.is_appealed()
+                    && escrow_value
+                        .appeal_decision_deadline()
+                        .is_some_and(|appeal_decision_deadline| now >= appeal_decision_deadline)
+                {
+                    if let Some(arbiter_decision) = &escrow_value.arbiter_decision {
+                        escrow_value.state = arbiter_decision.claim_state();
+                    }
+                }
+
+                // the arbiter should have decided who gets which share of the escrow
+                let arbiter_decision = match (&escrow_value.state, escrow_value.decision()) {
+                    (
+                        EscrowStates::WaitingforBuyerToClaim
+                        | EscrowStates::WaitingforSellerToClaim
+                        | EscrowStates::WaitingforBothToClaim,
+                        Some(arbiter_decision),
+                    ) => arbiter_decision,
+                    _ => return Err(EscrowInputError::InvalidStateForClaimingEscrow),
+                };
+
+                // nothing is paid out while the decision can still be appealed
+                if let Some(appeal_deadline) = escrow_value.appeal_deadline() {
+                    if escrow_value.appealed_by.is_none() && now < appeal_deadline {
+                        return Err(EscrowInputError::AppealWindowOpen);
+                    }
+                }
+                let (buyer_share, seller_share) = arbiter_decision.split(escrow_value.amount);
+
+                // Determine who is claiming and what is still left to claim for them
+                let claimable = if escrow_input.claimer == escrow_value.buyer_pubkey {
+                    buyer_share.saturating_sub(escrow_value.buyer_withdrawn)
+                } else if escrow_input.claimer == escrow_value.seller_pubkey {
+                    seller_share.saturating_sub(escrow_value.seller_withdrawn)
+                } else {
+                    return Err(EscrowInputError::UnauthorizedToClaim);
+                };
+                if claimable == Amount::ZERO {
+                    return Err(EscrowInputError::NothingToClaim);
+                }
+
+                // the payout is the share decided by the arbiter, not the one chosen by the
+                // client
+                check_claim_amount(escrow_input.amount, claimable)?;
+
+                // check the signature of claimer
+                let message = escrow_signing_message(
+                    &self.cfg.consensus.signing_domain,
+                    &escrow_input.escrow_id,
+                    EscrowAction::ClaimAfterDispute,
+                    escrow_input.amount,
+                );
+                if !verify_signature(&message, &escrow_input.signature, &escrow_input.claimer) {
+                    return Err(EscrowInputError::UnauthorizedToClaim);
+                }
+
+                if escrow_input.claimer == escrow_value.buyer_pubkey {
+                    escrow_value.buyer_withdrawn = escrow_value.buyer_withdrawn + claimable;
+                } else {
+                    escrow_value.seller_withdrawn = escrow_value.seller_withdrawn + claimable;
+                }
+
+                // the escrow is resolved once both shares are paid out
+                if escrow_value.locked_amount() == Amount::ZERO {
+                    escrow_value.state = EscrowStates::ResolvedWithDispute;
+                }
+
+                // Update the escrow value in the database
+                self.update_escrow_value(dbtx, escrow_input.escrow_id.clone(), &escrow_value)
+                    .await;
+
+                Ok(InputMeta {
+                    amount: TransactionItemAmount {
+                        amount: claimable,
+                        fee: Amount::ZERO,
+                    },
+                    pub_key: escrow_input.claimer, // the one who is getting the ecash
+                })
+            }
+            EscrowInput::ClaimingAfterExpiry(escrow_input) => {
+                let mut escrow_value = self
+                    .get_unresolved_escrow_value(dbtx, escrow_input.escrow_id.clone())
+                    .await?;
+
+                // only an escrow that nobody claimed or disputed can expire, or one that the
+                // seller did not accept in time
+                let refundable_at = match escrow_value.state {
+                    EscrowStates::Open => escrow_value.deadline,
+                    EscrowStates::PendingAcceptance => escrow_value.acceptance_deadline(),
+                    _ => return Err(EscrowInputError::InvalidStateForClaimingEscrow),
+                };
+
+                // the deadline is checked against the time agreed by the guardians
+                if self.consensus_unix_time(dbtx).await < refundable_at {
+                    return Err(EscrowInputError::EscrowNotExpired);
+                }
+
+                // the whole contract is refunded
+                check_claim_amount(escrow_input.amount, escrow_value.amount)?;
+
+                // check the signature of buyer
+                let message = escrow_signing_message(
+                    &self.cfg.consensus.signing_domain,
+                    &escrow_input.escrow_id,
+                    EscrowAction::ClaimAfterExpiry,
+                    escrow_input.amount,
+                );
+                if !verify_signature(
+                    &message,
+                    &escrow_input.signature,
+                    &escrow_value.buyer_pubkey,
+                ) {
+                    return Err(EscrowInputError::InvalidBuyer);
+                }
+                escrow_value.state = EscrowStates::Expired;
+
+                // Update the escrow value in the database
+                self.update_escrow_value(dbtx, escrow_input.escrow_id.clone(), &escrow_value)
+                    .await;
+
+                Ok(InputMeta {
+                    amount: TransactionItemAmount {
+                        amount: escrow_value.amount,
+                        fee: Amount::ZERO,
+                    },
+                    pub_key: escrow_value.buyer_pubkey, // the one who is getting the ecash
+                })
+            }
+            EscrowInput::Cancel(escrow_input) => {
+                let mut escrow_value = self
+                    .get_unresolved_escrow_value(dbtx, escrow_input.escrow_id.clone())
+                    .await?;
+
+                // the escrow can be cancelled as long as nobody has been paid out yet
+                match escrow_value.state {
+                    EscrowStates::PendingAcceptance
+                    | EscrowStates::Open
+                    | EscrowStates::DisputedByBuyer
+                    | EscrowStates::DisputedBySeller => {}
+                    _ => return Err(EscrowInputError::InvalidStateForCancellation),
+                }
+
+                // the whole contract is refunded
+                check_claim_amount(escrow_input.amount, escrow_value.amount)?;
+
+                // both buyer and seller should have signed the cancellation
+                let message = escrow_signing_message(
+                    &self.cfg.consensus.signing_domain,
+                    &escrow_input.escrow_id,
+                    EscrowAction::Cancel,
+                    escrow_input.amount,
+                );
+                if !verify_signature(
+                    &message,
+                    &escrow_input.buyer_signature,
+                    &escrow_value.buyer_pubkey,
+                ) {
+                    return Err(EscrowInputError::InvalidBuyer);
+                }
+                if !verify_signature(
+                    &message,
+                    &escrow_input.seller_signature,
+                    &escrow_value.seller_pubkey,
+                ) {
+                    return Err(EscrowInputError::InvalidSeller);
+                }
+                escrow_value.state = EscrowStates::Cancelled;
+
+                // Update the escrow value in the database
+                self.update_escrow_value(dbtx, escrow_input.escrow_id.clone(), &escrow_value)
+                    .await;
+
+                Ok(InputMeta {
+                    amount: TransactionItemAmount {
+                        amount: escrow_value.amount,
+                        fee: Amount::ZERO,
+                    },
+                    pub_key: escrow_value.buyer_pubkey, // the one who is getting the ecash
+                })
+            }
+            EscrowInput::RespondingToDispute(escrow_input) => {
+                let escrow_value = self
+                    .get_unresolved_escrow_value(dbtx, escrow_input.escrow_id.clone())
+                    .await?;
+
+                // the response is only useful before the arbiter decides
+                let responder = match escrow_value.state {
+                    EscrowStates::DisputedByBuyer => Disputer::Seller,
+                    EscrowStates::DisputedBySeller => Disputer::Buyer,
+                    _ => return Err(EscrowInputError::EscrowNotDisputed),
+                };
+                let responder_pubkey = match responder {
+                    Disputer::Buyer => escrow_value.buyer_pubkey,
+                    Disputer::Seller => escrow_value.seller_pubkey,
+                };
+                if escrow_input.responder != responder_pubkey {
+                    return Err(EscrowInputError::UnauthorizedToRespond);
+                }
+
+                // check the signature of the responder, which also covers the response
+                let message = escrow_signing_message(
+                    &self.cfg.consensus.signing_domain,
+                    &escrow_input.escrow_id,
+                    EscrowAction::RespondToDispute {
+                        response: escrow_input.response.clone(),
+                    },
+                    Amount::ZERO,
+                );
+                if !verify_signature(&message, &escrow_input.signature, &escrow_input.responder) {
+                    return Err(EscrowInputError::UnauthorizedToRespond);
+                }
+
+                if !escrow_input.response.is_within_size_limit() {
+                    return Err(EscrowInputError::EvidenceTooLarge);
+                }
+
+                // the counterparty can respond only once
+                let evidence_key = DisputeEvidenceKey {
+                    escrow_id: escrow_input.escrow_id.clone(),
+                    party: responder,
+                };
+                if dbtx.get_value(&evidence_key).await.is_some() {
+                    return Err(EscrowInputError::DisputeAlreadyResponded);
+                }
+                let dispute_evidence = DisputeEvidence {
+                    party: responder,
+                    submitted_at: self.consensus_unix_time(dbtx).await,
+                    evidence: escrow_input.response.clone(),
+                };
+                dbtx.insert_new_entry(&evidence_key, &dispute_evidence)
+                    .await;
+
+                Ok(InputMeta {
+                    amount: TransactionItemAmount {
+                        amount: Amount::ZERO,
+                        fee: Amount::ZERO,
+                    },
+                    pub_key: escrow_input.responder,
+                })
+            }
+        }
+    }
+
+    async fn process_output<'a, 'b>(
+        &'a self,
+        dbtx: &mut DatabaseTransaction<'b>,
+        output: &'a EscrowOutput,
+        _out_point: OutPoint,
+    ) -> Result<TransactionItemAmount, EscrowOutputError> {
+        if output.escrow_id.len() > self.cfg.consensus.max_escrow_id_length as usize {
+            return Err(EscrowOutputError::EscrowIdTooLong);
+        }
+        if self
+            .get_escrow_value(dbtx, output.escrow_id.clone())
+            .await
+            .is_ok()
+        {
+            return Err(EscrowOutputError::EscrowAlreadyExists);
+        }
+        let now = self.consensus_unix_time(dbtx).await;
+        if output.deadline <= now {
+            return Err(EscrowOutputError::DeadlineInPast);
+        }
+        // the limits of the federation
+        if output.amount < self.cfg.consensus.min_escrow_amount {
+            return Err(EscrowOutputError::AmountBelowMinimum);
+        }
+        if output.amount > self.cfg.consensus.max_escrow_amount {
+            return Err(EscrowOutputError::AmountAboveMaximum);
+        }
+        if output.max_arbiter_fee > self.cfg.consensus.max_arbiter_fee(output.amount) {
+            return Err(EscrowOutputError::ArbiterFeeAboveMaximum);
+        }
+        if output.arbitration_timeout_secs < self.cfg.consensus.min_arbitration_timeout_secs {
+            return Err(EscrowOutputError::ArbitrationTimeoutBelowMinimum);
+        }
+        let open_escrow_count_key = OpenEscrowCountKey(output.buyer_pubkey);
+        let open_escrows = dbtx.get_value(&open_escrow_count_key).await.unwrap_or(0);
+        if open_escrows >= self.cfg.consensus.max_open_escrows_per_buyer {
+            return Err(EscrowOutputError::TooManyOpenEscrows);
+        }
+        // the milestones should lock exactly the ecash of the escrow, each behind the
+        // hash of its secret code
+        if output.milestones.is_empty()
+            || output.milestones.iter().any(|milestone| {
+                milestone.amount == Amount::ZERO || !is_valid_hash(&milestone.secret_code_hash)
+            })
+            || output
+                .milestones
+                .iter()
+                .map(|milestone| milestone.amount.msats)
+                .try_fold(0u64, |total, msats| total.checked_add(msats))
+                != Some(output.amount.msats)
+        {
+            return Err(EscrowOutputError::InvalidMilestones);
+        }
+        // the panel needs distinct arbiters other than the buyer and the seller, and a
+        // reachable threshold
+        if !is_valid_panel(
+            &output.arbiter_pubkeys,
+            output.arbiter_threshold,
+            &output.buyer_pubkey,
+            &output.seller_pubkey,
+        ) {
+            return Err(EscrowOutputError::InvalidArbiters);
+        }
+        // the appeal arbiter reviews the decision of the panel, thus it is not part of
+        // it
+        if let Some(appeal_arbiter) = &output.appeal_arbiter {
+            if output.arbiter_pubkeys.contains(appeal_arbiter)
+                || appeal_arbiter == &output.buyer_pubkey
+                || appeal_arbiter == &output.seller_pubkey
+            {
+                return Err(EscrowOutputError::InvalidAppealArbiter);
+            }
+        }
+        let escrow_key = EscrowKey {
+            escrow_id: output.escrow_id.clone(),
+        };
+        let escrow_value = EscrowValue {
+            buyer_pubkey: output.buyer_pubkey,
+            seller_pubkey: output.seller_pubkey,
+            arbiter_pubkeys: output.arbiter_pubkeys.clone(),
+            arbiter_threshold: output.arbiter_threshold,
+            amount: output.amount,
+            milestones: output
+                .milestones
+                .iter()
+                .cloned()
+                .map(EscrowMilestone::from)
+                .collect(),
+            max_arbiter_fee: output.max_arbiter_fee,
+            state: EscrowStates::PendingAcceptance,
+            created_at: now,
+            deadline: output.deadline,
+            arbitration_timeout_secs: output.arbitration_timeout_secs,
+            fallback: output.fallback,
+            hash_lock: output.hash_lock,
+            fallback_applied: false,
+            disputer: None,
+            disputed_at: None,
+            arbiter_decision: None,
+            decided_at: None,
+            arbiter_fee: Amount::ZERO,
+            decided_vote: None,
+            appeal_arbiter: output.appeal_arbiter,
+            appealed_by: None,
+            appealed_at: None,
+            appeal_decision: None,
+            unclaimed_arbiter_fee: Amount::ZERO,
+            released_unclaimed: Amount::ZERO,
+            buyer_withdrawn: Amount::ZERO,
+            seller_withdrawn: Amount::ZERO,
+        };
+
+        // guardian db entry
+        dbtx.insert_new_entry(&escrow_key, &escrow_value).await;
+        dbtx.insert_entry(&open_escrow_count_key, &(open_escrows + 1))
+            .await;
+
+        // index the escrow by its participants, so that they can discover it
+        let participants = [
+            (output.buyer_pubkey, EscrowRole::Buyer),
+            (output.seller_pubkey, EscrowRole::Seller),
+        ]
+        .into_iter()
+        .chain(
+            output
+                .arbiter_pubkeys
+                .iter()
+                .chain(&output.appeal_arbiter)
+                .map(|arbiter_pubkey| (*arbiter_pubkey, EscrowRole::Arbiter)),
+        );
+        for (pubkey, role) in participants {
+            dbtx.insert_entry(
+                &EscrowParticipantKey {
+                    pubkey,
+                    role,
+                    escrow_id: output.escrow_id.clone(),
+                },
+                &(),
+            )
+            .await;
+        }
+
+        Ok(TransactionItemAmount {
+            amount: output.amount,
+            fee: self.cfg.consensus.deposit_fee,
+        })
+    }
+
+    async fn output_status(
+        &self,
+        _dbtx: &mut DatabaseTransaction<'_>,
+        _out_point: OutPoint,
+    ) -> Option<EscrowOutputOutcome> {
+        Some(EscrowOutputOutcome {})
+    }
+
+    async fn audit(
+        &self,
+        dbtx: &mut DatabaseTransaction<'_>,
+        audit: &mut Audit,
+        module_instance_id: ModuleInstanceId,
+    ) {
+        // the ecash locked in an escrow is a liability of the federation until the
+        // escrow is resolved, before the arbiters decide this still includes the
+        // maximum arbiter fee that can be paid out of the contract
+        audit
+            .add_items(
+                dbtx,
+                module_instance_id,
+                &EscrowKeyPrefix,
+                |_, escrow_value| -(escrow_value.liability().msats as i64),
+            )
+            .await;
+    }
+
+    // api will be called in client by GET_MODULE_INFO, LIST_ESCROWS_BY_PUBKEY,
+    // ARBITER_INBOX and GET_DISPUTE_EVIDENCE endpoints
+    fn api_endpoints(&self) -> Vec<ApiEndpoint<Self>> {
+        vec![
+            api_endpoint! {
+                GET_MODULE_INFO,
+                ApiVersion::new(0, 0),
+                async |module: &Escrow, context, escrow_id: String| -> EscrowInfo {
+                    module.handle_get_module_info(&mut context.dbtx().into_nc(), escrow_id).await
+                }
+            },
+            api_endpoint! {
+                LIST_ESCROWS_BY_PUBKEY,
+                ApiVersion::new(0, 0),
+                async |module: &Escrow, context, request: ListEscrowsRequest| -> ListEscrowsResponse {
+                    Ok(module
+                        .handle_list_escrows_by_pubkey(&mut context.dbtx().into_nc(), request)
+                        .await)
+                }
+            },
+            api_endpoint! {
+                ARBITER_INBOX,
+                ApiVersion::new(0, 0),
+                async |module: &Escrow, context, arbiter_pubkey: PublicKey| -> Vec<DisputeSummary> {
+                    Ok(module
+                        .handle_arbiter_inbox(&mut context.dbtx().into_nc(), arbiter_pubkey)
+                        .await)
+                }
+            },
+            api_endpoint! {
+                GET_DISPUTE_EVIDENCE,
+                ApiVersion::new(0, 0),
+                async |module: &Escrow, context, escrow_id: String| -> Vec<DisputeEvidence> {
+                    Ok(module
+                        .handle_get_dispute_evidence(&mut context.dbtx().into_nc(), escrow_id)
+                        .await)
+                }
+            },
+        ]
+    }
+}
+
+impl Escrow {
+    /// Create new module instance
+    pub fn new(cfg: EscrowConfig, our_peer_id: PeerId, peer_count: usize) -> Escrow {
+        Escrow {
+            cfg,
+            our_peer_id,
+            peer_count,
+        }
+    }
+
+    async fn handle_get_module_info(
+        &self,
+        dbtx: &mut DatabaseTransaction<'_, NonCommittable>,
+        escrow_id: String,
+    ) -> Result<EscrowInfo, ApiError> {
+        let escrow_value: EscrowValue = dbtx
+            .get_value(&EscrowKey {
+                escrow_id: escrow_id.clone(),
+            })
+            .await
+            .ok_or_else(|| ApiError::not_found("Escrow not found".to_owned()))?;
+        let arbiter_votes = dbtx
+            .find_by_prefix(&ArbiterVoteEscrowPrefix { escrow_id })
+            .await
+            .map(|(_, arbiter_vote)| arbiter_vote)
+            .collect()
+            .await;
+        let escrow_info = EscrowInfo {
+            buyer_pubkey: escrow_value.buyer_pubkey,
+            seller_pubkey: escrow_value.seller_pubkey,
+            arbiter_pubkeys: escrow_value.arbiter_pubkeys,
+            arbiter_threshold: escrow_value.arbiter_threshold,
+            amount: escrow_value.amount,
+            milestones: escrow_value.milestones,
+            state: escrow_value.state,
+            max_arbiter_fee: escrow_value.max_arbiter_fee,
+            created_at: escrow_value.created_at,
+            deadline: escrow_value.deadline,
+            arbitration_timeout_secs: escrow_value.arbitration_timeout_secs,
+            arbiter_votes,
+            arbiter_decision: escrow_value.arbiter_decision,
+            decided_at: escrow_value.decided_at,
+            decided_vote: escrow_value.decided_vote,
+            appeal_arbiter: escrow_value.appeal_arbiter,
+            appealed_by: escrow_value.appealed_by,
+            appeal_decision: escrow_value.appeal_decision,
+            arbitration_deadline: escrow_value.arbitration_deadline(),
+            appeal_decision_deadline: escrow_value.appeal_decision_deadline(),
+            fallback: escrow_value.fallback,
+            hash_lock: escrow_value.hash_lock,
+            fallback_applied: escrow_value.fallback_applied,
+            released_unclaimed: escrow_value.released_unclaimed,
+            buyer_withdrawn: escrow_value.buyer_withdrawn,
+            seller_withdrawn: escrow_value.seller_withdrawn,
+        };
+        Ok(escrow_info)
+    }
+
+    async fn handle_list_escrows_by_pubkey(
+        &self,
+        dbtx: &mut DatabaseTransaction<'_, NonCommittable>,
+        request: ListEscrowsRequest,
+    ) -> ListEscrowsResponse {
+        let offset = request.offset as usize;
+        let limit = request.limit.min(MAX_LIST_ESCROWS_LIMIT) as usize;
+
+        // one more than the limit is looked up to know whether there is a next page
+        let participant_keys: Vec<EscrowParticipantKey> = match request.role {
+            Some(role) => {
+                dbtx.find_by_prefix(&EscrowParticipantRolePrefix {
+                    pubkey: request.pubkey,
+                    role,
+                })
+                .await
+                .map(|(participant_key, ())| participant_key)
+                .skip(offset)
+                .take(limit + 1)
+                .collect()
+                .await
+            }
+            None => {
+                dbtx.find_by_prefix(&EscrowParticipantPubkeyPrefix {
+                    pubkey: request.pubkey,
+                })
+                .await
+                .map(|(participant_key, ())| participant_key)
+                .skip(offset)
+                .take(limit + 1)
+                .collect()
+                .await
+            }
+        };
+        let next_offset = (participant_keys.len() > limit).then_some(request.offset + limit as u64);
+
+        let mut escrows = Vec::new();
+        for participant_key in participant_keys.into_iter().take(limit) {
+            // an index entry without its escrow is skipped rather than failing the request
+            let Some(escrow_value) = dbtx
+                .get_value(&EscrowKey {
+                    escrow_id: participant_key.escrow_id.clone(),
+                })
+                .await
+            else {
+                continue;
+            };
+            escrows.push(EscrowSummary {
+                escrow_id: participant_key.escrow_id,
+                role: participant_key.role,
+                amount: escrow_value.amount,
+                state: escrow_value.state,
+                deadline: escrow_value.deadline,
+            });
+        }
+
+        ListEscrowsResponse {
+            escrows,
+            next_offset,
+        }
+    }
+
+    async fn handle_arbiter_inbox(
+        &self,
+        dbtx: &mut DatabaseTransaction<'_, NonCommittable>,
+        arbiter_pubkey: PublicKey,
+    ) -> Vec<DisputeSummary> {
+        let escrow_ids: Vec<String> = dbtx
+            .find_by_prefix(&EscrowParticipantRolePrefix {
+                pubkey: arbiter_pubkey,
+                role: EscrowRole::Arbiter,
+            })
+            .await
+            .map(|(participant_key, ())| participant_key.escrow_id)
+            .collect()
+            .await;
+
+        let mut disputes = Vec::new();
+        for escrow_id in escrow_ids {
+            // an index entry without its escrow is skipped rather than failing the request
+            let Some(escrow_value) = dbtx
+                .get_value(&EscrowKey {
+                    escrow_id: escrow_id.clone(),
+                })
+                .await
+            else {
+                continue;
+            };
+
+            // the appeals waiting for the decision of the appeal arbiter
+            if escrow_value.state.is_appealed()
+                && escrow_value.appeal_arbiter == Some(arbiter_pubkey)
+            {
+                if let (Some(appellant), Some(appealed_at)) =
+                    (escrow_value.appealed_by, escrow_value.appealed_at)
+                {
+                    disputes.push(DisputeSummary {
+                        escrow_id,
+                        amount: escrow_value.amount,
+                        max_arbiter_fee: escrow_value.max_arbiter_fee - escrow_value.arbiter_fee,
+                        disputer: appellant,
+                        disputed_at: appealed_at,
+                        appeal: true,
+                        arbitration_deadline: escrow_value.appeal_decision_deadline(),
+                    });
+                }
+                continue;
+            }
+
+            // only the disputes still waiting for the vote of the arbiter
+            if !escrow_value.arbiter_pubkeys.contains(&arbiter_pubkey) {
+                continue;
+            }
+            if escrow_value.state != EscrowStates::DisputedByBuyer
+                && escrow_value.state != EscrowStates::DisputedBySeller
+            {
+                continue;
+            }
+            if dbtx
+                .get_value(&ArbiterVoteKey {
+                    escrow_id: escrow_id.clone(),
+                    arbiter: arbiter_pubkey,
+                })
+                .await
+                .is_some()
+            {
+                continue;
+            }
+            if let (Some(disputer), Some(disputed_at)) =
+                (escrow_value.disputer, escrow_value.disputed_at)
+            {
+                disputes.push(DisputeSummary {
+                    escrow_id,
+                    amount: escrow_value.amount,
+                    max_arbiter_fee: escrow_value.max_arbiter_fee,
+                    disputer,
+                    disputed_at,
+                    appeal: false,
+                    arbitration_deadline: escrow_value.arbitration_deadline(),
+                });
+            }
+        }
+
+        // the oldest dispute first
+        disputes.sort_by_key(|dispute| dispute.disputed_at);
+        disputes
+    }
+
+    async fn handle_get_dispute_evidence(
+        &self,
+        dbtx: &mut DatabaseTransaction<'_, NonCommittable>,
+        escrow_id: String,
+    ) -> Vec<DisputeEvidence> {
+        // the evidence is encrypted, thus anyone can look it up but only the
+        // recipients can read it
+        let mut evidence: Vec<DisputeEvidence> = dbtx
+            .find_by_prefix(&DisputeEvidenceEscrowPrefix { escrow_id })
+            .await
+            .map(|(_, dispute_evidence)| dispute_evidence)
+            .collect()
+            .await;
+        evidence.sort_by_key(|dispute_evidence| dispute_evidence.submitted_at);
+        evidence
+    }
+
+    // get the unix time agreed upon by the guardians from their votes
+    async fn consensus_unix_time(&self, dbtx: &mut DatabaseTransaction<'_>) -> u64 {
+        let times = dbtx
+            .find_by_prefix(&UnixTimeVotePrefix)
+            .await
+            .map(|(_, unix_time)| unix_time)
+            .collect::<Vec<u64>>()
+            .await;
+
+        median_unix_time(times, self.peer_count)
+    }
+
+    // get the escrow value from the database using the escrow id
+    async fn get_escrow_value<'a>(
+        &self,
+        dbtx: &mut DatabaseTransaction<'a>,
+        escrow_id: String,
+    ) -> Result<EscrowValue, EscrowInputError> {
+        let escrow_key = self.get_escrow_key(escrow_id).await;
+        dbtx.get_value(&escrow_key)
+            .await
+            .ok_or_else(|| EscrowInputError::EscrowNotFound)
+    }
+
+    // get the escrow value from the database and make sure it has not been paid
+    // out already
+    async fn get_unresolved_escrow_value<'a>(
+        &self,
+        dbtx: &mut DatabaseTransaction<'a>,
+        escrow_id: String,
+    ) -> Result<EscrowValue, EscrowInputError> {
+        let escrow_value = self.get_escrow_value(dbtx, escrow_id).await?;
+        if escrow_value.state.is_resolved() {
+            return Err(EscrowInputError::EscrowAlreadyResolved);
+        }
+        Ok(escrow_value)
+    }
+
+    // update the escrow value in the database, the buyer has one open escrow
+    // less once it is paid out
+    async fn update_escrow_value<'a>(
+        &self,
+        dbtx: &mut DatabaseTransaction<'a>,
+        escrow_id: String,
+        escrow_value: &EscrowValue,
+    ) {
+        let escrow_key = self.get_escrow_key(escrow_id).await;
+        let previous_value = dbtx.insert_entry(&escrow_key, escrow_value).await;
+        let was_resolved = previous_value.is_some_and(|value| value.state.is_resolved());
+        if escrow_value.state.is_resolved() && !was_resolved {
+            let open_escrow_count_key = OpenEscrowCountKey(escrow_value.buyer_pubkey);
+            let open_escrows = dbtx.get_value(&open_escrow_count_key).await.unwrap_or(0);
+            dbtx.insert_entry(&open_escrow_count_key, &open_escrows.saturating_sub(1))
+                .await;
+        }
+    }
+
+    // get the escrow key from the escrow id
+    async fn get_escrow_key<'a>(&self, escrow_id: String) -> EscrowKey {
+        EscrowKey { escrow_id }
+    }
+}
+
+/// Checks that the amount claimed by the client is the one stored in the
+/// escrow contract
+fn check_claim_amount(claimed: Amount, expected: Amount) -> Result<(), EscrowInputError> {
+    if claimed != expected {
+        return Err(EscrowInputError::ClaimAmountMismatch { claimed, expected });
+    }
+    Ok(())
+}
+
+/// Checks that every guardian generated the same consensus config as ours,
+/// otherwise they would disagree on the limits of the escrows
+fn check_peer_consensus(
+    consensus: &EscrowConfigConsensus,
+    peer_consensus: BTreeMap<PeerId, EscrowConfigConsensus>,
+) -> anyhow::Result<()> {
+    for (peer, peer_consensus) in peer_consensus {
+        if &peer_consensus != consensus {
+            bail!(
+                "Escrow consensus config of peer {peer} differs from ours: {peer_consensus:?} != {consensus:?}"
+            );
+        }
+    }
+    Ok(())
+}
+
+/// The signing domain of the escrow module of the federation, derived from the
+/// random contributions of all guardians in the order of their ids
+fn signing_domain(contributions: &BTreeMap<PeerId, [u8; 32]>) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    for contribution in contributions.values() {
+        hasher.update(contribution);
+    }
+    hasher.finalize().into()
+}
+
+/// The median of the unix time votes of the `peer_count` guardians, so that a
+/// minority of faulty guardians cannot move it
+fn median_unix_time(mut times: Vec<u64>, peer_count: usize) -> u64 {
+    assert!(times.len() <= peer_count);
+
+    // guardians that have not voted yet are counted as voting for 0
+    times.resize(peer_count, 0);
+
+    times.sort_unstable();
+
+    times[peer_count / 2]
+}
+
+/// The escrows of database version 1 had a single arbiter and no deadline, they
+/// cannot be decoded as the escrows of version 2. No federation ran the module
+/// with them, thus there is nothing to migrate, and without escrows there are
+/// no indexes or open escrow counts to build either. An upgrade still refuses
+/// to silently drop escrows it cannot read.
+async fn migrate_db_v1(dbtx: &mut DatabaseTransaction<'_>) -> anyhow::Result<()> {
+    let has_escrows = dbtx
+        .raw_find_by_prefix(&[DbKeyPrefix::Escrow as u8])
+        .await?
+        .next()
+        .await
+        .is_some();
+    if has_escrows {
+        bail!("Escrows of database version 1 cannot be migrated to version 2");
+    }
+    Ok(())
+}
+
+/// The granularity of the unix time votes, so that guardians do not propose a
+/// new consensus item in every round
+const UNIX_TIME_VOTE_INTERVAL_SECS: u64 = 60;
+
+/// The current unix time of this guardian, rounded down to the vote interval
+fn unix_time_vote() -> u64 {
+    let unix_time = fedimint_core::time::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("time is after unix epoch")
+        .as_secs();
+    unix_time - unix_time % UNIX_TIME_VOTE_INTERVAL_SECS
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use fedimint_core::db::mem_impl::MemDatabase;
+    use fedimint_core::db::{Database, DatabaseTransaction, IDatabaseTransactionOpsCoreTyped};
+    use fedimint_core::module::registry::ModuleDecoderRegistry;
+    use fedimint_core::{Amount, BitcoinHash, OutPoint, PeerId, ServerModule, TransactionId};
+    use fedimint_escrow_common::config::{
+        EscrowConfig, EscrowConfigConsensus, EscrowConfigLocal, EscrowConfigPrivate,
+        EscrowGenParams, EscrowGenParamsConsensus,
+    };
+    use fedimint_escrow_common::{
+        escrow_signing_message, hash256, ArbiterDecision, Disputer, EscrowAction, EscrowInput,
+        EscrowInputAccepting, EscrowInputAppealDecision, EscrowInputArbiterDecision,
+        EscrowInputClaimingAfterDispute, EscrowInputClaimingArbiterFee,
+        EscrowInputClamingWithoutDispute, EscrowInputError, EscrowMilestone, EscrowOutput,
+        EscrowOutputError, EscrowStates, FallbackOutcome, HashLock, Milestone, MilestoneState,
+    };
+    use secp256k1::schnorr::Signature;
+    use secp256k1::{KeyPair, PublicKey, Secp256k1};
+
+    use super::{check_peer_consensus, median_unix_time, Escrow};
+    use crate::db::{EscrowKey, UnixTimeVoteKey};
+    use crate::EscrowValue;
+
+    const BUYER: u8 = 1;
+    const SELLER: u8 = 2;
+    const ARBITER: u8 = 3;
+    const APPEAL_ARBITER: u8 = 4;
+    const ESCROW_ID: &str = "escrow";
+    const SIGNING_DOMAIN: [u8; 32] = [7; 32];
+    const SECRET_CODE: &str = "secret code";
+
+    fn keypair(seed: u8) -> KeyPair {
+        KeyPair::from_seckey_slice(&Secp256k1::new(), &[seed; 32]).expect("valid secret key")
+    }
+
+    fn pubkey(seed: u8) -> PublicKey {
+        keypair(seed).public_key()
+    }
+
+    /// Signs the action on the test escrow with the key of the participant
+    fn sign(seed: u8, action: EscrowAction, amount: Amount) -> Signature {
+        Secp256k1::new().sign_schnorr(
+            &escrow_signing_message(&SIGNING_DOMAIN, ESCROW_ID, action, amount),
+            &keypair(seed),
+        )
+    }
+
+    /// The module of a federation of a single guardian with the default limits
+    fn escrow_module() -> Escrow {
+        escrow_module_with_params(EscrowGenParams::default().consensus)
+    }
+
+    fn escrow_module_with_params(params: EscrowGenParamsConsensus) -> Escrow {
+        Escrow::new(
+            EscrowConfig {
+                local: EscrowConfigLocal {},
+                private: EscrowConfigPrivate,
+                consensus: params.to_config(SIGNING_DOMAIN),
+            },
+            PeerId::from(0),
+            1,
+        )
+    }
+
+    fn database() -> Database {
+        Database::new(MemDatabase::new(), ModuleDecoderRegistry::default())
+    }
+
+    async fn insert_escrow(dbtx: &mut DatabaseTransaction<'_>, escrow_value: &EscrowValue) {
+        dbtx.insert_entry(
+            &EscrowKey {
+                escrow_id: ESCROW_ID.to_owned(),
+            },
+            escrow_value,
+        )
+        .await;
+    }
+
+    /// An open escrow of the amount with a single milestone
+    fn escrow_value(amount: Amount) -> EscrowValue {
+        EscrowValue {
+            buyer_pubkey: pubkey(BUYER),
+            seller_pubkey: pubkey(SELLER),
+            arbiter_pubkeys: vec![pubkey(ARBITER)],
+            arbiter_threshold: 1,
+            amount,
+            milestones: vec![EscrowMilestone {
+                amount,
+                secret_code_hash: hash256(SECRET_CODE.to_owned()),
+                state: MilestoneState::Pending,
+            }],
+            max_arbiter_fee: Amount::from_msats(amount.msats / 100),
+            state: EscrowStates::Open,
+            created_at: 0,
+            deadline: 1_000,
+            arbitration_timeout_secs: 1_000,
+            fallback: FallbackOutcome::RefundBuyer,
+            hash_lock: HashLock::SecretCode,
+            fallback_applied: false,
+            disputer: None,
+            disputed_at: None,
+            arbiter_decision: None,
+            decided_at: None,
+            arbiter_fee: Amount::ZERO,
+            decided_vote: None,
+            appeal_arbiter: None,
+            appealed_by: None,
+            appealed_at: None,
+            appeal_decision: None,
+            unclaimed_arbiter_fee: Amount::ZERO,
+            released_unclaimed: Amount::ZERO,
+            buyer_withdrawn: Amount::ZERO,
+            seller_withdrawn: Amount::ZERO,
+        }
+    }
+
+    #[test]
+    fn audit_counts_locked_ecash_until_resolved() {
+        let mut escrow = escrow_value(Amount::from_sats(50));
+        escrow.state = EscrowStates::PendingAcceptance;
+        assert_eq!(escrow.liability(), Amount::from_sats(50));
+        escrow.state = EscrowStates::Open;
+        assert_eq!(escrow.liability(), Amount::from_sats(50));
+
+        // the maximum arbiter fee stays locked until the arbiters decide
+        escrow.state = EscrowStates::DisputedByBuyer;
+        assert_eq!(escrow.liability(), Amount::from_sats(50));
+
+        // nothing is owed once the escrow paid out all of its ecash
+        for state in [
+            EscrowStates::ResolvedWithoutDispute,
+            EscrowStates::ResolvedWithDispute,
+            EscrowStates::Expired,
+            EscrowStates::Cancelled,
+        ] {
+            escrow.state = state;
+            assert_eq!(escrow.liability(), Amount::ZERO);
+        }
+    }
+
+    #[test]
+    fn audit_counts_shares_not_withdrawn_yet() {
+        // the arbiter fee is paid out of the escrow, half of the rest is withdrawn
+        let mut escrow = escrow_value(Amount::from_sats(50));
+        escrow.state = EscrowStates::WaitingforBothToClaim;
+        escrow.amount = Amount::from_msats(49_500);
+        escrow.buyer_withdrawn = Amount::from_msats(24_750);
+        assert_eq!(escrow.liability(), Amount::from_msats(24_750));
+
+        escrow.seller_withdrawn = Amount::from_msats(24_750);
+        assert_eq!(escrow.liability(), Amount::ZERO);
+    }
+
+    #[test]
+    fn audit_counts_unclaimed_ecash_after_resolution() {
+        // the buyer released the escrow, the seller has not claimed it yet
+        let mut escrow = escrow_value(Amount::from_sats(50));
+        escrow.state = EscrowStates::ResolvedWithoutDispute;
+        escrow.amount = Amount::ZERO;
+        escrow.released_unclaimed = Amount::from_sats(50);
+        assert_eq!(escrow.liability(), Amount::from_sats(50));
+
+        // the arbiters who did not make the decision have not claimed their share
+        let mut escrow = escrow_value(Amount::from_sats(50));
+        escrow.state = EscrowStates::ResolvedWithDispute;
+        escrow.unclaimed_arbiter_fee = Amount::from_msats(250);
+        assert_eq!(escrow.liability(), Amount::from_msats(250));
+    }
+
+    #[test]
+    fn peers_have_to_generate_the_same_consensus_config() {
+        let consensus = EscrowGenParams::default()
+            .consensus
+            .to_config(SIGNING_DOMAIN);
+        let peers = |peer_consensus: &[EscrowConfigConsensus]| {
+            peer_consensus
+                .iter()
+                .enumerate()
+                .map(|(peer, consensus)| (PeerId::from(peer as u16 + 1), consensus.clone()))
+                .collect::<BTreeMap<_, _>>()
+        };
+
+        check_peer_consensus(&consensus, peers(&[consensus.clone(), consensus.clone()]))
+            .expect("same consensus config");
+
+        // a single guardian with other limits fails the config generation
+        let other_consensus = EscrowConfigConsensus {
+            max_escrow_amount: Amount::from_sats(1_000),
+            ..consensus.clone()
+        };
+        let error = check_peer_consensus(&consensus, peers(&[consensus.clone(), other_consensus]))
+            .expect_err("different consensus config");
+        assert!(error.to_string().contains("peer 2"));
+    }
+
+    #[test]
+    fn consensus_unix_time_is_median_of_votes() {
+        assert_eq!(median_unix_time(vec![400, 100, 300, 200], 4), 300);
+        assert_eq!(median_unix_time(vec![120, 60, 180], 3), 120);
+    }
+
+    #[test]
+    fn consensus_unix_time_counts_missing_votes_as_zero() {
+        // a single vote cannot move the time of a federation of four
+        assert_eq!(median_unix_time(vec![], 4), 0);
+        assert_eq!(median_unix_time(vec![600], 4), 0);
+        assert_eq!(median_unix_time(vec![600, 660], 4), 600);
+        assert_eq!(median_unix_time(vec![600, 660, 720], 4), 660);
+    }
+
+    #[test]
+    fn consensus_unix_time_ignores_faulty_minority() {
+        // the time stays within the votes of the honest guardians
+        assert_eq!(median_unix_time(vec![600, 660, 720, u64::MAX], 4), 720);
+        assert_eq!(median_unix_time(vec![0, 600, 660, 720], 4), 660);
+    }
+
+    #[tokio::test]
+    async fn acceptance_times_out_at_the_acceptance_deadline() {
+        let escrow = escrow_module();
+        let db = database();
+        let mut dbtx = db.begin_transaction().await;
+        let mut escrow_value = escrow_value(Amount::from_sats(50));
+        escrow_value.state = EscrowStates::PendingAcceptance;
+        insert_escrow(&mut dbtx.to_ref_nc(), &escrow_value).await;
+        let accept = EscrowInput::Accepting(EscrowInputAccepting {
+            escrow_id: ESCROW_ID.to_owned(),
+            seller: pubkey(SELLER),
+            signature: sign(
+                SELLER,
+                EscrowAction::Accept(escrow_value.terms()),
+                Amount::ZERO,
+            ),
+        });
+
+        // once the buyer can withdraw the escrow, the seller cannot accept it anymore
+        dbtx.insert_entry(
+            &UnixTimeVoteKey(PeerId::from(0)),
+            &escrow_value.acceptance_deadline(),
+        )
+        .await;
+        let result = escrow.process_input(&mut dbtx.to_ref_nc(), &accept).await;
+        assert_eq!(result.err(), Some(EscrowInputError::AcceptanceTimedOut));
+
+        dbtx.insert_entry(
+            &UnixTimeVoteKey(PeerId::from(0)),
+            &(escrow_value.acceptance_deadline() - 1),
+        )
+        .await;
+        escrow
+            .process_input(&mut dbtx.to_ref_nc(), &accept)
+            .await
+            .expect("acceptance before the deadline is valid");
+    }
+
+    fn claim_milestone(amount: Amount) -> EscrowInput {
+        EscrowInput::ClamingWithoutDispute(EscrowInputClamingWithoutDispute {
+            amount,
+            escrow_id: ESCROW_ID.to_owned(),
+            milestone: 0,
+            secret_code: SECRET_CODE.to_owned(),
+            signature: sign(
+                SELLER,
+                EscrowAction::ClaimWithoutDispute { milestone: 0 },
+                amount,
+            ),
+        })
+    }
+
+    fn claim_after_dispute(claimer: u8, amount: Amount) -> EscrowInput {
+        EscrowInput::ClaimingAfterDispute(EscrowInputClaimingAfterDispute {
+            amount,
+            escrow_id: ESCROW_ID.to_owned(),
+            claimer: pubkey(claimer),
+            signature: sign(claimer, EscrowAction::ClaimAfterDispute, amount),
+        })
+    }
+
+    #[tokio::test]
+    async fn claim_pays_out_the_milestone_once() {
+        let escrow = escrow_module();
+        let db = database();
+        let mut dbtx = db.begin_transaction().await;
+        insert_escrow(&mut dbtx.to_ref_nc(), &escrow_value(Amount::from_sats(50))).await;
+
+        // the seller cannot choose the amount paid out, even with a valid signature
+        let result = escrow
+            .process_input(
+                &mut dbtx.to_ref_nc(),
+                &claim_milestone(Amount::from_sats(51)),
+            )
+            .await;
+        assert_eq!(
+            result.err(),
+            Some(EscrowInputError::ClaimAmountMismatch {
+                claimed: Amount::from_sats(51),
+                expected: Amount::from_sats(50),
+            })
+        );
+
+        let input_meta = escrow
+            .process_input(
+                &mut dbtx.to_ref_nc(),
+                &claim_milestone(Amount::from_sats(50)),
+            )
+            .await
+            .expect("claim of the milestone is valid");
+        assert_eq!(input_meta.amount.amount, Amount::from_sats(50));
+
+        // the same claim cannot be paid out twice
+        let result = escrow
+            .process_input(
+                &mut dbtx.to_ref_nc(),
+                &claim_milestone(Amount::from_sats(50)),
+            )
+            .await;
+        assert_eq!(result.err(), Some(EscrowInputError::EscrowAlreadyResolved));
+    }
+
+    #[tokio::test]
+    async fn claim_after_dispute_pays_out_each_share_once() {
+        let escrow = escrow_module();
+        let db = database();
+        let mut dbtx = db.begin_transaction().await;
+        let mut escrow_value = escrow_value(Amount::from_sats(50));
+        escrow_value.state = EscrowStates::WaitingforBothToClaim;
+        escrow_value.arbiter_decision = Some(ArbiterDecision::Split {
+            buyer_share_bps: 4_000,
+        });
+        insert_escrow(&mut dbtx.to_ref_nc(), &escrow_value).await;
+
+        // the buyer cannot claim the share of the seller
+        let result = escrow
+            .process_input(
+                &mut dbtx.to_ref_nc(),
+                &claim_after_dispute(BUYER, Amount::from_sats(30)),
+            )
+            .await;
+        assert_eq!(
+            result.err(),
+            Some(EscrowInputError::ClaimAmountMismatch {
+                claimed: Amount::from_sats(30),
+                expected: Amount::from_sats(20),
+            })
+        );
+
+        escrow
+            .process_input(
+                &mut dbtx.to_ref_nc(),
+                &claim_after_dispute(BUYER, Amount::from_sats(20)),
+            )
+            .await
+            .expect("claim of the buyer share is valid");
+        let result = escrow
+            .process_input(
+                &mut dbtx.to_ref_nc(),
+                &claim_after_dispute(BUYER, Amount::from_sats(20)),
+            )
+            .await;
+        assert_eq!(result.err(), Some(EscrowInputError::NothingToClaim));
+
+        // the escrow is resolved once the seller claimed its share as well
+        escrow
+            .process_input(
+                &mut dbtx.to_ref_nc(),
+                &claim_after_dispute(SELLER, Amount::from_sats(30)),
+            )
+            .await
+            .expect("claim of the seller share is valid");
+        let result = escrow
+            .process_input(
+                &mut dbtx.to_ref_nc(),
+                &claim_after_dispute(SELLER, Amount::from_sats(30)),
+            )
+            .await;
+        assert_eq!(result.err(), Some(EscrowInputError::EscrowAlreadyResolved));
+    }
+
+    #[tokio::test]
+    async fn appeal_times_out_to_the_decision_of_the_arbiters() {
+        let escrow = escrow_module();
+        let db = database();
+        let mut dbtx = db.begin_transaction().await;
+        let mut escrow_value = escrow_value(Amount::from_sats(50));
+        escrow_value.state = EscrowStates::AppealedBySeller;
+        escrow_value.arbiter_decision = Some(ArbiterDecision::BuyerWins);
+        escrow_value.decided_at = Some(100);
+        escrow_value.appeal_arbiter = Some(pubkey(APPEAL_ARBITER));
+        escrow_value.appealed_by = Some(Disputer::Seller);
+        escrow_value.appealed_at = Some(200);
+        insert_escrow(&mut dbtx.to_ref_nc(), &escrow_value).await;
+
+        // nothing is paid out while the appeal arbiter can still decide
+        dbtx.insert_entry(&UnixTimeVoteKey(PeerId::from(0)), &1_199)
+            .await;
+        let result = escrow
+            .process_input(
+                &mut dbtx.to_ref_nc(),
+                &claim_after_dispute(BUYER, Amount::from_sats(50)),
+            )
+            .await;
+        assert_eq!(
+            result.err(),
+            Some(EscrowInputError::InvalidStateForClaimingEscrow)
+        );
+
+        // once the appeal arbiter has had as much time as the arbiters, it cannot
+        // decide anymore and the decision of the arbiters stands
+        dbtx.insert_entry(&UnixTimeVoteKey(PeerId::from(0)), &1_200)
+            .await;
+        let appeal_decision = EscrowInput::AppealDecision(EscrowInputAppealDecision {
+            amount: Amount::ZERO,
+            escrow_id: ESCROW_ID.to_owned(),
+            arbiter_decision: ArbiterDecision::SellerWins,
+            signature: sign(
+                APPEAL_ARBITER,
+                EscrowAction::AppealDecision(ArbiterDecision::SellerWins),
+                Amount::ZERO,
+            ),
+        });
+        let result = escrow
+            .process_input(&mut dbtx.to_ref_nc(), &appeal_decision)
+            .await;
+        assert_eq!(result.err(), Some(EscrowInputError::AppealTimedOut));
+
+        escrow
+            .process_input(
+                &mut dbtx.to_ref_nc(),
+                &claim_after_dispute(BUYER, Amount::from_sats(50)),
+            )
+            .await
+            .expect("claim of the decision of the arbiters is valid");
+    }
+
+    fn arbiter_decision(arbiter: u8, fee: Amount, amount: Amount) -> EscrowInput {
+        EscrowInput::ArbiterDecision(EscrowInputArbiterDecision {
+            amount,
+            escrow_id: ESCROW_ID.to_owned(),
+            arbiter: pubkey(arbiter),
+            arbiter_decision: ArbiterDecision::BuyerWins,
+            fee,
+            signature: sign(
+                arbiter,
+                EscrowAction::ArbiterDecision {
+                    decision: ArbiterDecision::BuyerWins,
+                    fee,
+                },
+                amount,
+            ),
+        })
+    }
+
+    fn claim_arbiter_fee(arbiter: u8, amount: Amount) -> EscrowInput {
+        EscrowInput::ClaimingArbiterFee(EscrowInputClaimingArbiterFee {
+            amount,
+            escrow_id: ESCROW_ID.to_owned(),
+            arbiter: pubkey(arbiter),
+            signature: sign(arbiter, EscrowAction::ClaimArbiterFee, amount),
+        })
+    }
+
+    #[tokio::test]
+    async fn arbiter_fee_is_paid_to_arbiters_agreeing_on_the_fee() {
+        const OTHER_ARBITER: u8 = 5;
+        const DISSENTING_ARBITER: u8 = 6;
+
+        let escrow = escrow_module();
+        let db = database();
+        let mut dbtx = db.begin_transaction().await;
+        let mut escrow_value = escrow_value(Amount::from_sats(1_000));
+        escrow_value.arbiter_pubkeys = vec![
+            pubkey(ARBITER),
+            pubkey(OTHER_ARBITER),
+            pubkey(DISSENTING_ARBITER),
+        ];
+        escrow_value.arbiter_threshold = 2;
+        escrow_value.state = EscrowStates::DisputedByBuyer;
+        escrow_value.disputer = Some(Disputer::Buyer);
+        escrow_value.disputed_at = Some(0);
+        insert_escrow(&mut dbtx.to_ref_nc(), &escrow_value).await;
+
+        // the dissenting arbiter votes for the same decision with another fee
+        for (arbiter, fee) in [
+            (OTHER_ARBITER, Amount::from_sats(4)),
+            (DISSENTING_ARBITER, Amount::from_sats(2)),
+        ] {
+            escrow
+                .process_input(
+                    &mut dbtx.to_ref_nc(),
+                    &arbiter_decision(arbiter, fee, Amount::ZERO),
+                )
+                .await
+                .expect("vote of the arbiter is valid");
+        }
+
+        // the vote agreeing on the decision and the fee decides the dispute
+        let input_meta = escrow
+            .process_input(
+                &mut dbtx.to_ref_nc(),
+                &arbiter_decision(ARBITER, Amount::from_sats(4), Amount::from_sats(2)),
+            )
+            .await
+            .expect("deciding vote is valid");
+        assert_eq!(input_meta.amount.amount, Amount::from_sats(2));
+
+        // the arbiter who voted another fee is not paid, even if its share is left
+        let result = escrow
+            .process_input(
+                &mut dbtx.to_ref_nc(),
+                &claim_arbiter_fee(DISSENTING_ARBITER, Amount::from_sats(1)),
+            )
+            .await;
+        assert_eq!(result.err(), Some(EscrowInputError::NoArbiterFeeToClaim));
+
+        escrow
+            .process_input(
+                &mut dbtx.to_ref_nc(),
+                &claim_arbiter_fee(OTHER_ARBITER, Amount::from_sats(2)),
+            )
+            .await
+            .expect("claim of the agreeing arbiter is valid");
+    }
+
+    /// A valid escrow of the buyer with a single milestone and arbiter
+    fn escrow_output(amount: Amount) -> EscrowOutput {
+        EscrowOutput {
+            amount,
+            buyer_pubkey: pubkey(BUYER),
+            seller_pubkey: pubkey(SELLER),
+            arbiter_pubkeys: vec![pubkey(ARBITER)],
+            arbiter_threshold: 1,
+            appeal_arbiter: None,
+            escrow_id: ESCROW_ID.to_owned(),
+            milestones: vec![Milestone {
+                amount,
+                secret_code_hash: hash256(SECRET_CODE.to_owned()),
+            }],
+            max_arbiter_fee: Amount::ZERO,
+            deadline: 1000,
+            arbitration_timeout_secs: 1000,
+            fallback: FallbackOutcome::RefundBuyer,
+            hash_lock: HashLock::SecretCode,
+        }
+    }
+
+    fn out_point() -> OutPoint {
+        OutPoint {
+            txid: TransactionId::all_zeros(),
+            out_idx: 0,
+        }
+    }
+
+    #[tokio::test]
+    async fn output_rejects_partial_panel() {
+        let escrow = escrow_module();
+        let db = database();
+        let mut dbtx = db.begin_transaction().await;
+
+        // the buyer or the seller cannot sit on the panel deciding their dispute, nor
+        // can an arbiter sit twice
+        for (arbiter_pubkeys, arbiter_threshold) in [
+            (vec![pubkey(ARBITER), pubkey(BUYER)], 1),
+            (vec![pubkey(SELLER)], 1),
+            (vec![pubkey(ARBITER), pubkey(ARBITER)], 1),
+        ] {
+            let output = EscrowOutput {
+                arbiter_pubkeys,
+                arbiter_threshold,
+                ..escrow_output(Amount::from_sats(50))
+            };
+            let result = escrow
+                .process_output(&mut dbtx.to_ref_nc(), &output, out_point())
+                .await;
+            assert_eq!(result.err(), Some(EscrowOutputError::InvalidArbiters));
+        }
+
+        escrow
+            .process_output(
+                &mut dbtx.to_ref_nc(),
+                &escrow_output(Amount::from_sats(50)),
+                out_point(),
+            )
+            .await
+            .expect("escrow with an impartial panel is valid");
+    }
+
+    #[tokio::test]
+    async fn output_rejects_arbitration_timeout_below_minimum() {
+        let escrow = escrow_module();
+        let db = database();
+        let mut dbtx = db.begin_transaction().await;
+
+        // the arbiters could never decide before the fallback outcome applies
+        let output = EscrowOutput {
+            arbitration_timeout_secs: 0,
+            ..escrow_output(Amount::from_sats(50))
+        };
+        let result = escrow
+            .process_output(&mut dbtx.to_ref_nc(), &output, out_point())
+            .await;
+        assert_eq!(
+            result.err(),
+            Some(EscrowOutputError::ArbitrationTimeoutBelowMinimum)
+        );
+    }
+
+    #[tokio::test]
+    async fn output_counts_open_escrows_of_buyer() {
+        let escrow = escrow_module_with_params(EscrowGenParamsConsensus {
+            max_open_escrows_per_buyer: 1,
+            ..EscrowGenParams::default().consensus
+        });
+        let db = database();
+        let mut dbtx = db.begin_transaction().await;
+        escrow
+            .process_output(
+                &mut dbtx.to_ref_nc(),
+                &escrow_output(Amount::from_sats(50)),
+                out_point(),
+            )
+            .await
+            .expect("first escrow of the buyer is valid");
+
+        let other_escrow = EscrowOutput {
+            escrow_id: "other escrow".to_owned(),
+            ..escrow_output(Amount::from_sats(50))
+        };
+        let result = escrow
+            .process_output(&mut dbtx.to_ref_nc(), &other_escrow, out_point())
+            .await;
+        assert_eq!(result.err(), Some(EscrowOutputError::TooManyOpenEscrows));
+
+        // the buyer can open another escrow once the first one is paid out
+        insert_escrow(&mut dbtx.to_ref_nc(), &escrow_value(Amount::from_sats(50))).await;
+        escrow
+            .process_input(
+                &mut dbtx.to_ref_nc(),
+                &claim_milestone(Amount::from_sats(50)),
+            )
+            .await
+            .expect("claim of the milestone is valid");
+        escrow
+            .process_output(&mut dbtx.to_ref_nc(), &other_escrow, out_point())
+            .await
+            .expect("escrow after the first one was paid out is valid");
     }
 }
@@ -3,6 +3,7 @@ use std::fmt::Write;
 use std::path::Path;
 
 use anyhow::Context;
+use bitcoin::hashes::{sha256, Hash};
 use devimint::federation::{Client, Federation};
 use devimint::util::ProcessManager;
 use devimint::{cmd, dev_fed, vars, DevFed};
@@ -121,9 +122,26 @@ async fn happy_path_test() -> anyhow::Result<()> {
         setup_clients().await.context("failed to setup client")?;
     let fed = &dev_fed.fed;
 
+    // the federation does not lock amounts outside of its limits
+    let max_arbiter_fee_bps = 100; // 1%
+    for cost in [500u64, 20_000_000_000] {
+        let create_result = cmd!(
+            buyer,
+            "module",
+            "escrow",
+            "create",
+            &seller_pubkey,
+            &arbiter_pubkey,
+            &cost.to_string(),
+            &max_arbiter_fee_bps.to_string()
+        )
+        .out_json()
+        .await;
+        assert!(create_result.is_err());
+    }
+
     // Create escrow by buyer
     let cost = 50_000;
-    let max_arbiter_fee_bps = 100; // 1%
     let create_result = cmd!(
         buyer,
         "module",
@@ -144,9 +162,40 @@ async fn happy_path_test() -> anyhow::Result<()> {
     let escrow_info = cmd!(buyer, "module", "escrow", "info", escrow_id)
         .out_json()
         .await?;
-    assert_eq!(escrow_info["state"].as_str().unwrap(), "Open");
+    assert_eq!(escrow_info["state"].as_str().unwrap(), "PendingAcceptance");
     assert_eq!(escrow_info["amount"].as_u64().unwrap(), cost);
 
+    // Seller cannot claim the escrow before accepting it
+    let early_claim_result = cmd!(seller, "module", "escrow", "claim", escrow_id, secret_code)
+        .out_json()
+        .await;
+    assert!(early_claim_result.is_err());
+
+    // Buyer cannot accept the escrow in place of the seller
+    let buyer_accept_result = cmd!(buyer, "module", "escrow", "accept", escrow_id)
+        .out_json()
+        .await;
+    assert!(buyer_accept_result.is_err());
+
+    // Seller accepts the terms of the escrow
+    let accept_result = cmd!(seller, "module", "escrow", "accept", escrow_id)
+        .out_json()
+        .await?;
+    assert_eq!(accept_result["status"], "escrow accepted!");
+
+    let escrow_info = cmd!(buyer, "module", "escrow", "info", escrow_id)
+        .out_json()
+        .await?;
+    assert_eq!(escrow_info["state"].as_str().unwrap(), "Open");
+
+    // Buyer keeps the secret code of the escrow in the client database
+    let list_result = cmd!(buyer, "module", "escrow", "list").out_json().await?;
+    let escrows = list_result["escrows"].as_array().unwrap();
+    assert_eq!(escrows.len(), 1);
+    assert_eq!(escrows[0]["escrow_id"].as_str().unwrap(), escrow_id);
+    assert_eq!(escrows[0]["role"].as_str().unwrap(), "Buyer");
+    assert_eq!(escrows[0]["secret_codes"][0].as_str().unwrap(), secret_code);
+
     // Seller claims escrow
     let claim_result = cmd!(seller, "module", "escrow", "claim", escrow_id, secret_code)
         .out_json()
@@ -195,13 +244,86 @@ async fn unhappy_path_test() -> anyhow::Result<()> {
     .await?;
 
     let escrow_id = create_result["escrow-id"].as_str().unwrap().to_string();
+
+    // Seller accepts the terms of the escrow
+    let accept_result = cmd!(seller, "module", "escrow", "accept", escrow_id.clone())
+        .out_json()
+        .await?;
+    assert_eq!(accept_result["status"], "escrow accepted!");
     let secret_code = create_result["secret-code"].as_str().unwrap().to_string();
 
     // Seller initiates dispute
-    let dispute_result = cmd!(seller, "module", "escrow", "dispute", escrow_id.clone())
+    let dispute_result = cmd!(
+        seller,
+        "module",
+        "escrow",
+        "dispute",
+        escrow_id.clone(),
+        "--reason",
+        "buyer never confirmed the delivery"
+    )
+    .out_json()
+    .await?;
+    assert_eq!(dispute_result["status"], "disputed!");
+
+    // the watcher of the buyer picks up the dispute of the seller
+    let await_result = cmd!(
+        buyer,
+        "module",
+        "escrow",
+        "await-state",
+        escrow_id.clone(),
+        "DisputedBySeller"
+    )
+    .out_json()
+    .await?;
+    assert_eq!(await_result["state"], "DisputedBySeller");
+
+    // Buyer responds to the dispute, only once
+    let respond_result = cmd!(
+        buyer,
+        "module",
+        "escrow",
+        "respond-to-dispute",
+        escrow_id.clone(),
+        "the package never arrived"
+    )
+    .out_json()
+    .await?;
+    assert_eq!(respond_result["status"], "responded to dispute!");
+    let respond_again_result = cmd!(
+        buyer,
+        "module",
+        "escrow",
+        "respond-to-dispute",
+        escrow_id.clone(),
+        "still waiting"
+    )
+    .out_json()
+    .await;
+    assert!(respond_again_result.is_err());
+
+    // Arbiter reads both sides of the dispute
+    let evidence_result = cmd!(arbiter, "module", "escrow", "evidence", escrow_id.clone())
         .out_json()
         .await?;
-    assert_eq!(dispute_result["status"], "disputed!");
+    let evidence = evidence_result["evidence"].as_array().unwrap();
+    assert_eq!(evidence.len(), 2);
+    let texts: Vec<&str> = evidence
+        .iter()
+        .map(|evidence| evidence["text"].as_str().unwrap())
+        .collect();
+    assert!(texts.contains(&"buyer never confirmed the delivery"));
+    assert!(texts.contains(&"the package never arrived"));
+
+    // the dispute shows up in the inbox of the arbiter
+    let inbox_result = cmd!(arbiter, "module", "escrow", "arbiter-inbox")
+        .out_json()
+        .await?;
+    let disputes = inbox_result["disputes"].as_array().unwrap();
+    assert_eq!(disputes.len(), 1);
+    assert_eq!(disputes[0]["escrow_id"].as_str().unwrap(), escrow_id);
+    assert_eq!(disputes[0]["disputer"].as_str().unwrap(), "Seller");
 
     let claim_result = cmd!(
         seller,
@@ -256,6 +378,12 @@ async fn unhappy_path_test() -> anyhow::Result<()> {
     .await?;
     assert_eq!(decision_result["status"], "arbiter decision made!");
 
+    // the decided dispute leaves the inbox of the arbiter
+    let inbox_result = cmd!(arbiter, "module", "escrow", "arbiter-inbox")
+        .out_json()
+        .await?;
+    assert!(inbox_result["disputes"].as_array().unwrap().is_empty());
+
     // Buyer cannot claim the escrow against arbiter decision
     let claim_result = cmd!(buyer, "module", "escrow", "seller-claim", escrow_id.clone())
         .out_json()
@@ -274,11 +402,1609 @@ async fn unhappy_path_test() -> anyhow::Result<()> {
     .await?;
     assert_eq!(claim_result["status"], "resolved!");
 
+    // the stream of the buyer ends with the resolution of the escrow, a state it
+    // cannot get into anymore is an error
+    let await_result = cmd!(
+        buyer,
+        "module",
+        "escrow",
+        "await-state",
+        escrow_id.clone(),
+        "ResolvedWithDispute"
+    )
+    .out_json()
+    .await?;
+    assert_eq!(await_result["state"], "ResolvedWithDispute");
+    let await_result = cmd!(
+        buyer,
+        "module",
+        "escrow",
+        "await-state",
+        escrow_id.clone(),
+        "Cancelled"
+    )
+    .out_json()
+    .await;
+    assert!(await_result.is_err());
+
+    // Verify final balances
+    let arbiter_fee = cost * arbiter_fee_bps / 10_000;
+    assert_eq!(buyer.balance().await?, 99_900_000);
+    assert_eq!(seller.balance().await?, cost - arbiter_fee);
+    assert_eq!(arbiter.balance().await?, arbiter_fee);
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn cancel_path_test() -> anyhow::Result<()> {
+    let (dev_fed, buyer, seller, arbiter, seller_pubkey, arbiter_pubkey) =
+        setup_clients().await.context("failed to setup client")?;
+    let fed = &dev_fed.fed;
+
+    // Create escrow
+    let cost = 50_000;
+    let max_arbiter_fee_bps = 100; // 1%
+    let create_result = cmd!(
+        buyer,
+        "module",
+        "escrow",
+        "create",
+        &seller_pubkey,
+        &arbiter_pubkey,
+        &cost.to_string(),
+        &max_arbiter_fee_bps.to_string()
+    )
+    .out_json()
+    .await?;
+
+    let escrow_id = create_result["escrow-id"].as_str().unwrap().to_string();
+
+    // Seller accepts the terms of the escrow
+    let accept_result = cmd!(seller, "module", "escrow", "accept", escrow_id.clone())
+        .out_json()
+        .await?;
+    assert_eq!(accept_result["status"], "escrow accepted!");
+
+    // Seller agrees to cancel the escrow
+    let propose_result = cmd!(
+        seller,
+        "module",
+        "escrow",
+        "cancel-propose",
+        escrow_id.clone()
+    )
+    .out_json()
+    .await?;
+    let seller_signature = propose_result["signature"].as_str().unwrap().to_string();
+
+    // Arbiter cannot complete the cancellation
+    let arbiter_cancel_result = cmd!(
+        arbiter,
+        "module",
+        "escrow",
+        "cancel-sign",
+        escrow_id.clone(),
+        seller_signature.clone()
+    )
+    .out_json()
+    .await;
+    assert!(arbiter_cancel_result.is_err());
+
+    // Buyer completes the cancellation
+    let cancel_result = cmd!(
+        buyer,
+        "module",
+        "escrow",
+        "cancel-sign",
+        escrow_id.clone(),
+        seller_signature.clone()
+    )
+    .out_json()
+    .await?;
+    assert_eq!(cancel_result["status"], "cancelled!");
+
+    let escrow_info = cmd!(buyer, "module", "escrow", "info", escrow_id.clone())
+        .out_json()
+        .await?;
+    assert_eq!(escrow_info["state"].as_str().unwrap(), "Cancelled");
+
+    // Seller cannot claim a cancelled escrow
+    let claim_result = cmd!(
+        seller,
+        "module",
+        "escrow",
+        "seller-claim",
+        escrow_id.clone()
+    )
+    .out_json()
+    .await;
+    assert!(claim_result.is_err());
+
+    // Verify final balances
+    assert_eq!(buyer.balance().await?, 100_000_000);
+    assert_eq!(seller.balance().await?, 0);
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn expiry_refund_test() -> anyhow::Result<()> {
+    let (_dev_fed, buyer, seller, _arbiter, seller_pubkey, arbiter_pubkey) =
+        setup_clients().await.context("failed to setup client")?;
+
+    // Create escrow which the buyer can reclaim after a minute
+    let cost = 50_000;
+    let max_arbiter_fee_bps = 100; // 1%
+    let create_result = cmd!(
+        buyer,
+        "module",
+        "escrow",
+        "create",
+        &seller_pubkey,
+        &arbiter_pubkey,
+        &cost.to_string(),
+        &max_arbiter_fee_bps.to_string(),
+        "--expiry-secs",
+        "60"
+    )
+    .out_json()
+    .await?;
+    let escrow_id = create_result["escrow-id"].as_str().unwrap().to_string();
+
+    // Seller accepts the terms of the escrow
+    let accept_result = cmd!(seller, "module", "escrow", "accept", escrow_id.clone())
+        .out_json()
+        .await?;
+    assert_eq!(accept_result["status"], "escrow accepted!");
+
+    // Buyer cannot reclaim the escrow before its deadline
+    let early_refund_result = cmd!(buyer, "module", "escrow", "refund", escrow_id.clone())
+        .out_json()
+        .await;
+    assert!(early_refund_result.is_err());
+
+    // the escrow can be refunded once the consensus time of the guardians has
+    // passed the deadline
+    let mut refund_result = None;
+    for _ in 0..30 {
+        if let Ok(result) = cmd!(buyer, "module", "escrow", "refund", escrow_id.clone())
+            .out_json()
+            .await
+        {
+            refund_result = Some(result);
+            break;
+        }
+        tokio::time::sleep(std::time::Duration::from_secs(10)).await;
+    }
+    let refund_result = refund_result.context("escrow never became refundable")?;
+    assert_eq!(refund_result["status"], "refunded!");
+
+    let escrow_info = cmd!(buyer, "module", "escrow", "info", escrow_id.clone())
+        .out_json()
+        .await?;
+    assert_eq!(escrow_info["state"].as_str().unwrap(), "Expired");
+
+    // Buyer cannot be refunded twice
+    let refund_result = cmd!(buyer, "module", "escrow", "refund", escrow_id.clone())
+        .out_json()
+        .await;
+    assert!(refund_result.is_err());
+
+    // Verify final balances
+    assert_eq!(buyer.balance().await?, 100_000_000);
+    assert_eq!(seller.balance().await?, 0);
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn split_decision_test() -> anyhow::Result<()> {
+    let (dev_fed, buyer, seller, arbiter, seller_pubkey, arbiter_pubkey) =
+        setup_clients().await.context("failed to setup client")?;
+    let fed = &dev_fed.fed;
+
+    // Create escrow
+    let cost = 100_000;
+    let max_arbiter_fee_bps = 200; // 2%
+    let create_result = cmd!(
+        buyer,
+        "module",
+        "escrow",
+        "create",
+        &seller_pubkey,
+        &arbiter_pubkey,
+        &cost.to_string(),
+        &max_arbiter_fee_bps.to_string()
+    )
+    .out_json()
+    .await?;
+
+    let escrow_id = create_result["escrow-id"].as_str().unwrap().to_string();
+
+    // Seller accepts the terms of the escrow
+    let accept_result = cmd!(seller, "module", "escrow", "accept", escrow_id.clone())
+        .out_json()
+        .await?;
+    assert_eq!(accept_result["status"], "escrow accepted!");
+
+    // Buyer initiates dispute
+    let dispute_result = cmd!(buyer, "module", "escrow", "dispute", escrow_id.clone())
+        .out_json()
+        .await?;
+    assert_eq!(dispute_result["status"], "disputed!");
+
+    // a split decision needs the buyer share
+    let missing_share_result = cmd!(
+        arbiter,
+        "module",
+        "escrow",
+        "arbiter-decision",
+        escrow_id.clone(),
+        "split",
+        "50"
+    )
+    .out_json()
+    .await;
+    assert!(missing_share_result.is_err());
+
+    // Arbiter splits the escrow, 30% to the buyer and 70% to the seller
+    let arbiter_fee_bps = 50; // 0.5%
+    let decision_result = cmd!(
+        arbiter,
+        "module",
+        "escrow",
+        "arbiter-decision",
+        escrow_id.clone(),
+        "split",
+        &arbiter_fee_bps.to_string(),
+        "--buyer-share-bps",
+        "3000"
+    )
+    .out_json()
+    .await?;
+    assert_eq!(decision_result["status"], "arbiter decision made!");
+
+    // Seller claims the seller share
+    let claim_result = cmd!(
+        seller,
+        "module",
+        "escrow",
+        "seller-claim",
+        escrow_id.clone()
+    )
+    .out_json()
+    .await?;
+    assert_eq!(claim_result["status"], "resolved!");
+
+    // Seller cannot claim twice
+    let claim_result = cmd!(
+        seller,
+        "module",
+        "escrow",
+        "seller-claim",
+        escrow_id.clone()
+    )
+    .out_json()
+    .await;
+    assert!(claim_result.is_err());
+
+    let escrow_info = cmd!(buyer, "module", "escrow", "info", escrow_id.clone())
+        .out_json()
+        .await?;
+    assert_eq!(
+        escrow_info["state"].as_str().unwrap(),
+        "WaitingforBothToClaim"
+    );
+
+    // Buyer claims the buyer share
+    let claim_result = cmd!(buyer, "module", "escrow", "buyer-claim", escrow_id.clone())
+        .out_json()
+        .await?;
+    assert_eq!(claim_result["status"], "resolved!");
+
+    let escrow_info = cmd!(buyer, "module", "escrow", "info", escrow_id.clone())
+        .out_json()
+        .await?;
+    assert_eq!(
+        escrow_info["state"].as_str().unwrap(),
+        "ResolvedWithDispute"
+    );
+
+    // Verify final balances
+    assert_eq!(buyer.balance().await?, 99_929_850);
+    assert_eq!(seller.balance().await?, 69_650);
+    assert_eq!(arbiter.balance().await?, 500);
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn milestone_path_test() -> anyhow::Result<()> {
+    let (dev_fed, buyer, seller, arbiter, seller_pubkey, arbiter_pubkey) =
+        setup_clients().await.context("failed to setup client")?;
+    let fed = &dev_fed.fed;
+
+    // milestones have to add up to the cost
+    let cost = 60_000;
+    let max_arbiter_fee_bps = 200; // 2%
+    let invalid_create_result = cmd!(
+        buyer,
+        "module",
+        "escrow",
+        "create",
+        &seller_pubkey,
+        &arbiter_pubkey,
+        &cost.to_string(),
+        &max_arbiter_fee_bps.to_string(),
+        "--milestone",
+        "20000",
+        "--milestone",
+        "30000"
+    )
+    .out_json()
+    .await;
+    assert!(invalid_create_result.is_err());
+
+    // Create escrow with two milestones
+    let create_result = cmd!(
+        buyer,
+        "module",
+        "escrow",
+        "create",
+        &seller_pubkey,
+        &arbiter_pubkey,
+        &cost.to_string(),
+        &max_arbiter_fee_bps.to_string(),
+        "--milestone",
+        "20000",
+        "--milestone",
+        "40000"
+    )
+    .out_json()
+    .await?;
+
+    let escrow_id = create_result["escrow-id"].as_str().unwrap().to_string();
+
+    // Seller accepts the terms of the escrow
+    let accept_result = cmd!(seller, "module", "escrow", "accept", escrow_id.clone())
+        .out_json()
+        .await?;
+    assert_eq!(accept_result["status"], "escrow accepted!");
+    let milestones = create_result["milestones"].as_array().unwrap();
+    assert_eq!(milestones.len(), 2);
+    let second_secret_code = milestones[1]["secret-code"].as_str().unwrap().to_string();
+
+    // Seller claims the second milestone on its own
+    let claim_result = cmd!(
+        seller,
+        "module",
+        "escrow",
+        "claim",
+        escrow_id.clone(),
+        second_secret_code.clone()
+    )
+    .out_json()
+    .await?;
+    assert_eq!(claim_result["status"], "milestone released");
+
+    // a milestone can only be released once
+    let claim_result = cmd!(
+        seller,
+        "module",
+        "escrow",
+        "claim",
+        escrow_id.clone(),
+        second_secret_code.clone()
+    )
+    .out_json()
+    .await;
+    assert!(claim_result.is_err());
+
+    let escrow_info = cmd!(buyer, "module", "escrow", "info", escrow_id.clone())
+        .out_json()
+        .await?;
+    assert_eq!(escrow_info["state"].as_str().unwrap(), "Open");
+    assert_eq!(escrow_info["amount"].as_u64().unwrap(), 20_000);
+    assert_eq!(
+        escrow_info["milestones"][0]["state"].as_str().unwrap(),
+        "Pending"
+    );
+    assert_eq!(
+        escrow_info["milestones"][1]["state"].as_str().unwrap(),
+        "Released"
+    );
+
+    // Buyer disputes the remaining milestone
+    let dispute_result = cmd!(buyer, "module", "escrow", "dispute", escrow_id.clone())
+        .out_json()
+        .await?;
+    assert_eq!(dispute_result["status"], "disputed!");
+
+    let escrow_info = cmd!(buyer, "module", "escrow", "info", escrow_id.clone())
+        .out_json()
+        .await?;
+    assert_eq!(
+        escrow_info["milestones"][0]["state"].as_str().unwrap(),
+        "Disputed"
+    );
+    assert_eq!(
+        escrow_info["milestones"][1]["state"].as_str().unwrap(),
+        "Released"
+    );
+
+    // Arbiter decides the remaining milestone in favor of the buyer
+    let arbiter_fee_bps = 50; // 0.5%
+    let decision_result = cmd!(
+        arbiter,
+        "module",
+        "escrow",
+        "arbiter-decision",
+        escrow_id.clone(),
+        "buyer",
+        &arbiter_fee_bps.to_string()
+    )
+    .out_json()
+    .await?;
+    assert_eq!(decision_result["status"], "arbiter decision made!");
+
+    let claim_result = cmd!(buyer, "module", "escrow", "buyer-claim", escrow_id.clone())
+        .out_json()
+        .await?;
+    assert_eq!(claim_result["status"], "resolved!");
+
+    // Verify final balances, the released milestone stays with the seller
+    assert_eq!(buyer.balance().await?, 99_959_900);
+    assert_eq!(seller.balance().await?, 40_000);
+    assert_eq!(arbiter.balance().await?, 100);
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn operation_log_test() -> anyhow::Result<()> {
+    let (_dev_fed, buyer, seller, _arbiter, seller_pubkey, arbiter_pubkey) =
+        setup_clients().await.context("failed to setup client")?;
+
+    // Create escrow
+    let cost = 30_000;
+    let max_arbiter_fee_bps = 100; // 1%
+    let create_result = cmd!(
+        buyer,
+        "module",
+        "escrow",
+        "create",
+        &seller_pubkey,
+        &arbiter_pubkey,
+        &cost.to_string(),
+        &max_arbiter_fee_bps.to_string()
+    )
+    .out_json()
+    .await?;
+    let escrow_id = create_result["escrow-id"].as_str().unwrap().to_string();
+
+    // Seller accepts the terms of the escrow
+    let accept_result = cmd!(seller, "module", "escrow", "accept", escrow_id.clone())
+        .out_json()
+        .await?;
+    assert_eq!(accept_result["status"], "escrow accepted!");
+
+    let buyer_pubkey = cmd!(buyer, "module", "escrow", "public-key")
+        .out_json()
+        .await?["public_key"]
+        .as_str()
+        .unwrap()
+        .to_string();
+
+    // every action is recorded in the operation log with its meta, and its
+    // transaction can be awaited by a later run of the client
+    for (client, operation, role, amount, counterparty) in [
+        (&buyer, "Create", "Buyer", cost * 1000, &seller_pubkey),
+        (&seller, "Accept", "Seller", 0, &buyer_pubkey),
+    ] {
+        let operations = cmd!(client, "list-operations", "--limit", "100")
+            .out_json()
+            .await?;
+        let escrow_operations: Vec<_> = operations["operations"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .filter(|operation| operation["operation_kind"] == "escrow")
+            .collect();
+        assert_eq!(escrow_operations.len(), 1);
+        let operation_meta = &escrow_operations[0]["operation_meta"];
+        assert_eq!(operation_meta["escrow_id"], escrow_id.as_str());
+        assert_eq!(operation_meta["operation"], operation);
+        assert_eq!(operation_meta["role"], role);
+        assert_eq!(operation_meta["amount"], amount);
+        assert_eq!(operation_meta["counterparty"], counterparty.as_str());
+
+        let operation_id = escrow_operations[0]["id"].as_str().unwrap().to_string();
+        let await_result = cmd!(client, "module", "escrow", "await-operation", operation_id)
+            .out_json()
+            .await?;
+        assert_eq!(await_result["escrow_id"], escrow_id.as_str());
+        assert_eq!(await_result["state"], "Accepted");
+    }
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn discover_test() -> anyhow::Result<()> {
+    let (dev_fed, buyer, seller, arbiter, seller_pubkey, arbiter_pubkey) =
+        setup_clients().await.context("failed to setup client")?;
+    let fed = &dev_fed.fed;
+
+    // Create two escrows
+    let cost = 10_000;
+    let max_arbiter_fee_bps = 100; // 1%
+    let mut escrow_ids = Vec::new();
+    for _ in 0..2 {
+        let create_result = cmd!(
+            buyer,
+            "module",
+            "escrow",
+            "create",
+            &seller_pubkey,
+            &arbiter_pubkey,
+            &cost.to_string(),
+            &max_arbiter_fee_bps.to_string()
+        )
+        .out_json()
+        .await?;
+        escrow_ids.push(create_result["escrow-id"].as_str().unwrap().to_string());
+    }
+
+    // Seller discovers the incoming escrows without being told the escrow ids
+    let discover_result = cmd!(seller, "module", "escrow", "discover", "--role", "seller")
+        .out_json()
+        .await?;
+    let escrows = discover_result["escrows"].as_array().unwrap();
+    assert_eq!(escrows.len(), 2);
+    for escrow in escrows {
+        assert!(escrow_ids.contains(&escrow["escrow_id"].as_str().unwrap().to_string()));
+        assert_eq!(escrow["role"].as_str().unwrap(), "Seller");
+        assert_eq!(escrow["state"].as_str().unwrap(), "PendingAcceptance");
+    }
+    assert!(discover_result["next_offset"].is_null());
+
+    // Seller is not the arbiter of any escrow
+    let discover_result = cmd!(seller, "module", "escrow", "discover", "--role", "arbiter")
+        .out_json()
+        .await?;
+    assert!(discover_result["escrows"].as_array().unwrap().is_empty());
+
+    // Arbiter pages through the escrows naming them
+    let discover_result = cmd!(arbiter, "module", "escrow", "discover", "--limit", "1")
+        .out_json()
+        .await?;
+    assert_eq!(discover_result["escrows"].as_array().unwrap().len(), 1);
+    assert_eq!(discover_result["next_offset"].as_u64().unwrap(), 1);
+
+    let discover_result =
+        cmd!(arbiter, "module", "escrow", "discover", "--offset", "1", "--limit", "1")
+            .out_json()
+            .await?;
+    assert_eq!(discover_result["escrows"].as_array().unwrap().len(), 1);
+    assert!(discover_result["next_offset"].is_null());
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn recovery_test() -> anyhow::Result<()> {
+    let (dev_fed, buyer, seller, _arbiter, seller_pubkey, arbiter_pubkey) =
+        setup_clients().await.context("failed to setup client")?;
+    let fed = &dev_fed.fed;
+
+    let cost = 10_000;
+    let max_arbiter_fee_bps = 100; // 1%
+    let create_escrow = || async {
+        let create_result = cmd!(
+            buyer,
+            "module",
+            "escrow",
+            "create",
+            &seller_pubkey,
+            &arbiter_pubkey,
+            &cost.to_string(),
+            &max_arbiter_fee_bps.to_string()
+        )
+        .out_json()
+        .await?;
+        anyhow::Ok((
+            create_result["escrow-id"].as_str().unwrap().to_string(),
+            create_result["secret-code"].as_str().unwrap().to_string(),
+        ))
+    };
+
+    // the first escrow is part of the backup of the buyer, the second one is
+    // created after it
+    let (backed_up_escrow_id, secret_code) = create_escrow().await?;
+    cmd!(buyer, "backup").run().await?;
+    let (escrow_id, _) = create_escrow().await?;
+
+    // the seller never backed up, it only takes part in the escrows
+    for (name, client, role) in [("buyer", &buyer, "Buyer"), ("seller", &seller, "Seller")] {
+        let secret = cmd!(client, "print-secret").out_json().await?["secret"]
+            .as_str()
+            .unwrap()
+            .to_string();
+        let restored = Client::create(&format!("restored-{name}")).await?;
+        cmd!(
+            restored,
+            "restore",
+            "--mnemonic",
+            &secret,
+            "--invite-code",
+            fed.invite_code()?
+        )
+        .run()
+        .await?;
+
+        // the restored client finds both escrows from its public key
+        let mut escrows = Vec::new();
+        for _ in 0..30 {
+            let list_result = cmd!(restored, "module", "escrow", "list")
+                .out_json()
+                .await?;
+            escrows = list_result["escrows"].as_array().unwrap().clone();
+            if escrows.len() == 2 {
+                break;
+            }
+            fedimint_core::task::sleep(std::time::Duration::from_secs(1)).await;
+        }
+        assert_eq!(escrows.len(), 2);
+        for escrow in &escrows {
+            assert_eq!(escrow["role"], role);
+            assert_eq!(escrow["state"], "PendingAcceptance");
+        }
+
+        // only the secret code of the escrow in the backup of the buyer is restored
+        let secret_codes = |id: &str| {
+            escrows
+                .iter()
+                .find(|escrow| escrow["escrow_id"] == id)
+                .unwrap()["secret_codes"]
+                .clone()
+        };
+        if role == "Buyer" {
+            assert_eq!(secret_codes(&backed_up_escrow_id)[0], secret_code.as_str());
+        } else {
+            assert!(secret_codes(&backed_up_escrow_id)
+                .as_array()
+                .unwrap()
+                .is_empty());
+        }
+        assert!(secret_codes(&escrow_id).as_array().unwrap().is_empty());
+    }
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn arbiter_panel_test() -> anyhow::Result<()> {
+    let (dev_fed, buyer, seller, arbiter, seller_pubkey, arbiter_pubkey) =
+        setup_clients().await.context("failed to setup client")?;
+    let fed = &dev_fed.fed;
+
+    // two more arbiters for a 2-of-3 panel
+    let random_suffix_client: String = (0..8)
+        .map(|_| (b'a' + (rand::random::<u8>() % 26)) as char)
+        .collect();
+    let second_arbiter = fed
+        .new_joined_client(&format!("fedimint-cli-arbiter-2-{}", random_suffix_client))
+        .await?;
+    let third_arbiter = fed
+        .new_joined_client(&format!("fedimint-cli-arbiter-3-{}", random_suffix_client))
+        .await?;
+    let second_arbiter_pubkey = cmd!(second_arbiter, "module", "escrow", "public-key")
+        .out_json()
+        .await?["public_key"]
+        .as_str()
+        .unwrap()
+        .to_string();
+    let third_arbiter_pubkey = cmd!(third_arbiter, "module", "escrow", "public-key")
+        .out_json()
+        .await?["public_key"]
+        .as_str()
+        .unwrap()
+        .to_string();
+
+    // Create escrow with the arbiter panel, decided by the majority
+    let cost = 100_000;
+    let max_arbiter_fee_bps = 200; // 2%
+    let create_result = cmd!(
+        buyer,
+        "module",
+        "escrow",
+        "create",
+        &seller_pubkey,
+        &arbiter_pubkey,
+        &cost.to_string(),
+        &max_arbiter_fee_bps.to_string(),
+        "--arbiter",
+        &second_arbiter_pubkey,
+        "--arbiter",
+        &third_arbiter_pubkey
+    )
+    .out_json()
+    .await?;
+    let escrow_id = create_result["escrow-id"].as_str().unwrap().to_string();
+
+    // Seller accepts the terms of the escrow
+    let accept_result = cmd!(seller, "module", "escrow", "accept", escrow_id.clone())
+        .out_json()
+        .await?;
+    assert_eq!(accept_result["status"], "escrow accepted!");
+
+    let escrow_info = cmd!(buyer, "module", "escrow", "info", escrow_id.clone())
+        .out_json()
+        .await?;
+    assert_eq!(escrow_info["arbiter_threshold"].as_u64().unwrap(), 2);
+
+    // Buyer initiates dispute
+    let dispute_result = cmd!(buyer, "module", "escrow", "dispute", escrow_id.clone())
+        .out_json()
+        .await?;
+    assert_eq!(dispute_result["status"], "disputed!");
+
+    // the arbiters disagree at first, thus nothing is decided
+    let arbiter_fee_bps = 50; // 0.5%
+    let vote_result = cmd!(
+        arbiter,
+        "module",
+        "escrow",
+        "arbiter-decision",
+        escrow_id.clone(),
+        "seller",
+        &arbiter_fee_bps.to_string()
+    )
+    .out_json()
+    .await?;
+    assert_eq!(vote_result["status"], "arbiter vote recorded!");
+
+    let vote_result = cmd!(
+        second_arbiter,
+        "module",
+        "escrow",
+        "arbiter-decision",
+        escrow_id.clone(),
+        "buyer",
+        &arbiter_fee_bps.to_string()
+    )
+    .out_json()
+    .await?;
+    assert_eq!(vote_result["status"], "arbiter vote recorded!");
+
+    // an arbiter votes only once
+    let vote_again_result = cmd!(
+        arbiter,
+        "module",
+        "escrow",
+        "arbiter-decision",
+        escrow_id.clone(),
+        "seller",
+        &arbiter_fee_bps.to_string()
+    )
+    .out_json()
+    .await;
+    assert!(vote_again_result.is_err());
+
+    // the third arbiter makes the majority
+    let vote_result = cmd!(
+        third_arbiter,
+        "module",
+        "escrow",
+        "arbiter-decision",
+        escrow_id.clone(),
+        "seller",
+        &arbiter_fee_bps.to_string()
+    )
+    .out_json()
+    .await?;
+    assert_eq!(vote_result["status"], "arbiter decision made!");
+
+    // the first arbiter of the majority claims its share of the fee, the outvoted
+    // one gets nothing
+    let claim_fee_result = cmd!(
+        arbiter,
+        "module",
+        "escrow",
+        "arbiter-claim-fee",
+        escrow_id.clone()
+    )
+    .out_json()
+    .await?;
+    assert_eq!(claim_fee_result["status"], "arbiter fee claimed!");
+    let claim_fee_result = cmd!(
+        second_arbiter,
+        "module",
+        "escrow",
+        "arbiter-claim-fee",
+        escrow_id.clone()
+    )
+    .out_json()
+    .await;
+    assert!(claim_fee_result.is_err());
+
+    // Seller claims escrow
+    let claim_result = cmd!(
+        seller,
+        "module",
+        "escrow",
+        "seller-claim",
+        escrow_id.clone()
+    )
+    .out_json()
+    .await?;
+    assert_eq!(claim_result["status"], "resolved!");
+
+    // Verify final balances, the fee is split between the majority
+    assert_eq!(buyer.balance().await?, 99_900_000);
+    assert_eq!(seller.balance().await?, 99_500);
+    assert_eq!(arbiter.balance().await?, 250);
+    assert_eq!(second_arbiter.balance().await?, 0);
+    assert_eq!(third_arbiter.balance().await?, 250);
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn appeal_test() -> anyhow::Result<()> {
+    let (dev_fed, buyer, seller, arbiter, seller_pubkey, arbiter_pubkey) =
+        setup_clients().await.context("failed to setup client")?;
+    let fed = &dev_fed.fed;
+
+    // the appeal arbiter reviewing the decision of the arbiter
+    let random_suffix_client: String = (0..8)
+        .map(|_| (b'a' + (rand::random::<u8>() % 26)) as char)
+        .collect();
+    let appeal_arbiter = fed
+        .new_joined_client(&format!(
+            "fedimint-cli-appeal-arbiter-{}",
+            random_suffix_client
+        ))
+        .await?;
+    let appeal_arbiter_pubkey = cmd!(appeal_arbiter, "module", "escrow", "public-key")
+        .out_json()
+        .await?["public_key"]
+        .as_str()
+        .unwrap()
+        .to_string();
+
+    // Create escrow with an appeal arbiter
+    let cost = 100_000;
+    let max_arbiter_fee_bps = 200; // 2%
+    let create_result = cmd!(
+        buyer,
+        "module",
+        "escrow",
+        "create",
+        &seller_pubkey,
+        &arbiter_pubkey,
+        &cost.to_string(),
+        &max_arbiter_fee_bps.to_string(),
+        "--appeal-arbiter",
+        &appeal_arbiter_pubkey
+    )
+    .out_json()
+    .await?;
+    let escrow_id = create_result["escrow-id"].as_str().unwrap().to_string();
+
+    // Seller accepts the terms of the escrow
+    let accept_result = cmd!(seller, "module", "escrow", "accept", escrow_id.clone())
+        .out_json()
+        .await?;
+    assert_eq!(accept_result["status"], "escrow accepted!");
+
+    // Buyer initiates dispute
+    let dispute_result = cmd!(buyer, "module", "escrow", "dispute", escrow_id.clone())
+        .out_json()
+        .await?;
+    assert_eq!(dispute_result["status"], "disputed!");
+
+    // Arbiter decides in favor of seller
+    let arbiter_fee_bps = 50; // 0.5%
+    let decision_result = cmd!(
+        arbiter,
+        "module",
+        "escrow",
+        "arbiter-decision",
+        escrow_id.clone(),
+        "seller",
+        &arbiter_fee_bps.to_string()
+    )
+    .out_json()
+    .await?;
+    assert_eq!(decision_result["status"], "arbiter decision made!");
+
+    // the decision cannot be claimed while it can still be appealed
+    let claim_result = cmd!(
+        seller,
+        "module",
+        "escrow",
+        "seller-claim",
+        escrow_id.clone()
+    )
+    .out_json()
+    .await;
+    assert!(claim_result.is_err());
+
+    // only the party who lost the decision can appeal it
+    let appeal_result = cmd!(seller, "module", "escrow", "appeal", escrow_id.clone())
+        .out_json()
+        .await;
+    assert!(appeal_result.is_err());
+
+    let appeal_result = cmd!(buyer, "module", "escrow", "appeal", escrow_id.clone())
+        .out_json()
+        .await?;
+    assert_eq!(appeal_result["status"], "appealed!");
+
+    // the appeal waits in the inbox of the appeal arbiter
+    let inbox_result = cmd!(appeal_arbiter, "module", "escrow", "arbiter-inbox")
+        .out_json()
+        .await?;
+    let disputes = inbox_result["disputes"].as_array().unwrap();
+    assert_eq!(disputes.len(), 1);
+    assert_eq!(disputes[0]["escrow_id"].as_str().unwrap(), escrow_id);
+    assert_eq!(disputes[0]["disputer"].as_str().unwrap(), "Buyer");
+    assert!(disputes[0]["appeal"].as_bool().unwrap());
+
+    // the arbiter of the first decision cannot decide the appeal
+    let appeal_decision_result = cmd!(
+        arbiter,
+        "module",
+        "escrow",
+        "appeal-decision",
+        escrow_id.clone(),
+        "buyer",
+        &arbiter_fee_bps.to_string()
+    )
+    .out_json()
+    .await;
+    assert!(appeal_decision_result.is_err());
+
+    // Appeal arbiter overturns the decision in favor of buyer
+    let appeal_decision_result = cmd!(
+        appeal_arbiter,
+        "module",
+        "escrow",
+        "appeal-decision",
+        escrow_id.clone(),
+        "buyer",
+        &arbiter_fee_bps.to_string()
+    )
+    .out_json()
+    .await?;
+    assert_eq!(appeal_decision_result["status"], "appeal decision made!");
+
+    let escrow_info = cmd!(buyer, "module", "escrow", "info", escrow_id.clone())
+        .out_json()
+        .await?;
+    assert_eq!(escrow_info["arbiter_decision"], "SellerWins");
+    assert_eq!(escrow_info["appeal_decision"], "BuyerWins");
+
+    // the seller cannot claim against the appeal decision
+    let claim_result = cmd!(
+        seller,
+        "module",
+        "escrow",
+        "seller-claim",
+        escrow_id.clone()
+    )
+    .out_json()
+    .await;
+    assert!(claim_result.is_err());
+
+    // Buyer claims escrow
+    let claim_result = cmd!(buyer, "module", "escrow", "buyer-claim", escrow_id.clone())
+        .out_json()
+        .await?;
+    assert_eq!(claim_result["status"], "resolved!");
+
+    // Verify final balances, both arbiters are paid their fee
+    assert_eq!(buyer.balance().await?, 99_999_003);
+    assert_eq!(seller.balance().await?, 0);
+    assert_eq!(arbiter.balance().await?, 500);
+    assert_eq!(appeal_arbiter.balance().await?, 497);
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn arbitration_timeout_test() -> anyhow::Result<()> {
+    let (_dev_fed, buyer, seller, arbiter, seller_pubkey, arbiter_pubkey) =
+        setup_clients().await.context("failed to setup client")?;
+
+    // Create escrow which is split if the arbiter does not decide right away
+    let cost = 100_000;
+    let max_arbiter_fee_bps = 200; // 2%
+    let create_result = cmd!(
+        buyer,
+        "module",
+        "escrow",
+        "create",
+        &seller_pubkey,
+        &arbiter_pubkey,
+        &cost.to_string(),
+        &max_arbiter_fee_bps.to_string(),
+        "--arbitration-timeout-secs",
+        "1",
+        "--fallback",
+        "split"
+    )
+    .out_json()
+    .await?;
+    let escrow_id = create_result["escrow-id"].as_str().unwrap().to_string();
+
+    // Seller accepts the terms of the escrow
+    let accept_result = cmd!(seller, "module", "escrow", "accept", escrow_id.clone())
+        .out_json()
+        .await?;
+    assert_eq!(accept_result["status"], "escrow accepted!");
+
+    // Buyer initiates dispute
+    let dispute_result = cmd!(buyer, "module", "escrow", "dispute", escrow_id.clone())
+        .out_json()
+        .await?;
+    assert_eq!(dispute_result["status"], "disputed!");
+
+    // the fallback outcome can be claimed once the consensus time of the guardians
+    // has passed the arbitration deadline
+    let mut claim_result = None;
+    for _ in 0..30 {
+        if let Ok(result) = cmd!(buyer, "module", "escrow", "buyer-claim", escrow_id.clone())
+            .out_json()
+            .await
+        {
+            claim_result = Some(result);
+            break;
+        }
+        tokio::time::sleep(std::time::Duration::from_secs(10)).await;
+    }
+    let claim_result = claim_result.context("fallback outcome never became claimable")?;
+    assert_eq!(claim_result["status"], "resolved!");
+
+    // the arbiter cannot decide anymore
+    let arbiter_fee_bps = 50; // 0.5%
+    let decision_result = cmd!(
+        arbiter,
+        "module",
+        "escrow",
+        "arbiter-decision",
+        escrow_id.clone(),
+        "seller",
+        &arbiter_fee_bps.to_string()
+    )
+    .out_json()
+    .await;
+    assert!(decision_result.is_err());
+
+    let escrow_info = cmd!(buyer, "module", "escrow", "info", escrow_id.clone())
+        .out_json()
+        .await?;
+    assert!(escrow_info["fallback_applied"].as_bool().unwrap());
+
+    // Seller claims the other half
+    let claim_result = cmd!(
+        seller,
+        "module",
+        "escrow",
+        "seller-claim",
+        escrow_id.clone()
+    )
+    .out_json()
+    .await?;
+    assert_eq!(claim_result["status"], "resolved!");
+
+    // Verify final balances, no arbiter fee is deducted
+    assert_eq!(buyer.balance().await?, 99_950_000);
+    assert_eq!(seller.balance().await?, 50_000);
+    assert_eq!(arbiter.balance().await?, 0);
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn release_test() -> anyhow::Result<()> {
+    let (_dev_fed, buyer, seller, _arbiter, seller_pubkey, arbiter_pubkey) =
+        setup_clients().await.context("failed to setup client")?;
+
+    // Create escrow with two milestones
+    let cost = 50_000;
+    let max_arbiter_fee_bps = 100; // 1%
+    let create_result = cmd!(
+        buyer,
+        "module",
+        "escrow",
+        "create",
+        &seller_pubkey,
+        &arbiter_pubkey,
+        &cost.to_string(),
+        &max_arbiter_fee_bps.to_string(),
+        "--milestone",
+        "20000",
+        "--milestone",
+        "30000"
+    )
+    .out_json()
+    .await?;
+    let escrow_id = create_result["escrow-id"].as_str().unwrap().to_string();
+    let first_secret_code = create_result["milestones"][0]["secret-code"]
+        .as_str()
+        .unwrap()
+        .to_string();
+
+    // Seller accepts the terms of the escrow
+    let accept_result = cmd!(seller, "module", "escrow", "accept", escrow_id.clone())
+        .out_json()
+        .await?;
+    assert_eq!(accept_result["status"], "escrow accepted!");
+
+    // Seller claims the first milestone with its secret code
+    let claim_result = cmd!(
+        seller,
+        "module",
+        "escrow",
+        "claim",
+        escrow_id.clone(),
+        first_secret_code
+    )
+    .out_json()
+    .await?;
+    assert_eq!(claim_result["status"], "milestone released");
+
+    // nothing is released to claim yet, and only the buyer can release
+    let early_claim_result = cmd!(
+        seller,
+        "module",
+        "escrow",
+        "claim-released",
+        escrow_id.clone()
+    )
+    .out_json()
+    .await;
+    assert!(early_claim_result.is_err());
+
+    let seller_release_result = cmd!(seller, "module", "escrow", "release", escrow_id.clone())
+        .out_json()
+        .await;
+    assert!(seller_release_result.is_err());
+
+    // Buyer releases the rest of the escrow without a secret code
+    let release_result = cmd!(buyer, "module", "escrow", "release", escrow_id.clone())
+        .out_json()
+        .await?;
+    assert_eq!(release_result["status"], "released!");
+
+    let escrow_info = cmd!(buyer, "module", "escrow", "info", escrow_id.clone())
+        .out_json()
+        .await?;
+    assert_eq!(
+        escrow_info["state"].as_str().unwrap(),
+        "ResolvedWithoutDispute"
+    );
+
+    // Seller claims the released milestone, only once
+    let claim_result = cmd!(
+        seller,
+        "module",
+        "escrow",
+        "claim-released",
+        escrow_id.clone()
+    )
+    .out_json()
+    .await?;
+    assert_eq!(claim_result["status"], "resolved");
+    assert_eq!(claim_result["amount"].as_u64().unwrap(), 30_000);
+
+    let claim_again_result = cmd!(
+        seller,
+        "module",
+        "escrow",
+        "claim-released",
+        escrow_id.clone()
+    )
+    .out_json()
+    .await;
+    assert!(claim_again_result.is_err());
+
+    // Verify final balances
+    assert_eq!(buyer.balance().await?, 99_950_000);
+    assert_eq!(seller.balance().await?, 50_000);
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn invoice_escrow_test() -> anyhow::Result<()> {
+    let (dev_fed, buyer, seller, arbiter, seller_pubkey, arbiter_pubkey) =
+        setup_clients().await.context("failed to setup client")?;
+    let fed = &dev_fed.fed;
+
+    // the arbiter pays the invoice of the buyer, as anyone outside the escrow would
+    fed.pegin_client(10_000, &arbiter).await?;
+
+    // Buyer creates an invoice paying for the escrow
+    let cost = 100_000;
+    let max_arbiter_fee_bps = 100; // 1%
+    let create_result = cmd!(
+        buyer,
+        "module",
+        "escrow",
+        "create-from-invoice",
+        &seller_pubkey,
+        &arbiter_pubkey,
+        &cost.to_string(),
+        &max_arbiter_fee_bps.to_string()
+    )
+    .out_json()
+    .await?;
+    assert_eq!(
+        create_result["state"],
+        "waiting for the invoice to be paid!"
+    );
+    let escrow_id = create_result["escrow-id"].as_str().unwrap().to_string();
+    let operation_id = create_result["operation-id"].as_str().unwrap().to_string();
+    let invoice = create_result["invoice"].as_str().unwrap().to_string();
+
+    // the escrow does not exist before the invoice is paid
+    let info_result = cmd!(buyer, "module", "escrow", "info", escrow_id.clone())
+        .out_json()
+        .await;
+    assert!(info_result.is_err());
+
+    cmd!(arbiter, "ln-pay", invoice).out_json().await?;
+
+    // the received ecash is locked in the escrow
+    let await_result = cmd!(
+        buyer,
+        "module",
+        "escrow",
+        "await-invoice-escrow",
+        operation_id
+    )
+    .out_json()
+    .await?;
+    assert_eq!(await_result["escrow-id"].as_str().unwrap(), escrow_id);
+    assert_eq!(await_result["state"], "waiting for seller acceptance!");
+    let secret_code = await_result["secret-code"].as_str().unwrap().to_string();
+
+    let escrow_info = cmd!(buyer, "module", "escrow", "info", escrow_id.clone())
+        .out_json()
+        .await?;
+    assert_eq!(escrow_info["state"].as_str().unwrap(), "PendingAcceptance");
+    assert_eq!(escrow_info["amount"].as_u64().unwrap(), cost);
+
+    // the escrow goes on as if the buyer funded it with its own ecash
+    let accept_result = cmd!(seller, "module", "escrow", "accept", escrow_id.clone())
+        .out_json()
+        .await?;
+    assert_eq!(accept_result["status"], "escrow accepted!");
+    let claim_result = cmd!(
+        seller,
+        "module",
+        "escrow",
+        "claim",
+        escrow_id.clone(),
+        secret_code
+    )
+    .out_json()
+    .await?;
+    assert_eq!(claim_result["status"], "resolved");
+
+    // Verify final balances, the ecash of the buyer is untouched
+    assert_eq!(buyer.balance().await?, 100_000_000);
+    assert_eq!(seller.balance().await?, cost);
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn claim_to_invoice_test() -> anyhow::Result<()> {
+    let (_dev_fed, buyer, seller, arbiter, seller_pubkey, arbiter_pubkey) =
+        setup_clients().await.context("failed to setup client")?;
+
+    // Create escrow by buyer
+    let cost = 50_000;
+    let max_arbiter_fee_bps = 100; // 1%
+    let create_result = cmd!(
+        buyer,
+        "module",
+        "escrow",
+        "create",
+        &seller_pubkey,
+        &arbiter_pubkey,
+        &cost.to_string(),
+        &max_arbiter_fee_bps.to_string()
+    )
+    .out_json()
+    .await?;
+    let escrow_id = create_result["escrow-id"].as_str().unwrap().to_string();
+    let secret_code = create_result["secret-code"].as_str().unwrap().to_string();
+
+    // Seller accepts the terms of the escrow
+    let accept_result = cmd!(seller, "module", "escrow", "accept", escrow_id.clone())
+        .out_json()
+        .await?;
+    assert_eq!(accept_result["status"], "escrow accepted!");
+
+    // an invoice above the claimed ecash is refused before the escrow is claimed,
+    // the arbiter stands in for the Lightning wallet of the seller
+    let invoice_result = cmd!(
+        arbiter,
+        "ln-invoice",
+        "--amount",
+        "60000",
+        "--description",
+        "escrow payout"
+    )
+    .out_json()
+    .await?;
+    let claim_result = cmd!(
+        seller,
+        "module",
+        "escrow",
+        "claim",
+        escrow_id.clone(),
+        secret_code.clone(),
+        "--to-invoice",
+        invoice_result["invoice"].as_str().unwrap()
+    )
+    .out_json()
+    .await;
+    assert!(claim_result.is_err());
+
+    let escrow_info = cmd!(buyer, "module", "escrow", "info", escrow_id.clone())
+        .out_json()
+        .await?;
+    assert_eq!(escrow_info["state"].as_str().unwrap(), "Open");
+
+    // Seller claims escrow straight to the invoice, leaving room for the routing
+    // fee of the gateway
+    let invoice_amount = 45_000;
+    let invoice_result = cmd!(
+        arbiter,
+        "ln-invoice",
+        "--amount",
+        &invoice_amount.to_string(),
+        "--description",
+        "escrow payout"
+    )
+    .out_json()
+    .await?;
+    let claim_result = cmd!(
+        seller,
+        "module",
+        "escrow",
+        "claim",
+        escrow_id.clone(),
+        secret_code,
+        "--to-invoice",
+        invoice_result["invoice"].as_str().unwrap()
+    )
+    .out_json()
+    .await?;
+    assert_eq!(claim_result["status"], "resolved");
+    assert!(claim_result["payment_preimage"].is_string());
+
+    cmd!(
+        arbiter,
+        "await-invoice",
+        invoice_result["operation_id"].as_str().unwrap()
+    )
+    .out_json()
+    .await?;
+
+    // Verify final balances, the seller only keeps what the payment leaves of the
+    // claim
+    assert_eq!(buyer.balance().await?, 99_950_000);
+    assert!(seller.balance().await? <= cost - invoice_amount);
+    assert_eq!(arbiter.balance().await?, invoice_amount);
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn claim_to_address_test() -> anyhow::Result<()> {
+    let (dev_fed, buyer, seller, _arbiter, seller_pubkey, arbiter_pubkey) =
+        setup_clients().await.context("failed to setup client")?;
+
+    // Create escrow by buyer, large enough to be pegged out on-chain
+    let cost = 50_000_000;
+    let max_arbiter_fee_bps = 100; // 1%
+    let create_result = cmd!(
+        buyer,
+        "module",
+        "escrow",
+        "create",
+        &seller_pubkey,
+        &arbiter_pubkey,
+        &cost.to_string(),
+        &max_arbiter_fee_bps.to_string()
+    )
+    .out_json()
+    .await?;
+    let escrow_id = create_result["escrow-id"].as_str().unwrap().to_string();
+    let secret_code = create_result["secret-code"].as_str().unwrap().to_string();
+
+    // Seller accepts the terms of the escrow
+    let accept_result = cmd!(seller, "module", "escrow", "accept", escrow_id.clone())
+        .out_json()
+        .await?;
+    assert_eq!(accept_result["status"], "escrow accepted!");
+
+    // an address on another network than the one of the federation is refused
+    // before the escrow is claimed
+    let claim_result = cmd!(
+        seller,
+        "module",
+        "escrow",
+        "claim",
+        escrow_id.clone(),
+        secret_code.clone(),
+        "--to-address",
+        "bc1qar0srrr7xfkvy5l643lydnw9re59gtzzwf5mdq"
+    )
+    .out_json()
+    .await;
+    assert!(claim_result.is_err());
+
+    let address = dev_fed.bitcoind.get_new_address().await?.to_string();
+
+    // the fees of the peg-out are shown without claiming the escrow
+    let estimate_result = cmd!(
+        seller,
+        "module",
+        "escrow",
+        "claim",
+        escrow_id.clone(),
+        secret_code.clone(),
+        "--to-address",
+        address.clone(),
+        "--estimate-fees"
+    )
+    .out_json()
+    .await?;
+    let fees_sats = estimate_result["peg_out_fees_sats"].as_u64().unwrap();
+    assert_eq!(
+        estimate_result["peg_out_amount_sats"].as_u64().unwrap(),
+        cost / 1000 - fees_sats
+    );
+
+    let escrow_info = cmd!(buyer, "module", "escrow", "info", escrow_id.clone())
+        .out_json()
+        .await?;
+    assert_eq!(escrow_info["state"].as_str().unwrap(), "Open");
+
+    // Seller claims escrow straight to the address
+    let claim_result = cmd!(
+        seller,
+        "module",
+        "escrow",
+        "claim",
+        escrow_id.clone(),
+        secret_code,
+        "--to-address",
+        address
+    )
+    .out_json()
+    .await?;
+    assert_eq!(claim_result["status"], "resolved");
+    assert!(claim_result["peg_out_txid"].is_string());
+
+    // Verify final balances, the seller never holds the ecash
+    assert_eq!(buyer.balance().await?, 100_000_000 - cost);
+    assert_eq!(seller.balance().await?, 0);
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn hash_lock_test() -> anyhow::Result<()> {
+    let (_dev_fed, buyer, seller, _arbiter, seller_pubkey, arbiter_pubkey) =
+        setup_clients().await.context("failed to setup client")?;
+
+    // the preimage is only known by the seller, like the one of a Lightning invoice
+    let preimage: [u8; 32] = rand::random();
+    let preimage_hex: String = preimage.iter().map(|byte| format!("{byte:02x}")).collect();
+    let payment_hash = sha256::Hash::hash(&preimage).to_string();
+
+    // every milestone has to be locked to a payment hash
+    let cost = 50_000;
+    let max_arbiter_fee_bps = 100; // 1%
+    let create_result = cmd!(
+        buyer,
+        "module",
+        "escrow",
+        "create",
+        &seller_pubkey,
+        &arbiter_pubkey,
+        &cost.to_string(),
+        &max_arbiter_fee_bps.to_string(),
+        "--milestone",
+        "20000",
+        "--milestone",
+        "30000",
+        "--payment-hash",
+        &payment_hash
+    )
+    .out_json()
+    .await;
+    assert!(create_result.is_err());
+
+    // Create escrow by buyer locked to the payment hash
+    let create_result = cmd!(
+        buyer,
+        "module",
+        "escrow",
+        "create",
+        &seller_pubkey,
+        &arbiter_pubkey,
+        &cost.to_string(),
+        &max_arbiter_fee_bps.to_string(),
+        "--payment-hash",
+        &payment_hash
+    )
+    .out_json()
+    .await?;
+    let escrow_id = create_result["escrow-id"].as_str().unwrap().to_string();
+    assert!(create_result["secret-code"].is_null());
+    assert_eq!(
+        create_result["payment-hashes"][0].as_str().unwrap(),
+        payment_hash
+    );
+
+    let escrow_info = cmd!(buyer, "module", "escrow", "info", escrow_id.clone())
+        .out_json()
+        .await?;
+    assert_eq!(escrow_info["hash_lock"].as_str().unwrap(), "Preimage");
+
+    // Seller accepts the terms of the escrow
+    let accept_result = cmd!(seller, "module", "escrow", "accept", escrow_id.clone())
+        .out_json()
+        .await?;
+    assert_eq!(accept_result["status"], "escrow accepted!");
+
+    // Neither another preimage nor the payment hash itself release the escrow
+    let other_preimage: [u8; 32] = rand::random();
+    let other_preimage_hex: String = other_preimage
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect();
+    for secret_code in [other_preimage_hex, payment_hash.clone()] {
+        let claim_result = cmd!(
+            seller,
+            "module",
+            "escrow",
+            "claim",
+            escrow_id.clone(),
+            secret_code
+        )
+        .out_json()
+        .await;
+        assert!(claim_result.is_err());
+    }
+
+    // Seller claims escrow with the preimage
+    let claim_result = cmd!(
+        seller,
+        "module",
+        "escrow",
+        "claim",
+        escrow_id.clone(),
+        preimage_hex
+    )
+    .out_json()
+    .await?;
+    assert_eq!(claim_result["status"], "resolved");
+
     // Verify final balances
-    let arbiter_fee = (cost as f64 * (arbiter_fee_bps as f64 / 10000.0)) as u64;
-    assert_eq!(buyer.balance().await?, 99900000);
-    assert_eq!(seller.balance().await?, 50000);
-    assert_eq!(arbiter.balance().await?, 50000);
+    assert_eq!(buyer.balance().await?, 100_000_000 - cost);
+    assert_eq!(seller.balance().await?, cost);
 
     Ok(())
 }